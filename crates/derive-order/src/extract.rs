@@ -0,0 +1,266 @@
+use std::ops::Range;
+use std::panic;
+
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+use syn::{parse_file, Attribute, Ident, Item, MacroDelimiter, Meta, Path, Token};
+
+use crate::Error;
+
+/// Byte order mark syn skips at the start of a file
+const BOM: char = '\u{feff}';
+
+/// Kinds of item a derive can be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ItemKind {
+    Struct,
+    Enum,
+    Union,
+}
+
+impl ItemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemKind::Struct => "struct",
+            ItemKind::Enum => "enum",
+            ItemKind::Union => "union",
+        }
+    }
+}
+
+/// The item a derive attribute is attached to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeriveTarget {
+    pub kind: ItemKind,
+    pub name: String,
+    /// 1-based line of the item's name
+    pub line: usize,
+}
+
+/// A single `#[derive(...)]` attribute found in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeriveAttribute {
+    /// 1-based line on which the attribute starts
+    pub line: usize,
+    /// Derive paths in source order, e.g. `["Debug", "serde::Serialize"]`
+    pub derives: Vec<String>,
+    /// The full text of the line the attribute starts on
+    pub full_line: String,
+    /// Byte range of the text between the derive parentheses
+    pub list_range: Range<usize>,
+    /// Byte range of each derive path, parallel to `derives`
+    pub entry_ranges: Vec<Range<usize>>,
+    /// The annotated item, when it could be determined
+    pub target: Option<DeriveTarget>,
+}
+
+/// Extracts derive attributes by parsing `source` with syn.
+///
+/// Items inside inline `mod` blocks are included, which the extractor built
+/// into the analysis tool before this crate skipped, so files with inline
+/// modules yield more statements than runs made with it.
+///
+/// Panics raised by the parser are caught and reported as [`Error::Panicked`].
+pub fn extract_syn(source: &str) -> Result<Vec<DeriveAttribute>, Error> {
+    let content = source.to_string();
+    let parsed = panic::catch_unwind(move || parse_file(&content));
+
+    let result = match parsed {
+        Ok(Ok(file)) => {
+            let mut attributes = Vec::new();
+            for item in &file.items {
+                collect_from_item(item, source, &mut attributes);
+            }
+
+            // syn drops a byte order mark and a shebang line before parsing, so
+            // its byte offsets start after them
+            let offset = if source.starts_with(BOM) { BOM.len_utf8() } else { 0 }
+                + file.shebang.as_ref().map_or(0, String::len);
+            for attribute in &mut attributes {
+                attribute.list_range = shift(&attribute.list_range, offset);
+                for range in &mut attribute.entry_ranges {
+                    *range = shift(range, offset);
+                }
+            }
+            Ok(attributes)
+        }
+        Ok(Err(e)) => Err(Error::Parse(e.to_string())),
+        Err(_) => Err(Error::Panicked),
+    };
+
+    // Span locations are kept in a thread-local source map that otherwise grows
+    // with every file parsed on this thread
+    proc_macro2::extra::invalidate_current_thread_spans();
+    result
+}
+
+fn shift(range: &Range<usize>, offset: usize) -> Range<usize> {
+    range.start + offset..range.end + offset
+}
+
+/// Extracts single-line `#[derive(...)]` attributes with a line scanner.
+///
+/// Used for files syn cannot handle; multi-line derive lists are not recognised.
+pub fn extract_text(source: &str) -> Vec<DeriveAttribute> {
+    let mut attributes = Vec::new();
+    let mut line_start = 0;
+    let lines: Vec<&str> = source.split('\n').collect();
+
+    for (line_idx, &line) in lines.iter().enumerate() {
+        let offset = line_start;
+        line_start += line.len() + 1;
+
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let trimmed = line.trim();
+        if !(trimmed.starts_with("#[derive(") && trimmed.ends_with(")]")) {
+            continue;
+        }
+
+        // Remove "#[derive(" and ")]"
+        let indent = line.len() - line.trim_start().len();
+        let list_start = offset + indent + "#[derive(".len();
+        let list_end = offset + indent + trimmed.len() - ")]".len();
+        let list = &source[list_start..list_end];
+
+        let mut derives = Vec::new();
+        let mut entry_ranges = Vec::new();
+        let mut entry_start = list_start;
+        for part in list.split(',') {
+            let leading = part.len() - part.trim_start().len();
+            let name = part.trim();
+            if !name.is_empty() {
+                let start = entry_start + leading;
+                derives.push(name.to_string());
+                entry_ranges.push(start..start + name.len());
+            }
+            entry_start += part.len() + 1;
+        }
+
+        if !derives.is_empty() {
+            attributes.push(DeriveAttribute {
+                line: line_idx + 1,
+                derives,
+                full_line: line.to_string(),
+                list_range: list_start..list_end,
+                entry_ranges,
+                target: scan_target(&lines, line_idx + 1),
+            });
+        }
+    }
+
+    attributes
+}
+
+/// Finds the item declared after the attribute on line `from - 1`, skipping
+/// further attributes, comments and blank lines.
+fn scan_target(lines: &[&str], from: usize) -> Option<DeriveTarget> {
+    for (line_idx, line) in lines.iter().enumerate().skip(from) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with("#[") || trimmed.starts_with("//") {
+            continue;
+        }
+
+        let mut words = trimmed.split_whitespace().skip_while(|word| word.starts_with("pub"));
+        let kind = match words.next()? {
+            "struct" => ItemKind::Struct,
+            "enum" => ItemKind::Enum,
+            "union" => ItemKind::Union,
+            _ => return None,
+        };
+        let name: String = words
+            .next()?
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        return (!name.is_empty()).then(|| DeriveTarget {
+            kind,
+            name,
+            line: line_idx + 1,
+        });
+    }
+    None
+}
+
+fn collect_from_item(item: &Item, source: &str, attributes: &mut Vec<DeriveAttribute>) {
+    match item {
+        Item::Struct(item_struct) => {
+            let target = target(ItemKind::Struct, &item_struct.ident);
+            collect_from_attrs(&item_struct.attrs, target, source, attributes);
+        }
+        Item::Enum(item_enum) => {
+            let target = target(ItemKind::Enum, &item_enum.ident);
+            collect_from_attrs(&item_enum.attrs, target, source, attributes);
+        }
+        Item::Union(item_union) => {
+            let target = target(ItemKind::Union, &item_union.ident);
+            collect_from_attrs(&item_union.attrs, target, source, attributes);
+        }
+        Item::Mod(item_mod) => {
+            if let Some((_, items)) = &item_mod.content {
+                for nested in items {
+                    collect_from_item(nested, source, attributes);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn target(kind: ItemKind, ident: &Ident) -> DeriveTarget {
+    DeriveTarget {
+        kind,
+        name: ident.to_string(),
+        line: ident.span().start().line,
+    }
+}
+
+fn collect_from_attrs(
+    attrs: &[Attribute],
+    target: DeriveTarget,
+    source: &str,
+    attributes: &mut Vec<DeriveAttribute>,
+) {
+    for attr in attrs {
+        if !attr.path().is_ident("derive") {
+            continue;
+        }
+        let Meta::List(list) = &attr.meta else {
+            continue;
+        };
+        let MacroDelimiter::Paren(paren) = &list.delimiter else {
+            continue;
+        };
+        let Ok(paths) = attr.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated) else {
+            continue;
+        };
+        if paths.is_empty() {
+            continue;
+        }
+
+        let derives = paths.iter().map(path_to_string).collect();
+        let entry_ranges = paths.iter().filter_map(|path| token_range(path.to_token_stream())).collect();
+        let line = attr.pound_token.span.start().line;
+
+        attributes.push(DeriveAttribute {
+            line,
+            derives,
+            full_line: source.lines().nth(line.saturating_sub(1)).unwrap_or("").to_string(),
+            list_range: paren.span.open().byte_range().end..paren.span.close().byte_range().start,
+            entry_ranges,
+            target: Some(target.clone()),
+        });
+    }
+}
+
+/// Renders a derive path without the token spacing `to_string` inserts.
+fn path_to_string(path: &Path) -> String {
+    path.to_token_stream().to_string().replace(' ', "")
+}
+
+fn token_range(tokens: TokenStream) -> Option<Range<usize>> {
+    let mut tokens = tokens.into_iter();
+    let first = tokens.next()?;
+    let last = tokens.last().unwrap_or_else(|| first.clone());
+    Some(first.span().byte_range().start..last.span().byte_range().end)
+}
@@ -0,0 +1,259 @@
+//! Extraction and canonical reordering of `#[derive(...)]` lists.
+//!
+//! This crate holds the parsing and reordering logic used by the analysis
+//! tool, with no dependency on its runtime, so that it can be used from build
+//! scripts or proposed as a rustfmt rule.
+//!
+//! ```
+//! use derive_order::{apply_edits, check, OrderingPolicy};
+//!
+//! let source = "#[derive(Debug, Clone)]\nstruct S;\n";
+//! let edits = check(source, &OrderingPolicy::alphabetical()).unwrap();
+//! assert_eq!(apply_edits(source, &edits), "#[derive(Clone, Debug)]\nstruct S;\n");
+//! ```
+
+mod extract;
+mod policy;
+mod sensitive;
+mod supertraits;
+
+use std::fmt;
+use std::ops::Range;
+
+pub use extract::{extract_syn, extract_text, DeriveAttribute, DeriveTarget, ItemKind};
+pub use policy::{last_segment, OrderingPolicy, Unlisted};
+pub use sensitive::{OrderSensitive, SensitiveDerive};
+pub use supertraits::{has_supertrait_pair, is_supertrait, supertrait_violations, SupertraitViolation, STD_SUPERTRAITS};
+
+/// A replacement of a byte range in the checked source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// Byte range of the source to replace
+    pub range: Range<usize>,
+    /// Text to put in its place
+    pub replacement: String,
+    /// 1-based line of the derive attribute being rewritten
+    pub line: usize,
+}
+
+/// Why a source file could not be checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The source is not valid Rust, or a user list is malformed
+    Parse(String),
+    /// syn panicked on the source, as it can on deeply nested input
+    Panicked,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) => write!(f, "Parse error: {}", msg),
+            Error::Panicked => write!(f, "Parser panicked"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the edits needed to bring every derive list in `source` into the
+/// order given by `policy`.
+///
+/// Only the derive paths move; separators, line breaks and trailing commas
+/// stay where they were. Lists containing comments are left untouched, and
+/// order-sensitive derives are not moved unless the policy is forced.
+pub fn check(source: &str, policy: &OrderingPolicy) -> Result<Vec<Edit>, Error> {
+    let attributes = extract_syn(source)?;
+    Ok(attributes
+        .iter()
+        .filter_map(|attribute| reorder_edit(source, attribute, policy))
+        .collect())
+}
+
+/// Applies non-overlapping `edits` to `source`.
+pub fn apply_edits(source: &str, edits: &[Edit]) -> String {
+    let mut edits: Vec<&Edit> = edits.iter().collect();
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+
+    let mut output = source.to_string();
+    for edit in edits {
+        output.replace_range(edit.range.clone(), &edit.replacement);
+    }
+    output
+}
+
+fn reorder_edit(source: &str, attribute: &DeriveAttribute, policy: &OrderingPolicy) -> Option<Edit> {
+    let ordering = policy.ordering(&attribute.derives);
+    if ordering.iter().enumerate().all(|(slot, &index)| slot == index) {
+        return None;
+    }
+
+    let entries = &attribute.entry_ranges;
+    if entries.len() != attribute.derives.len() {
+        return None;
+    }
+
+    let range = entries.first()?.start..entries.last()?.end;
+    let separators: Vec<&str> = entries
+        .windows(2)
+        .map(|pair| &source[pair[0].end..pair[1].start])
+        .collect();
+    if separators.iter().any(|sep| sep.contains("//") || sep.contains("/*")) {
+        return None;
+    }
+
+    let mut replacement = String::with_capacity(range.len());
+    for (slot, &index) in ordering.iter().enumerate() {
+        replacement.push_str(&source[entries[index].clone()]);
+        if let Some(separator) = separators.get(slot) {
+            replacement.push_str(separator);
+        }
+    }
+
+    Some(Edit {
+        range,
+        replacement,
+        line: attribute.line,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix(source: &str, policy: &OrderingPolicy) -> String {
+        apply_edits(source, &check(source, policy).unwrap())
+    }
+
+    #[test]
+    fn test_extract_paths_and_lines() {
+        let source = "use serde::Serialize;\n\n#[derive(Debug, serde::Serialize)]\nstruct A;\n\nmod inner {\n    #[derive(Clone)]\n    enum B { X }\n}\n";
+        let attributes = extract_syn(source).unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes[0].line, 3);
+        assert_eq!(attributes[0].derives, vec!["Debug", "serde::Serialize"]);
+        assert_eq!(&source[attributes[0].entry_ranges[1].clone()], "serde::Serialize");
+        assert_eq!(attributes[1].line, 7);
+        assert_eq!(attributes[1].full_line, "    #[derive(Clone)]");
+    }
+
+    #[test]
+    fn test_text_extraction_matches_syn_ranges() {
+        let source = "#[derive(Debug,  Clone)]\r\n#[repr(C)]\npub(crate) struct A;\n";
+        let text = extract_text(source);
+        let syn = extract_syn(source).unwrap();
+        assert_eq!(text[0].derives, syn[0].derives);
+        assert_eq!(text[0].entry_ranges, syn[0].entry_ranges);
+        assert_eq!(text[0].list_range, syn[0].list_range);
+        assert_eq!(text[0].target, syn[0].target);
+    }
+
+    #[test]
+    fn test_alphabetical_reorder_preserves_layout() {
+        let source = "#[derive(\n    PartialEq,\n    Debug,\n    Clone,\n)]\nstruct A;\n";
+        assert_eq!(
+            fix(source, &OrderingPolicy::alphabetical()),
+            "#[derive(\n    Clone,\n    Debug,\n    PartialEq,\n)]\nstruct A;\n"
+        );
+    }
+
+    #[test]
+    fn test_precedence_policy() {
+        let policy = OrderingPolicy::with_precedence(["Debug", "Clone"]);
+        let source = "#[derive(serde::Serialize, Clone, Eq, Debug)]\nstruct A;\n";
+        assert_eq!(fix(source, &policy), "#[derive(Debug, Clone, Eq, serde::Serialize)]\nstruct A;\n");
+        assert!(check("#[derive(Debug, Clone)]\nstruct B;\n", &policy).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_order_sensitive_derives_are_barriers() {
+        let mut policy = OrderingPolicy::alphabetical();
+        policy.order_sensitive.extend_from_str("# local\nmy_macros::* = registers the type globally\n").unwrap();
+
+        let source = "#[derive(Debug, Clone, my_macros::Register, Eq, Copy)]\nstruct A;\n";
+        assert_eq!(fix(source, &policy), "#[derive(Clone, Debug, my_macros::Register, Copy, Eq)]\nstruct A;\n");
+
+        policy.force = true;
+        assert_eq!(fix(source, &policy), "#[derive(Clone, Copy, Debug, Eq, my_macros::Register)]\nstruct A;\n");
+    }
+
+    #[test]
+    fn test_order_sensitive_lookup() {
+        let mut known = OrderSensitive::builtin();
+        known.extend_from_str("Register = registers the type globally\nmy_macros::* = local\n").unwrap();
+
+        // Path entries match exactly, or a bare name by final segment
+        assert!(known.is_sensitive("ambassador::Delegate"));
+        assert!(known.is_sensitive("::derivative::Derivative"));
+        assert!(known.is_sensitive("Educe"));
+        assert!(!known.is_sensitive("other::Delegate"));
+        // Bare entries match any path ending in them, like policy precedence
+        assert!(known.is_sensitive("Register"));
+        assert!(known.is_sensitive("app::Register"));
+        // Crate wildcards need the crate's path
+        assert!(known.is_sensitive("my_macros::Anything"));
+        assert!(!known.is_sensitive("Anything"));
+        assert!(!known.is_sensitive("my_macros_extra::Anything"));
+    }
+
+    #[test]
+    fn test_supertrait_violations() {
+        let derives = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert!(is_supertrait("Ord", "PartialEq"));
+        assert!(!is_supertrait("PartialEq", "Eq"));
+
+        let list = derives(&["Clone", "Copy", "PartialEq", "Eq", "PartialOrd", "Ord"]);
+        assert!(has_supertrait_pair(&list));
+        assert!(supertrait_violations(&list).is_empty());
+
+        let list = derives(&["Copy", "Ord", "Debug", "std::cmp::PartialEq", "Clone"]);
+        let pairs: Vec<_> = supertrait_violations(&list)
+            .into_iter()
+            .map(|violation| (violation.subtrait, violation.supertrait))
+            .collect();
+        assert_eq!(pairs, vec![("Copy".to_string(), "Clone".to_string()), ("Ord".to_string(), "PartialEq".to_string())]);
+
+        // Same-named derives from other crates are not the standard traits
+        let list = derives(&["Eq", "my_macros::PartialEq"]);
+        assert!(!has_supertrait_pair(&list));
+        assert!(supertrait_violations(&list).is_empty());
+    }
+
+    #[test]
+    fn test_supertraits_first_policy() {
+        let mut policy = OrderingPolicy::alphabetical();
+        policy.supertraits_first = true;
+        let source = "#[derive(Ord, Eq, Debug, Copy, PartialOrd, PartialEq, Hash, Clone)]\nstruct A;\n";
+        assert_eq!(fix(source, &policy), "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]\nstruct A;\n");
+
+        // The constraint wins over an explicit precedence
+        let mut policy = OrderingPolicy::with_precedence(["Eq", "PartialEq", "Debug"]);
+        policy.supertraits_first = true;
+        assert_eq!(fix("#[derive(Debug, Eq, PartialEq)]\nstruct B;\n", &policy), "#[derive(PartialEq, Eq, Debug)]\nstruct B;\n");
+    }
+
+    #[test]
+    fn test_byte_order_mark_and_shebang() {
+        let policy = OrderingPolicy::alphabetical();
+        let source = "\u{feff}#[derive(Debug, Clone)]\nstruct A;\n";
+        assert_eq!(fix(source, &policy), "\u{feff}#[derive(Clone, Debug)]\nstruct A;\n");
+
+        let source = "#!/usr/bin/env -S cargo +nightly -Zscript\n#[derive(Debug, Clone)]\nstruct A;\n";
+        assert_eq!(fix(source, &policy), "#!/usr/bin/env -S cargo +nightly -Zscript\n#[derive(Clone, Debug)]\nstruct A;\n");
+        assert_eq!(extract_syn(source).unwrap()[0].line, 2);
+
+        let source = "\u{feff}#!/usr/bin/env run-cargo-script\n#[derive(Debug, Clone)]\nstruct A;\n";
+        assert_eq!(fix(source, &policy), "\u{feff}#!/usr/bin/env run-cargo-script\n#[derive(Clone, Debug)]\nstruct A;\n");
+    }
+
+    #[test]
+    fn test_lists_with_comments_are_skipped() {
+        let source = "#[derive(Debug, /* keep */ Clone)]\nstruct A;\n";
+        assert!(check(source, &OrderingPolicy::alphabetical()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_source_is_an_error() {
+        assert!(matches!(check("struct {", &OrderingPolicy::alphabetical()), Err(Error::Parse(_))));
+    }
+}
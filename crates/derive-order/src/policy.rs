@@ -0,0 +1,139 @@
+use crate::sensitive::OrderSensitive;
+use crate::supertraits::{is_supertrait, std_trait};
+
+/// How derives that are not named in the precedence list are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unlisted {
+    /// Keep their original relative order
+    Preserve,
+    /// Sort them by their final path segment
+    #[default]
+    Alphabetical,
+}
+
+/// The canonical ordering a derive list is checked against.
+///
+/// Derives named in `precedence` come first, in that order, followed by all
+/// other derives arranged according to `unlisted`. A precedence entry without
+/// `::` matches on the final path segment, so `Serialize` also matches
+/// `serde::Serialize`.
+///
+/// Derives listed in `order_sensitive` stay where they are and act as
+/// barriers: only the runs of derives between them are reordered. Setting
+/// `force` sorts the whole list regardless.
+///
+/// With `supertraits_first`, a standard derive is moved after its supertraits
+/// wherever the rest of the policy would put it, so `PartialEq` comes before
+/// `Eq` and `Clone` before `Copy`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OrderingPolicy {
+    pub precedence: Vec<String>,
+    pub unlisted: Unlisted,
+    pub order_sensitive: OrderSensitive,
+    pub force: bool,
+    pub supertraits_first: bool,
+}
+
+impl OrderingPolicy {
+    /// Every derive sorted by its final path segment.
+    pub fn alphabetical() -> Self {
+        Self::default()
+    }
+
+    /// The given derives first, everything else alphabetically after them.
+    pub fn with_precedence<I, S>(precedence: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            precedence: precedence.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the indices of `derives` in canonical order.
+    pub fn ordering(&self, derives: &[String]) -> Vec<usize> {
+        if self.force {
+            return self.sort_run(derives, (0..derives.len()).collect());
+        }
+
+        let mut indices = Vec::with_capacity(derives.len());
+        let mut run = Vec::new();
+        for (index, derive) in derives.iter().enumerate() {
+            if self.order_sensitive.is_sensitive(derive) {
+                indices.extend(self.sort_run(derives, std::mem::take(&mut run)));
+                indices.push(index);
+            } else {
+                run.push(index);
+            }
+        }
+        indices.extend(self.sort_run(derives, run));
+        indices
+    }
+
+    fn sort_run(&self, derives: &[String], mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort_by(|&a, &b| {
+            let rank_a = self.rank(&derives[a]);
+            let rank_b = self.rank(&derives[b]);
+            rank_a.cmp(&rank_b).then_with(|| match self.unlisted {
+                Unlisted::Alphabetical if rank_a == usize::MAX => {
+                    last_segment(&derives[a]).cmp(last_segment(&derives[b]))
+                }
+                _ => a.cmp(&b),
+            })
+        });
+        if self.supertraits_first {
+            indices = supertraits_first(derives, indices);
+        }
+        indices
+    }
+
+    /// Returns `derives` in canonical order.
+    pub fn sorted(&self, derives: &[String]) -> Vec<String> {
+        self.ordering(derives).into_iter().map(|i| derives[i].clone()).collect()
+    }
+
+    fn rank(&self, derive: &str) -> usize {
+        self.precedence
+            .iter()
+            .position(|entry| {
+                if entry.contains("::") {
+                    entry == derive
+                } else {
+                    entry == last_segment(derive)
+                }
+            })
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// Reorders `indices` so each standard derive follows its supertraits among
+/// them, pulling a supertrait forward to just before its first subtrait and
+/// otherwise keeping the given order.
+fn supertraits_first(derives: &[String], indices: Vec<usize>) -> Vec<usize> {
+    fn place(derives: &[String], indices: &[usize], index: usize, placed: &mut Vec<usize>) {
+        if placed.contains(&index) {
+            return;
+        }
+        if let Some(subtrait) = std_trait(&derives[index]) {
+            for &other in indices {
+                if std_trait(&derives[other]).is_some_and(|supertrait| is_supertrait(subtrait, supertrait)) {
+                    place(derives, indices, other, placed);
+                }
+            }
+        }
+        placed.push(index);
+    }
+
+    let mut placed = Vec::with_capacity(indices.len());
+    for &index in &indices {
+        place(derives, &indices, index, &mut placed);
+    }
+    placed
+}
+
+/// The final segment of a derive path, e.g. `Serialize` for `serde::Serialize`.
+pub fn last_segment(derive: &str) -> &str {
+    derive.rsplit("::").next().unwrap_or(derive)
+}
@@ -0,0 +1,118 @@
+use crate::policy::last_segment;
+use crate::Error;
+
+/// A derive, or every derive from a crate, whose position relative to its
+/// siblings can change what the expanded code means.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SensitiveDerive {
+    /// `crate::Trait`, or `crate::*` for every derive the crate exports
+    pub path: String,
+    pub reason: String,
+}
+
+/// Knowledge base of order-sensitive derives.
+///
+/// Derives are expanded in list order. Most derive macros only look at the
+/// item they are attached to, but some keep state across invocations or read
+/// attributes left behind for sibling derives, and for those moving the derive
+/// within the list is not a purely cosmetic change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderSensitive {
+    entries: Vec<SensitiveDerive>,
+}
+
+impl Default for OrderSensitive {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl OrderSensitive {
+    /// The entries shipped with this crate.
+    ///
+    /// Attribute macros that must precede the `#[derive]` they feed, such as
+    /// `serde_with::serde_as` rewriting `#[serde_as]` field attributes into
+    /// the `#[serde]` ones serde's derives read, are about attribute order
+    /// rather than derive order and are not listed.
+    pub fn builtin() -> Self {
+        let mut known = Self::empty();
+        known.insert(
+            "ambassador::Delegate",
+            "expands using trait definitions recorded by earlier #[delegatable_trait] invocations",
+        );
+        known.insert(
+            "derivative::Derivative",
+            "implements the std traits named in #[derivative(...)] helpers, so its position stands in for theirs",
+        );
+        known.insert(
+            "educe::Educe",
+            "implements the std traits named in #[educe(...)] helpers, so its position stands in for theirs",
+        );
+        known
+    }
+
+    pub fn empty() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, reason: impl Into<String>) {
+        self.entries.push(SensitiveDerive {
+            path: path.into(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Adds entries from `path = reason` lines. Blank lines and lines starting
+    /// with `#` are ignored.
+    pub fn extend_from_str(&mut self, text: &str) -> Result<(), Error> {
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (path, reason) = line
+                .split_once('=')
+                .ok_or_else(|| Error::Parse(format!("line {}: expected `path = reason`", idx + 1)))?;
+            self.insert(path.trim(), reason.trim());
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[SensitiveDerive] {
+        &self.entries
+    }
+
+    /// Finds the entry covering `derive`.
+    ///
+    /// Entries match the way [`OrderingPolicy`](crate::OrderingPolicy)
+    /// precedence does: a bare entry matches any derive with that final
+    /// segment, and a path entry matches that path exactly. A bare derive
+    /// also matches path entries by final segment, since it is usually
+    /// brought into scope with a `use` of the full path. `crate::*` entries
+    /// only match derives written with that crate's path.
+    pub fn lookup(&self, derive: &str) -> Option<&SensitiveDerive> {
+        let derive = derive.trim_start_matches("::");
+        self.entries.iter().find(|entry| {
+            let path = entry.path.trim_start_matches("::");
+            match path.strip_suffix("::*") {
+                Some(krate) => derive.strip_prefix(krate).is_some_and(|rest| rest.starts_with("::")),
+                None if !path.contains("::") => path == last_segment(derive),
+                None if derive.contains("::") => path == derive,
+                None => last_segment(path) == derive,
+            }
+        })
+    }
+
+    pub fn is_sensitive(&self, derive: &str) -> bool {
+        self.lookup(derive).is_some()
+    }
+
+    /// The derives in `derives` that are order-sensitive, in list order.
+    pub fn matching<'a>(&self, derives: &'a [String]) -> Vec<&'a str> {
+        derives
+            .iter()
+            .filter(|derive| self.is_sensitive(derive))
+            .map(String::as_str)
+            .collect()
+    }
+}
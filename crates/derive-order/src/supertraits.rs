@@ -0,0 +1,77 @@
+use crate::policy::last_segment;
+
+/// Supertrait relations between the standard derives, as `(subtrait, supertrait)`.
+///
+/// A derive list that names a trait after its supertrait reads in the order
+/// the compiler requires the impls to exist, which many style guides follow.
+pub const STD_SUPERTRAITS: &[(&str, &str)] = &[
+    ("Eq", "PartialEq"),
+    ("PartialOrd", "PartialEq"),
+    ("Ord", "Eq"),
+    ("Ord", "PartialOrd"),
+    ("Copy", "Clone"),
+];
+
+/// Crate paths under which the standard derives can be named
+const STD_PATHS: &[&str] = &["std", "core"];
+
+/// A subtrait listed before one of its supertraits in the same derive list.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SupertraitViolation {
+    pub subtrait: String,
+    pub supertrait: String,
+}
+
+/// The standard trait a derive names, by final segment, when it is bare or
+/// spelled as a `std`/`core` path.
+pub(crate) fn std_trait(derive: &str) -> Option<&'static str> {
+    let path = derive.trim_start_matches("::");
+    if let Some((first, _)) = path.split_once("::") {
+        if !STD_PATHS.contains(&first) {
+            return None;
+        }
+    }
+    let name = last_segment(path);
+    STD_SUPERTRAITS
+        .iter()
+        .flat_map(|(subtrait, supertrait)| [*subtrait, *supertrait])
+        .find(|known| *known == name)
+}
+
+/// Whether `supertrait` is a direct or indirect supertrait of `subtrait`, so
+/// `Ord` counts as a subtrait of `PartialEq`.
+pub fn is_supertrait(subtrait: &str, supertrait: &str) -> bool {
+    STD_SUPERTRAITS
+        .iter()
+        .filter(|(sub, _)| *sub == subtrait)
+        .any(|(_, sup)| *sup == supertrait || is_supertrait(sup, supertrait))
+}
+
+/// Every pair of standard derives in `derives` with the subtrait listed
+/// before its supertrait, in list order of the subtrait.
+pub fn supertrait_violations(derives: &[String]) -> Vec<SupertraitViolation> {
+    let traits: Vec<Option<&str>> = derives.iter().map(|derive| std_trait(derive)).collect();
+    let mut violations = Vec::new();
+    for (i, subtrait) in traits.iter().enumerate() {
+        let Some(subtrait) = subtrait else { continue };
+        for supertrait in traits[i + 1..].iter().flatten() {
+            if is_supertrait(subtrait, supertrait) {
+                violations.push(SupertraitViolation {
+                    subtrait: subtrait.to_string(),
+                    supertrait: supertrait.to_string(),
+                });
+            }
+        }
+    }
+    violations
+}
+
+/// Whether `derives` names at least one subtrait together with a supertrait,
+/// and so says anything about the convention.
+pub fn has_supertrait_pair(derives: &[String]) -> bool {
+    let traits: Vec<&str> = derives.iter().filter_map(|derive| std_trait(derive)).collect();
+    traits
+        .iter()
+        .any(|subtrait| traits.iter().any(|supertrait| is_supertrait(subtrait, supertrait)))
+}
+
@@ -0,0 +1,750 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::taxonomy::{DeriveFamily, Taxonomy};
+use crate::DeriveStatement;
+
+/// Number of derive pairs kept in the statistics output, most frequent first
+const MAX_PAIR_PREFERENCES: usize = 500;
+
+/// Number of derives listed for each family, most used first
+const MAX_FAMILY_DERIVES: usize = 50;
+
+/// How often two derives appear in each order when they share a statement.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairPreference {
+    /// Lexicographically smaller derive of the pair
+    pub first: String,
+    pub second: String,
+    /// Statements with `first` before `second`, weighted when deduplicating by weight
+    pub forward: f64,
+    /// Statements with `second` before `first`
+    pub reverse: f64,
+    /// |forward - reverse| / (forward + reverse)
+    pub preference_strength: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositoryConsistency {
+    pub repository: String,
+    /// Commit the statements were extracted from, when known
+    #[serde(default)]
+    pub commit_sha: Option<String>,
+    pub multi_derive_statements: usize,
+    pub unique_patterns: usize,
+    pub consistency_score: f64,
+}
+
+/// Consistency of one Cargo package of a repository, so the crates of a
+/// workspace are scored on their own conventions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageConsistency {
+    pub repository: String,
+    pub package: String,
+    pub edition: Option<String>,
+    pub rust_version: Option<String>,
+    pub proc_macro: bool,
+    pub multi_derive_statements: usize,
+    pub unique_patterns: usize,
+    pub consistency_score: f64,
+}
+
+/// How often derives from one crate appear, `std` for built-in derives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OriginUsage {
+    pub crate_name: String,
+    /// Derives resolved to the crate, weighted like the pair preferences
+    pub derives: f64,
+    /// Distinct version requirements the crate is depended on with
+    pub versions: Vec<String>,
+}
+
+/// A derive name that resolved to different crates, such as `Display` from
+/// strum in one package and from derive_more in another.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedDeriveName {
+    pub derive: String,
+    /// Statements using the name for each crate
+    pub crates: BTreeMap<String, usize>,
+}
+
+/// How often a repository's derive lists name standard supertraits before
+/// their subtraits, e.g. `PartialEq` before `Eq`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupertraitConsistency {
+    pub repository: String,
+    /// Statements naming a standard derive together with one of its supertraits
+    pub statements: usize,
+    /// Those listing a subtrait before one of its supertraits
+    pub violating_statements: usize,
+    /// Violations by pair, as `Eq before PartialEq`
+    pub violations: BTreeMap<String, usize>,
+}
+
+/// How consistently a repository's authors order derives, individually and
+/// against each other. Only available for statements enriched with blame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorConsistency {
+    pub repository: String,
+    pub authors: usize,
+    /// Mean consistency of each author's own statements, weighted by statement count
+    pub within_author: f64,
+    /// Consistency across the authors' most common patterns
+    pub between_author: f64,
+}
+
+/// How often one derive appears.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeriveUsage {
+    pub derive: String,
+    /// Statements using the derive, weighted like the pair preferences
+    pub statements: f64,
+}
+
+/// Ordering statistics of one family, over each statement's derives of that
+/// family in the order they are listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FamilyStatistics {
+    pub family: DeriveFamily,
+    /// Statements with at least one derive of the family
+    pub statements: usize,
+    /// Statements with at least two, which carry ordering information
+    pub multi_derive_statements: usize,
+    pub mean_consistency: f64,
+    pub pair_preferences: Vec<PairPreference>,
+    pub derives: Vec<DeriveUsage>,
+}
+
+/// Ordering statistics per derive family, and how the families are ordered
+/// against each other, e.g. std derives before ecosystem ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FamilyReport {
+    pub families: Vec<FamilyStatistics>,
+    pub family_preferences: Vec<PairPreference>,
+}
+
+impl FamilyReport {
+    /// Computes the report with statements weighted as in
+    /// [`DeriveStatistics::compute_weighted`].
+    pub fn compute_weighted(derives: &[DeriveStatement], weights: &[f64], taxonomy: &Taxonomy) -> Self {
+        let families: Vec<Vec<DeriveFamily>> = derives.iter().map(|derive| taxonomy.families(derive)).collect();
+
+        let reports = DeriveFamily::ALL
+            .into_iter()
+            .map(|family| {
+                // Each statement narrowed to the family's derives, so the usual statistics apply
+                let (narrowed, narrowed_weights): (Vec<DeriveStatement>, Vec<f64>) = derives
+                    .iter()
+                    .zip(&families)
+                    .zip(weights)
+                    .filter_map(|((derive, families), weight)| {
+                        let names: Vec<String> = derive.derives.iter().zip(families)
+                            .filter(|(_, f)| **f == family)
+                            .map(|(name, _)| name.clone())
+                            .collect();
+                        (!names.is_empty()).then(|| (DeriveStatement { derives: names, ..derive.clone() }, *weight))
+                    })
+                    .unzip();
+                let statistics = DeriveStatistics::compute_weighted(&narrowed, &narrowed_weights);
+
+                let mut usage: BTreeMap<&str, f64> = BTreeMap::new();
+                for (derive, weight) in narrowed.iter().zip(&narrowed_weights) {
+                    for name in &derive.derives {
+                        *usage.entry(name).or_insert(0.0) += weight;
+                    }
+                }
+                let mut usage: Vec<DeriveUsage> = usage
+                    .into_iter()
+                    .map(|(derive, statements)| DeriveUsage { derive: derive.to_string(), statements })
+                    .collect();
+                usage.sort_by(|a, b| b.statements.total_cmp(&a.statements).then_with(|| a.derive.cmp(&b.derive)));
+                usage.truncate(MAX_FAMILY_DERIVES);
+
+                FamilyStatistics {
+                    family,
+                    statements: narrowed.len(),
+                    multi_derive_statements: statistics.multi_derive_statements,
+                    mean_consistency: statistics.mean_consistency,
+                    pair_preferences: statistics.pair_preferences,
+                    derives: usage,
+                }
+            })
+            .collect();
+
+        // Families as a sequence per statement, repeats merged like origin crates
+        let sequences: Vec<(Vec<String>, f64)> = families
+            .iter()
+            .zip(weights)
+            .map(|(families, weight)| {
+                let mut sequence: Vec<String> = families.iter().map(|family| family.as_str().to_string()).collect();
+                sequence.dedup();
+                (sequence, *weight)
+            })
+            .filter(|(sequence, _)| sequence.len() >= 2)
+            .collect();
+
+        Self {
+            families: reports,
+            family_preferences: pair_preferences(sequences.iter().map(|(sequence, weight)| (sequence.as_slice(), *weight))),
+        }
+    }
+}
+
+/// Ordering statistics over a set of derive statements, following METHODOLOGY.md.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeriveStatistics {
+    pub total_statements: usize,
+    pub multi_derive_statements: usize,
+    pub mean_consistency: f64,
+    pub repositories: Vec<RepositoryConsistency>,
+    /// Statements attributed to a Cargo package, grouped by repository and package
+    #[serde(default)]
+    pub packages: Vec<PackageConsistency>,
+    /// Mean package consistency, weighted like `mean_consistency`
+    #[serde(default)]
+    pub mean_package_consistency: Option<f64>,
+    pub pair_preferences: Vec<PairPreference>,
+    /// Crates the derives come from, most used first
+    #[serde(default)]
+    pub origins: Vec<OriginUsage>,
+    /// Preferences between the crates derives come from, e.g. `std` before `serde`
+    #[serde(default)]
+    pub origin_preferences: Vec<PairPreference>,
+    #[serde(default)]
+    pub shared_derive_names: Vec<SharedDeriveName>,
+    /// Repositories with statements the supertrait convention applies to, most violations first
+    #[serde(default)]
+    pub supertrait_consistency: Vec<SupertraitConsistency>,
+    /// Weighted share of those statements listing every supertrait first
+    #[serde(default)]
+    pub supertrait_respect_rate: Option<f64>,
+    /// Repositories with at least two attributed authors
+    #[serde(default)]
+    pub author_consistency: Vec<AuthorConsistency>,
+    #[serde(default)]
+    pub mean_within_author_consistency: Option<f64>,
+    #[serde(default)]
+    pub mean_between_author_consistency: Option<f64>,
+}
+
+impl DeriveStatistics {
+    pub fn compute(derives: &[DeriveStatement]) -> Self {
+        Self::compute_weighted(derives, &vec![1.0; derives.len()])
+    }
+
+    /// Computes the statistics with each statement counted by its weight in
+    /// `weights`, given in the same order. Weights scale the pair preferences
+    /// and each repository's share of the mean consistency; a repository's own
+    /// consistency depends only on its statements.
+    pub fn compute_weighted(derives: &[DeriveStatement], weights: &[f64]) -> Self {
+        // Single-derive statements carry no ordering information
+        let weighted: Vec<(&DeriveStatement, f64)> = derives
+            .iter()
+            .zip(weights.iter().copied())
+            .filter(|(d, _)| d.derives.len() >= 2)
+            .collect();
+        let multi: Vec<&DeriveStatement> = weighted.iter().map(|(d, _)| *d).collect();
+
+        let mut by_repository: HashMap<&str, Vec<&[String]>> = HashMap::new();
+        let mut repository_weights: HashMap<&str, f64> = HashMap::new();
+        let mut commits: HashMap<&str, &str> = HashMap::new();
+        for (derive, weight) in &weighted {
+            by_repository.entry(&derive.repository).or_default().push(&derive.derives);
+            *repository_weights.entry(&derive.repository).or_insert(0.0) += weight;
+            if let Some(commit_sha) = &derive.commit_sha {
+                commits.entry(&derive.repository).or_insert(commit_sha);
+            }
+        }
+
+        let mut repositories: Vec<RepositoryConsistency> = by_repository
+            .into_iter()
+            .map(|(repository, patterns)| {
+                let mut unique = patterns.clone();
+                unique.sort();
+                unique.dedup();
+                RepositoryConsistency {
+                    repository: repository.to_string(),
+                    commit_sha: commits.get(repository).map(|sha| sha.to_string()),
+                    multi_derive_statements: patterns.len(),
+                    unique_patterns: unique.len(),
+                    consistency_score: consistency_score(&patterns),
+                }
+            })
+            .collect();
+        repositories.sort_by(|a, b| b.multi_derive_statements.cmp(&a.multi_derive_statements)
+            .then_with(|| a.repository.cmp(&b.repository)));
+
+        // Each repository counts by the mean weight of its statements, so a fork of copied files counts little
+        let repository_weight = |r: &RepositoryConsistency| {
+            repository_weights[r.repository.as_str()] / r.multi_derive_statements as f64
+        };
+        let total_weight: f64 = repositories.iter().map(repository_weight).sum();
+        let mean_consistency = if total_weight > 0.0 {
+            repositories.iter().map(|r| r.consistency_score * repository_weight(r)).sum::<f64>() / total_weight
+        } else {
+            0.0
+        };
+
+        let (packages, mean_package_consistency) = package_consistency(&weighted);
+        let (origins, origin_sequences) = origin_usage(&weighted);
+        let (supertrait_consistency, supertrait_respect_rate) = supertrait_consistency(&weighted);
+        let author_consistency = author_consistency(&multi);
+        let mean = |score: fn(&AuthorConsistency) -> f64| {
+            (!author_consistency.is_empty())
+                .then(|| author_consistency.iter().map(score).sum::<f64>() / author_consistency.len() as f64)
+        };
+
+        Self {
+            total_statements: derives.len(),
+            multi_derive_statements: multi.len(),
+            mean_consistency,
+            repositories,
+            packages,
+            mean_package_consistency,
+            pair_preferences: pair_preferences(weighted.iter().map(|(d, weight)| (d.derives.as_slice(), *weight))),
+            origins,
+            origin_preferences: pair_preferences(origin_sequences.iter().map(|(sequence, weight)| (sequence.as_slice(), *weight))),
+            shared_derive_names: shared_derive_names(derives),
+            supertrait_consistency,
+            supertrait_respect_rate,
+            mean_within_author_consistency: mean(|a| a.within_author),
+            mean_between_author_consistency: mean(|a| a.between_author),
+            author_consistency,
+        }
+    }
+}
+
+/// Entropy-based consistency of a repository's derive orderings.
+///
+/// Counts adjacent derive pairs across all patterns and returns one minus the
+/// normalised Shannon entropy of that distribution: 1.0 when every pair
+/// always appears the same way, approaching 0.0 when ordering is arbitrary.
+pub fn consistency_score(patterns: &[&[String]]) -> f64 {
+    if patterns.len() <= 1 {
+        return 1.0;
+    }
+
+    let mut pair_counts: HashMap<(&str, &str), usize> = HashMap::new();
+    let mut total_pairs = 0usize;
+    for pattern in patterns {
+        for window in pattern.windows(2) {
+            *pair_counts.entry((&window[0], &window[1])).or_insert(0) += 1;
+            total_pairs += 1;
+        }
+    }
+
+    if total_pairs == 0 || pair_counts.len() <= 1 {
+        return 1.0;
+    }
+
+    let entropy: f64 = pair_counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total_pairs as f64;
+            -p * p.log2()
+        })
+        .sum();
+    let max_entropy = (pair_counts.len() as f64).log2();
+
+    1.0 - entropy / max_entropy
+}
+
+/// Counts the derives of each origin crate, and turns each statement into the
+/// sequence of crates its resolved derives come from, with repeats merged.
+fn origin_usage(weighted: &[(&DeriveStatement, f64)]) -> (Vec<OriginUsage>, Vec<(Vec<String>, f64)>) {
+    let mut usage: BTreeMap<&str, (f64, Vec<&str>)> = BTreeMap::new();
+    let mut sequences = Vec::new();
+    for (derive, weight) in weighted {
+        let mut sequence: Vec<String> = Vec::new();
+        for name in &derive.derives {
+            let Some(origin) = derive.origins.iter().find(|origin| &origin.derive == name) else { continue };
+            let (count, versions) = usage.entry(&origin.crate_name).or_default();
+            *count += weight;
+            versions.extend(origin.version.as_deref());
+            if sequence.last() != Some(&origin.crate_name) {
+                sequence.push(origin.crate_name.clone());
+            }
+        }
+        if sequence.len() >= 2 {
+            sequences.push((sequence, *weight));
+        }
+    }
+
+    let mut origins: Vec<OriginUsage> = usage
+        .into_iter()
+        .map(|(crate_name, (derives, mut versions))| {
+            versions.sort_unstable();
+            versions.dedup();
+            OriginUsage {
+                crate_name: crate_name.to_string(),
+                derives,
+                versions: versions.into_iter().map(String::from).collect(),
+            }
+        })
+        .collect();
+    origins.sort_by(|a, b| b.derives.total_cmp(&a.derives).then_with(|| a.crate_name.cmp(&b.crate_name)));
+    (origins, sequences)
+}
+
+/// Finds derive names, by final path segment, resolved to more than one crate.
+fn shared_derive_names(derives: &[DeriveStatement]) -> Vec<SharedDeriveName> {
+    let mut by_name: BTreeMap<&str, BTreeMap<String, usize>> = BTreeMap::new();
+    for derive in derives {
+        for origin in &derive.origins {
+            *by_name.entry(derive_order::last_segment(&origin.derive)).or_default().entry(origin.crate_name.clone()).or_insert(0) += 1;
+        }
+    }
+    by_name
+        .into_iter()
+        .filter(|(_, crates)| crates.len() > 1)
+        .map(|(derive, crates)| SharedDeriveName { derive: derive.to_string(), crates })
+        .collect()
+}
+
+/// Checks each statement naming a standard subtrait and supertrait against
+/// the supertrait-first convention, returning the repositories, most
+/// violating statements first, and the weighted share of statements that
+/// follow it.
+fn supertrait_consistency(weighted: &[(&DeriveStatement, f64)]) -> (Vec<SupertraitConsistency>, Option<f64>) {
+    let mut by_repository: BTreeMap<&str, SupertraitConsistency> = BTreeMap::new();
+    let mut total_weight = 0.0;
+    let mut respecting_weight = 0.0;
+    for (derive, weight) in weighted {
+        if !derive_order::has_supertrait_pair(&derive.derives) {
+            continue;
+        }
+        let repository = by_repository.entry(&derive.repository).or_insert_with(|| SupertraitConsistency {
+            repository: derive.repository.clone(),
+            statements: 0,
+            violating_statements: 0,
+            violations: BTreeMap::new(),
+        });
+        repository.statements += 1;
+        total_weight += weight;
+
+        let violations = derive_order::supertrait_violations(&derive.derives);
+        if violations.is_empty() {
+            respecting_weight += weight;
+            continue;
+        }
+        repository.violating_statements += 1;
+        for violation in violations {
+            *repository.violations.entry(format!("{} before {}", violation.subtrait, violation.supertrait)).or_insert(0) += 1;
+        }
+    }
+
+    let mut repositories: Vec<SupertraitConsistency> = by_repository.into_values().collect();
+    repositories.sort_by(|a, b| b.violating_statements.cmp(&a.violating_statements)
+        .then_with(|| a.repository.cmp(&b.repository)));
+    let rate = (total_weight > 0.0).then(|| respecting_weight / total_weight);
+    (repositories, rate)
+}
+
+/// Scores each package's multi-derive statements, returning the packages,
+/// largest first, and their mean consistency weighted by each package's mean
+/// statement weight.
+fn package_consistency(weighted: &[(&DeriveStatement, f64)]) -> (Vec<PackageConsistency>, Option<f64>) {
+    struct Group<'a> {
+        /// Any statement of the package, for the package's fields
+        first: &'a DeriveStatement,
+        patterns: Vec<&'a [String]>,
+        weight: f64,
+    }
+
+    let mut by_package: BTreeMap<(&str, &str), Group> = BTreeMap::new();
+    for (derive, weight) in weighted {
+        if let Some(package) = &derive.package {
+            let group = by_package
+                .entry((&derive.repository, package))
+                .or_insert(Group { first: derive, patterns: Vec::new(), weight: 0.0 });
+            group.patterns.push(&derive.derives);
+            group.weight += weight;
+        }
+    }
+
+    let mut total_weight = 0.0;
+    let mut weighted_score = 0.0;
+    let mut packages: Vec<PackageConsistency> = by_package
+        .into_iter()
+        .map(|((repository, package), Group { first, patterns, weight })| {
+            let mut unique = patterns.clone();
+            unique.sort();
+            unique.dedup();
+            let consistency_score = consistency_score(&patterns);
+            let package_weight = weight / patterns.len() as f64;
+            total_weight += package_weight;
+            weighted_score += consistency_score * package_weight;
+            PackageConsistency {
+                repository: repository.to_string(),
+                package: package.to_string(),
+                edition: first.edition.clone(),
+                rust_version: first.rust_version.clone(),
+                proc_macro: first.proc_macro.unwrap_or(false),
+                multi_derive_statements: patterns.len(),
+                unique_patterns: unique.len(),
+                consistency_score,
+            }
+        })
+        .collect();
+    packages.sort_by(|a, b| b.multi_derive_statements.cmp(&a.multi_derive_statements)
+        .then_with(|| (&a.repository, &a.package).cmp(&(&b.repository, &b.package))));
+
+    let mean = (total_weight > 0.0).then(|| weighted_score / total_weight);
+    (packages, mean)
+}
+
+/// Splits each repository's multi-derive statements by author. Authors with a
+/// single statement still count towards between-author consistency, but carry
+/// no within-author information.
+fn author_consistency(multi: &[&DeriveStatement]) -> Vec<AuthorConsistency> {
+    let mut by_repository: BTreeMap<&str, BTreeMap<&str, Vec<&[String]>>> = BTreeMap::new();
+    for derive in multi {
+        if let Some(author) = &derive.author_hash {
+            by_repository.entry(&derive.repository).or_default()
+                .entry(author).or_default()
+                .push(&derive.derives);
+        }
+    }
+
+    by_repository
+        .into_iter()
+        .filter(|(_, authors)| authors.len() >= 2)
+        .filter_map(|(repository, authors)| {
+            let (weighted, statements) = authors
+                .values()
+                .filter(|patterns| patterns.len() >= 2)
+                .fold((0.0, 0), |(sum, count), patterns| {
+                    (sum + consistency_score(patterns) * patterns.len() as f64, count + patterns.len())
+                });
+            if statements == 0 {
+                return None;
+            }
+
+            let modal: Vec<&[String]> = authors.values().map(|patterns| modal_pattern(patterns)).collect();
+            Some(AuthorConsistency {
+                repository: repository.to_string(),
+                authors: authors.len(),
+                within_author: weighted / statements as f64,
+                between_author: consistency_score(&modal),
+            })
+        })
+        .collect()
+}
+
+/// The most common pattern, preferring the lexicographically smallest on ties.
+fn modal_pattern<'a>(patterns: &[&'a [String]]) -> &'a [String] {
+    let mut counts: BTreeMap<&[String], usize> = BTreeMap::new();
+    for pattern in patterns {
+        *counts.entry(pattern).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(pattern, _)| pattern)
+        .unwrap_or_default()
+}
+
+fn pair_preferences<'a>(patterns: impl Iterator<Item = (&'a [String], f64)>) -> Vec<PairPreference> {
+    let mut counts: HashMap<(&str, &str), (f64, f64)> = HashMap::new();
+    for (pattern, weight) in patterns {
+        for (i, a) in pattern.iter().enumerate() {
+            for b in &pattern[i + 1..] {
+                if a == b {
+                    continue;
+                }
+                if a < b {
+                    counts.entry((a, b)).or_default().0 += weight;
+                } else {
+                    counts.entry((b, a)).or_default().1 += weight;
+                }
+            }
+        }
+    }
+
+    let mut preferences: Vec<PairPreference> = counts
+        .into_iter()
+        .filter(|(_, (forward, reverse))| forward + reverse > 0.0)
+        .map(|((first, second), (forward, reverse))| PairPreference {
+            first: first.to_string(),
+            second: second.to_string(),
+            forward,
+            reverse,
+            preference_strength: (forward - reverse).abs() / (forward + reverse),
+        })
+        .collect();
+    preferences.sort_by(|a, b| (b.forward + b.reverse).total_cmp(&(a.forward + a.reverse))
+        .then_with(|| (&a.first, &a.second).cmp(&(&b.first, &b.second))));
+    preferences.truncate(MAX_PAIR_PREFERENCES);
+    preferences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(derives: &[&str]) -> Vec<String> {
+        derives.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_consistency_score() {
+        let a = pattern(&["Debug", "Clone"]);
+        let b = pattern(&["Clone", "Debug"]);
+        assert_eq!(consistency_score(&[&a, &a, &a]), 1.0);
+        assert_eq!(consistency_score(&[&a, &b]), 0.0);
+        let mixed = consistency_score(&[&a, &a, &a, &b]);
+        assert!(mixed > 0.0 && mixed < 1.0);
+    }
+
+    #[test]
+    fn test_pair_preferences() {
+        let a = pattern(&["Debug", "Clone", "Copy"]);
+        let b = pattern(&["Clone", "Debug"]);
+        let preferences = pair_preferences([a.as_slice(), a.as_slice(), b.as_slice()].into_iter().map(|p| (p, 1.0)));
+        let clone_debug = preferences.iter().find(|p| p.first == "Clone" && p.second == "Debug").unwrap();
+        assert_eq!((clone_debug.forward, clone_debug.reverse), (1.0, 2.0));
+        assert_eq!(preferences[0].first, "Clone");
+
+        // Halving the weight of the copies evens the preference out
+        let preferences = pair_preferences([(a.as_slice(), 0.5), (a.as_slice(), 0.5), (b.as_slice(), 1.0)].into_iter());
+        let clone_debug = preferences.iter().find(|p| p.first == "Clone" && p.second == "Debug").unwrap();
+        assert_eq!((clone_debug.forward, clone_debug.reverse, clone_debug.preference_strength), (1.0, 1.0, 0.0));
+    }
+
+    fn statement(repository: &str, author: &str, derives: &[&str]) -> DeriveStatement {
+        DeriveStatement {
+            repository: repository.to_string(),
+            file_path: "src/lib.rs".to_string(),
+            line_number: 1,
+            derives: pattern(derives),
+            author_hash: Some(author.to_string()),
+            ..DeriveStatement::default()
+        }
+    }
+
+    #[test]
+    fn test_author_consistency() {
+        // Each author is self-consistent, but they disagree with each other
+        let derives = vec![
+            statement("r", "alice", &["Debug", "Clone"]),
+            statement("r", "alice", &["Debug", "Clone"]),
+            statement("r", "bob", &["Clone", "Debug"]),
+            statement("r", "bob", &["Clone", "Debug"]),
+            statement("solo", "carol", &["Clone", "Debug"]),
+        ];
+        let statistics = DeriveStatistics::compute(&derives);
+        assert_eq!(statistics.author_consistency.len(), 1);
+        let repository = &statistics.author_consistency[0];
+        assert_eq!((repository.authors, repository.within_author, repository.between_author), (2, 1.0, 0.0));
+        assert_eq!(statistics.repositories.iter().find(|r| r.repository == "r").unwrap().consistency_score, 0.0);
+    }
+
+    #[test]
+    fn test_package_consistency() {
+        // A workspace whose crates each follow their own convention
+        let mut derives = vec![
+            statement("ws", "alice", &["Debug", "Clone"]),
+            statement("ws", "alice", &["Debug", "Clone"]),
+            statement("ws", "alice", &["Clone", "Debug"]),
+            statement("ws", "alice", &["Clone", "Debug"]),
+            statement("ws", "alice", &["Clone", "Debug"]),
+        ];
+        for (index, derive) in derives.iter_mut().enumerate() {
+            derive.package = Some(if index < 2 { "ws-core" } else { "ws-derive" }.to_string());
+            derive.proc_macro = Some(index >= 2);
+        }
+        derives.push(statement("ws", "alice", &["Clone", "Debug"]));
+
+        let statistics = DeriveStatistics::compute(&derives);
+        assert!(statistics.repositories[0].consistency_score < 1.0);
+        let names: Vec<_> = statistics.packages.iter().map(|p| (p.package.as_str(), p.multi_derive_statements, p.proc_macro)).collect();
+        assert_eq!(names, vec![("ws-derive", 3, true), ("ws-core", 2, false)]);
+        assert!(statistics.packages.iter().all(|p| p.consistency_score == 1.0));
+        assert_eq!(statistics.mean_package_consistency, Some(1.0));
+
+        // Without attribution there is nothing to group by
+        assert_eq!(DeriveStatistics::compute(&derives[5..]).mean_package_consistency, None);
+    }
+
+    #[test]
+    fn test_origins() {
+        let mut derives = vec![
+            statement("a", "alice", &["Debug", "Clone", "Serialize", "Display"]),
+            statement("b", "bob", &["Display", "Debug"]),
+            statement("b", "bob", &["Serialize", "Debug"]),
+        ];
+        let origins = [
+            vec!["Debug=std", "Clone=std", "Serialize=serde@1", "Display=strum@0.26"],
+            vec!["Display=derive_more@1", "Debug=std"],
+            vec!["Serialize=serde@1.0.197", "Debug=std"],
+        ];
+        for (derive, origins) in derives.iter_mut().zip(origins) {
+            derive.origins = origins.into_iter().map(|origin| origin.parse().unwrap()).collect();
+        }
+
+        let statistics = DeriveStatistics::compute(&derives);
+        let usage: Vec<_> = statistics.origins.iter().map(|o| (o.crate_name.as_str(), o.derives)).collect();
+        assert_eq!(usage, vec![("std", 4.0), ("serde", 2.0), ("derive_more", 1.0), ("strum", 1.0)]);
+        assert_eq!(statistics.origins[1].versions, vec!["1", "1.0.197"]);
+
+        // std before serde once, serde before std once; repeated std derives count as one
+        let std_serde = statistics.origin_preferences.iter().find(|p| p.first == "serde" && p.second == "std").unwrap();
+        assert_eq!((std_serde.forward, std_serde.reverse), (1.0, 1.0));
+
+        assert_eq!(statistics.shared_derive_names.len(), 1);
+        assert_eq!(statistics.shared_derive_names[0].derive, "Display");
+        assert_eq!(statistics.shared_derive_names[0].crates.keys().collect::<Vec<_>>(), vec!["derive_more", "strum"]);
+    }
+
+    #[test]
+    fn test_supertrait_consistency() {
+        let derives = vec![
+            statement("a", "alice", &["Clone", "Copy", "PartialEq", "Eq"]),
+            statement("a", "alice", &["Eq", "PartialEq", "Ord", "PartialOrd"]),
+            statement("b", "bob", &["Debug", "Clone", "Copy"]),
+            // No supertrait pair, so the convention doesn't apply
+            statement("c", "carol", &["Eq", "Debug", "Hash"]),
+        ];
+
+        let statistics = DeriveStatistics::compute_weighted(&derives, &[1.0, 1.0, 2.0, 1.0]);
+        let repositories: Vec<_> = statistics.supertrait_consistency.iter()
+            .map(|r| (r.repository.as_str(), r.statements, r.violating_statements))
+            .collect();
+        assert_eq!(repositories, vec![("a", 2, 1), ("b", 1, 0)]);
+        assert_eq!(statistics.supertrait_consistency[0].violations.keys().collect::<Vec<_>>(),
+                   vec!["Eq before PartialEq", "Ord before PartialOrd"]);
+        assert_eq!(statistics.supertrait_respect_rate, Some(0.75));
+    }
+
+    #[test]
+    fn test_families() {
+        let mut derives = vec![
+            statement("a", "alice", &["Debug", "Clone", "Serialize", "Deserialize", "Register"]),
+            statement("a", "alice", &["Clone", "Debug", "Serialize", "Deserialize"]),
+            statement("b", "bob", &["Register", "Debug"]),
+        ];
+        for derive in &mut derives {
+            derive.origins = derive.derives.iter()
+                .filter_map(|name| crate::provenance::resolve(name, None, None))
+                .collect();
+            if derive.derives.contains(&"Register".to_string()) {
+                derive.origins.push("Register=app-macros+local".parse().unwrap());
+            }
+        }
+
+        let report = FamilyReport::compute_weighted(&derives, &[1.0; 3], &Taxonomy::builtin());
+        let family = |family: DeriveFamily| report.families.iter().find(|f| f.family == family).unwrap();
+        // std derives disagree, ecosystem ones always follow the same order
+        assert_eq!((family(DeriveFamily::Std).statements, family(DeriveFamily::Std).multi_derive_statements), (3, 2));
+        assert_eq!(family(DeriveFamily::Std).mean_consistency, 0.0);
+        assert_eq!(family(DeriveFamily::Ecosystem).mean_consistency, 1.0);
+        assert_eq!(family(DeriveFamily::Local).derives[0].derive, "Register");
+        assert_eq!(family(DeriveFamily::Other).statements, 0);
+
+        // std before ecosystem twice, std before local once and after it once
+        let std_ecosystem = report.family_preferences.iter().find(|p| p.first == "ecosystem" && p.second == "std").unwrap();
+        assert_eq!((std_ecosystem.forward, std_ecosystem.reverse), (0.0, 2.0));
+        let local_std = report.family_preferences.iter().find(|p| p.first == "local" && p.second == "std").unwrap();
+        assert_eq!((local_std.forward, local_std.reverse), (1.0, 1.0));
+    }
+}
@@ -0,0 +1,236 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::git_backend::GitError;
+use crate::DeriveStatement;
+
+/// Hex digits kept from an author's hash
+const AUTHOR_HASH_LEN: usize = 16;
+
+/// Who last changed a line, and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineBlame {
+    pub author_hash: String,
+    /// Committer date of the commit that last changed the line, RFC 3339
+    pub commit_date: String,
+}
+
+/// Attributes derive lines to their authors with `git blame`.
+///
+/// Authors are identified only by a salted hash of their email address, so
+/// the same person is recognisable across repositories of one corpus without
+/// the output naming anyone.
+#[derive(Debug, Clone)]
+pub struct Blamer {
+    salt: String,
+}
+
+impl Blamer {
+    pub fn new(salt: String) -> Self {
+        Self { salt }
+    }
+
+    pub fn author_hash(&self, email: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update([0]);
+        hasher.update(email.trim().to_lowercase().as_bytes());
+        let digest = format!("{:x}", hasher.finalize());
+        digest[..AUTHOR_HASH_LEN].to_string()
+    }
+
+    /// Fills in `author_hash` and `line_commit_date` on statements from a clone
+    /// with full history, returning how many were attributed. Files that can't
+    /// be blamed are skipped with a warning.
+    pub async fn annotate(&self, repo_path: &Path, derives: &mut [DeriveStatement]) -> usize {
+        let mut by_file: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for derive in derives.iter() {
+            by_file.entry(derive.file_path.clone()).or_default().push(derive.line_number);
+        }
+
+        let mut blamed: HashMap<(String, usize), LineBlame> = HashMap::new();
+        for (file_path, lines) in by_file {
+            match self.blame_lines(repo_path, &file_path, &lines).await {
+                Ok(lines) => {
+                    for (line, blame) in lines {
+                        blamed.insert((file_path.clone(), line), blame);
+                    }
+                }
+                Err(e) => warn!("Failed to blame {}: {}", file_path, e),
+            }
+        }
+
+        let mut attributed = 0;
+        for derive in derives.iter_mut() {
+            // Several attributes may share a line, so entries are not consumed
+            if let Some(blame) = blamed.get(&(derive.file_path.clone(), derive.line_number)) {
+                derive.author_hash = Some(blame.author_hash.clone());
+                derive.line_commit_date = Some(blame.commit_date.clone());
+                attributed += 1;
+            }
+        }
+        attributed
+    }
+
+    /// Blames the given 1-based `lines` of `file_path` in a single git invocation.
+    pub async fn blame_lines(&self, repo_path: &Path, file_path: &str, lines: &[usize]) -> Result<HashMap<usize, LineBlame>, GitError> {
+        let mut args = vec!["blame".to_string(), "--porcelain".to_string()];
+        for line in lines {
+            args.push("-L".to_string());
+            args.push(format!("{},{}", line, line));
+        }
+        args.push("--".to_string());
+        args.push(file_path.to_string());
+
+        let output = Command::new("git")
+            .args(&args)
+            .current_dir(repo_path)
+            .output()
+            .await
+            .map_err(|e| GitError::Io(format!("Failed to execute git blame: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(GitError::Command {
+                args: format!("blame {}", file_path),
+                status: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        let blamed = self.parse_porcelain(&String::from_utf8_lossy(&output.stdout));
+        debug!("Blamed {} of {} lines in {}", blamed.len(), lines.len(), file_path);
+        Ok(blamed)
+    }
+
+    /// Parses `git blame --porcelain` output. Commit details are only printed
+    /// the first time a commit appears, so they are remembered by SHA.
+    fn parse_porcelain(&self, output: &str) -> HashMap<usize, LineBlame> {
+        let mut commits: HashMap<&str, (Option<&str>, Option<i64>)> = HashMap::new();
+        let mut line_commits: Vec<(usize, &str)> = Vec::new();
+        let mut current: Option<&str> = None;
+
+        for line in output.lines() {
+            if line.starts_with('\t') {
+                current = None;
+                continue;
+            }
+            match current {
+                None => {
+                    // Header: <sha> <original line> <final line> [<group size>]
+                    let mut fields = line.split(' ');
+                    if let (Some(sha), Some(_), Some(final_line)) = (fields.next(), fields.next(), fields.next()) {
+                        if let Ok(final_line) = final_line.parse() {
+                            commits.entry(sha).or_default();
+                            line_commits.push((final_line, sha));
+                            current = Some(sha);
+                        }
+                    }
+                }
+                Some(sha) => {
+                    let details = commits.entry(sha).or_default();
+                    if let Some(mail) = line.strip_prefix("author-mail ") {
+                        details.0 = Some(mail.trim_start_matches('<').trim_end_matches('>'));
+                    } else if let Some(time) = line.strip_prefix("committer-time ") {
+                        details.1 = time.parse().ok();
+                    }
+                }
+            }
+        }
+
+        line_commits
+            .into_iter()
+            .filter_map(|(line, sha)| {
+                let (mail, time) = commits.get(sha)?;
+                let commit_date = chrono::DateTime::from_timestamp((*time)?, 0)?.to_rfc3339();
+                Some((line, LineBlame { author_hash: self.author_hash((*mail)?), commit_date }))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, git_at, scratch_dir};
+
+    #[test]
+    fn test_parse_porcelain() {
+        let output = "\
+1111111111111111111111111111111111111111 3 3 1
+author Alice
+author-mail <Alice@Example.com>
+author-time 1577836800
+author-tz +0000
+committer Alice
+committer-mail <alice@example.com>
+committer-time 1577836800
+committer-tz +0000
+summary first
+filename src/lib.rs
+\t#[derive(Debug, Clone)]
+2222222222222222222222222222222222222222 9 10 1
+author Bob
+author-mail <bob@example.com>
+author-time 1580515200
+author-tz +0100
+committer Bob
+committer-mail <bob@example.com>
+committer-time 1580515200
+committer-tz +0100
+summary second
+filename src/lib.rs
+\t#[derive(Clone, Debug)]
+1111111111111111111111111111111111111111 7 20 1
+\t#[derive(Debug)]
+";
+        let blamer = Blamer::new("corpus".to_string());
+        let blamed = blamer.parse_porcelain(output);
+
+        assert_eq!(blamed.len(), 3);
+        assert_eq!(blamed[&3].author_hash, blamer.author_hash("alice@example.com"));
+        assert_eq!(blamed[&3], blamed[&20]);
+        assert_eq!(blamed[&3].commit_date, "2020-01-01T00:00:00+00:00");
+        assert_ne!(blamed[&10].author_hash, blamed[&3].author_hash);
+        assert_ne!(Blamer::new(String::new()).author_hash("bob@example.com"), blamed[&10].author_hash);
+    }
+
+    #[tokio::test]
+    async fn test_annotate_two_authors() {
+        let scratch = scratch_dir();
+        let repo = scratch.path();
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        std::fs::write(repo.join("src/lib.rs"), "#[derive(Debug)]\nstruct A;\n").unwrap();
+        git(repo, &["init", "-q"]);
+        git(repo, &["add", "-A"]);
+        git(repo, &["commit", "-q", "-m", "first"]);
+        std::fs::write(repo.join("src/lib.rs"), "#[derive(Debug)]\nstruct A;\n#[derive(Clone, Debug)]\nstruct B;\n").unwrap();
+        git_at(repo, &["commit", "-q", "-a", "-m", "second", "--author", "Bob <Bob@Example.com>"], "2022-03-01T09:30:00+00:00");
+
+        let statement = |file_path: &str, line_number: usize| DeriveStatement {
+            repository: "owner/repo".to_string(),
+            file_path: file_path.to_string(),
+            line_number,
+            ..DeriveStatement::default()
+        };
+        let mut derives = vec![statement("src/lib.rs", 1), statement("src/lib.rs", 3), statement("src/missing.rs", 1)];
+        let blamer = Blamer::new("corpus".to_string());
+        assert_eq!(blamer.annotate(repo, &mut derives).await, 2);
+
+        // Each line goes to whoever last changed it, at that commit's date
+        assert_eq!(derives[0].author_hash.as_deref(), Some(blamer.author_hash("fixture@example.com").as_str()));
+        assert_eq!(derives[0].line_commit_date.as_deref(), Some("2021-06-01T10:00:00+00:00"));
+        assert_eq!(derives[1].author_hash.as_deref(), Some(blamer.author_hash("bob@example.com").as_str()));
+        assert_eq!(derives[1].line_commit_date.as_deref(), Some("2022-03-01T09:30:00+00:00"));
+        assert_eq!((derives[2].author_hash.as_ref(), derives[2].line_commit_date.as_ref()), (None, None));
+
+        // Hashes are stable for one salt and differ between salts
+        let again = Blamer::new("corpus".to_string()).blame_lines(repo, "src/lib.rs", &[1, 3]).await.unwrap();
+        assert_eq!(Some(&again[&1].author_hash), derives[0].author_hash.as_ref());
+        assert_eq!(Some(&again[&3].author_hash), derives[1].author_hash.as_ref());
+        let other = Blamer::new("other corpus".to_string()).blame_lines(repo, "src/lib.rs", &[3]).await.unwrap();
+        assert_ne!(Some(&other[&3].author_hash), derives[1].author_hash.as_ref());
+    }
+}
@@ -0,0 +1,159 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+pub const INDEX_FILE: &str = "cache_index.json";
+
+/// A repository checkout managed by the cache.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub full_name: String,
+    /// Directory name under the cache root
+    pub directory: String,
+    pub size_bytes: u64,
+    pub commit_sha: Option<String>,
+    pub cloned_at: DateTime<Utc>,
+    pub last_accessed: DateTime<Utc>,
+    /// Only Rust sources and manifests are checked out
+    #[serde(default)]
+    pub sparse: bool,
+    /// GitHub's reported repository size less the sparse checkout's size on disk. GitHub
+    /// counts the packed history, so this is the saving against a full clone, not against
+    /// a shallow checkout of every file
+    #[serde(default)]
+    pub bytes_saved: Option<u64>,
+}
+
+/// The cache's record of what is on disk, persisted as `cache_index.json` in
+/// the cache root so checkouts outlive the run that made them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheIndex {
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+impl CacheIndex {
+    /// Reads the index, starting afresh if it is missing or unreadable.
+    pub fn load(cache_root: &Path) -> Self {
+        let path = cache_root.join(INDEX_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable cache index {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Writes the index atomically, so an interrupted run never leaves it truncated.
+    pub fn save(&self, cache_root: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(cache_root)?;
+        let path = cache_root.join(INDEX_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temp_path, &path)
+    }
+
+    pub fn get(&self, full_name: &str) -> Option<&CacheEntry> {
+        self.entries.get(full_name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size_bytes).sum()
+    }
+
+    /// Records `entry`, replacing any entry for the same repository or directory.
+    pub fn insert(&mut self, entry: CacheEntry) {
+        self.entries.retain(|_, existing| existing.directory != entry.directory);
+        self.entries.insert(entry.full_name.clone(), entry);
+    }
+
+    pub fn remove(&mut self, full_name: &str) -> Option<CacheEntry> {
+        self.entries.remove(full_name)
+    }
+
+    pub fn touch(&mut self, full_name: &str) {
+        if let Some(entry) = self.entries.get_mut(full_name) {
+            entry.last_accessed = Utc::now();
+        }
+    }
+
+    /// Whether some entry already manages `directory`.
+    pub fn manages(&self, directory: &str) -> bool {
+        self.entries.values().any(|entry| entry.directory == directory)
+    }
+
+    /// Drops entries whose directories have been deleted behind the cache's back.
+    pub fn prune_missing(&mut self, cache_root: &Path) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| Self::path_of(cache_root, entry).is_dir());
+        before - self.entries.len()
+    }
+
+    /// The least recently used entry that isn't `in_use`, larger entries first on ties.
+    pub fn eviction_candidate(&self, in_use: impl Fn(&str) -> bool) -> Option<&CacheEntry> {
+        self.entries
+            .values()
+            .filter(|entry| !in_use(&entry.full_name))
+            .min_by_key(|entry| (entry.last_accessed, std::cmp::Reverse(entry.size_bytes)))
+    }
+
+    pub fn path_of(cache_root: &Path, entry: &CacheEntry) -> PathBuf {
+        cache_root.join(&entry.directory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use chrono::Duration;
+
+    fn entry(full_name: &str, size_bytes: u64, idle_hours: i64) -> CacheEntry {
+        let last_accessed = Utc::now() - Duration::hours(idle_hours);
+        CacheEntry {
+            full_name: full_name.to_string(),
+            directory: full_name.replace('/', "_"),
+            size_bytes,
+            commit_sha: None,
+            cloned_at: last_accessed,
+            last_accessed,
+            sparse: false,
+            bytes_saved: None,
+        }
+    }
+
+    #[test]
+    fn test_eviction_order_and_persistence() {
+        let scratch = scratch_dir();
+        let cache_root = scratch.path();
+        let mut index = CacheIndex::default();
+        index.insert(entry("index-test/recent", 10, 1));
+        index.insert(entry("index-test/stale", 20, 48));
+        index.insert(entry("index-test/older", 30, 24));
+        index.save(cache_root).unwrap();
+
+        let mut index = CacheIndex::load(cache_root);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.total_size(), 60);
+        assert_eq!(index.eviction_candidate(|_| false).unwrap().full_name, "index-test/stale");
+
+        // Repositories in use are never evicted, and touching makes an entry recent
+        index.touch("index-test/older");
+        let candidate = index.eviction_candidate(|name| name == "index-test/stale");
+        assert_eq!(candidate.unwrap().full_name, "index-test/recent");
+
+        // An adopted directory is replaced once its repository is known by name
+        index.insert(CacheEntry { full_name: "index-test/Recent".to_string(), ..entry("index-test/recent", 15, 0) });
+        assert_eq!(index.len(), 3);
+        assert!(index.get("index-test/recent").is_none());
+
+        // No entry's directory exists, so all are pruned
+        assert_eq!(index.prune_missing(cache_root), 3);
+    }
+}
@@ -1,6 +1,16 @@
 use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
 
+/// The commit a checkout was analysed at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revision {
+    pub commit_sha: String,
+    /// Committer date, RFC 3339
+    pub commit_date: Option<String>,
+    /// Checked-out branch; `None` for a detached HEAD
+    pub branch: Option<String>,
+}
+
 /// Represents a repository that has been successfully cloned locally
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClonedRepository {
@@ -8,5 +18,5 @@ pub struct ClonedRepository {
     pub full_name: String,
     pub local_path: PathBuf,
     pub rust_files: Vec<PathBuf>,
+    pub revision: Option<Revision>,
 }
-
@@ -0,0 +1,216 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+use crate::DeriveStatement;
+
+/// Number of duplicated files listed in the report, most copied first
+const MAX_REPORTED_GROUPS: usize = 50;
+
+/// SHA-256 of a file's content, as recorded in `DeriveStatement::content_hash`.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// How statements from forks and from files copied between repositories are
+/// counted by the analysis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupMode {
+    /// Count every copy
+    #[default]
+    None,
+    /// Count a copied file once, in the first repository holding it; forks rank last
+    KeepFirst,
+    /// Count each copy of a file with weight 1/copies
+    Weight,
+    /// Drop forks and every copy of a file found in more than one repository
+    Exclude,
+}
+
+/// A file content found in more than one repository.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    /// Repositories holding the content, the one `keep_first` keeps first
+    pub repositories: Vec<String>,
+    /// Derive statements across all copies
+    pub statements: usize,
+}
+
+/// How much of the corpus is duplicated, and what deduplication kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicationReport {
+    pub mode: DedupMode,
+    pub repositories: usize,
+    /// Repositories GitHub reports as forks
+    pub forks: Vec<String>,
+    pub statements: usize,
+    /// Statements whose file content is unknown, so they can't be matched
+    pub unhashed_statements: usize,
+    /// Distinct file contents found in more than one repository
+    pub duplicated_files: usize,
+    /// Statements in copies other than the first of a duplicated file
+    pub duplicate_statements: usize,
+    pub retained_statements: usize,
+    /// Sum of the retained statements' weights
+    pub effective_statements: f64,
+    pub largest_groups: Vec<DuplicateGroup>,
+}
+
+/// Statements left for analysis, with their weights in the same order.
+#[derive(Debug, Clone)]
+pub struct Deduplication {
+    pub statements: Vec<DeriveStatement>,
+    pub weights: Vec<f64>,
+    pub report: DuplicationReport,
+}
+
+/// Finds file contents shared between repositories and applies `mode`.
+///
+/// The first copy of a file is the one in the repository ranked highest:
+/// non-forks before forks, then by position in `repository_order` (the run's
+/// discovery order), then by name for repositories it doesn't list.
+pub fn deduplicate(
+    derives: &[DeriveStatement],
+    repository_order: &[String],
+    forks: &HashSet<String>,
+    mode: DedupMode,
+) -> Deduplication {
+    let position: HashMap<&str, usize> = repository_order
+        .iter()
+        .enumerate()
+        .map(|(index, repository)| (repository.as_str(), index))
+        .collect();
+    let rank = |repository: &str| {
+        (forks.contains(repository), position.get(repository).copied().unwrap_or(usize::MAX), repository.to_string())
+    };
+
+    let mut holders: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut group_statements: HashMap<&str, usize> = HashMap::new();
+    for derive in derives {
+        if let Some(hash) = &derive.content_hash {
+            let repositories = holders.entry(hash).or_default();
+            if !repositories.contains(&derive.repository.as_str()) {
+                repositories.push(&derive.repository);
+            }
+            *group_statements.entry(hash).or_insert(0) += 1;
+        }
+    }
+    for repositories in holders.values_mut() {
+        repositories.sort_by_cached_key(|repository| rank(repository));
+    }
+
+    let mut statements = Vec::new();
+    let mut weights = Vec::new();
+    let mut unhashed_statements = 0;
+    let mut duplicate_statements = 0;
+    for derive in derives {
+        let fork = forks.contains(&derive.repository);
+        let (copies, first) = match derive.content_hash.as_deref().and_then(|hash| holders.get(hash)) {
+            Some(repositories) => (repositories.len(), repositories[0] == derive.repository),
+            None => {
+                unhashed_statements += 1;
+                (1, true)
+            }
+        };
+        if !first {
+            duplicate_statements += 1;
+        }
+
+        let weight = match mode {
+            DedupMode::None => Some(1.0),
+            DedupMode::KeepFirst => first.then_some(1.0),
+            DedupMode::Weight => Some(1.0 / copies as f64),
+            DedupMode::Exclude => (copies == 1 && !fork).then_some(1.0),
+        };
+        if let Some(weight) = weight {
+            statements.push(derive.clone());
+            weights.push(weight);
+        }
+    }
+
+    let mut largest_groups: Vec<DuplicateGroup> = holders
+        .iter()
+        .filter(|(_, repositories)| repositories.len() > 1)
+        .map(|(hash, repositories)| DuplicateGroup {
+            content_hash: hash.to_string(),
+            repositories: repositories.iter().map(|repository| repository.to_string()).collect(),
+            statements: group_statements[hash],
+        })
+        .collect();
+    let duplicated_files = largest_groups.len();
+    largest_groups.sort_by(|a, b| b.repositories.len().cmp(&a.repositories.len())
+        .then_with(|| b.statements.cmp(&a.statements))
+        .then_with(|| a.content_hash.cmp(&b.content_hash)));
+    largest_groups.truncate(MAX_REPORTED_GROUPS);
+
+    let repositories: HashSet<&str> = derives.iter().map(|derive| derive.repository.as_str()).collect();
+    let mut fork_names: Vec<String> = forks.iter().filter(|fork| repositories.contains(fork.as_str())).cloned().collect();
+    fork_names.sort();
+
+    let report = DuplicationReport {
+        mode,
+        repositories: repositories.len(),
+        forks: fork_names,
+        statements: derives.len(),
+        unhashed_statements,
+        duplicated_files,
+        duplicate_statements,
+        retained_statements: statements.len(),
+        effective_statements: weights.iter().sum(),
+        largest_groups,
+    };
+    Deduplication { statements, weights, report }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(repository: &str, content: &str) -> DeriveStatement {
+        DeriveStatement {
+            repository: repository.to_string(),
+            file_path: "src/lib.rs".to_string(),
+            line_number: 1,
+            derives: vec!["Debug".to_string(), "Clone".to_string()],
+            full_line: "#[derive(Debug, Clone)]".to_string(),
+            content_hash: Some(content_hash(content)),
+            ..DeriveStatement::default()
+        }
+    }
+
+    #[test]
+    fn test_dedup_modes() {
+        // The fork is discovered first, but the original still holds the first copy
+        let derives = vec![
+            statement("someone/fork", "shared"),
+            statement("owner/original", "shared"),
+            statement("other/vendor", "shared"),
+            statement("someone/fork", "diverged"),
+            statement("other/vendor", "own"),
+        ];
+        let order: Vec<String> = ["someone/fork", "owner/original", "other/vendor"].map(String::from).to_vec();
+        let forks: HashSet<String> = HashSet::from(["someone/fork".to_string()]);
+        fn repositories(dedup: &Deduplication) -> Vec<&str> {
+            dedup.statements.iter().map(|d| d.repository.as_str()).collect()
+        }
+
+        let none = deduplicate(&derives, &order, &forks, DedupMode::None);
+        assert_eq!(none.statements.len(), 5);
+        assert_eq!((none.report.duplicated_files, none.report.duplicate_statements), (1, 2));
+        assert_eq!(none.report.largest_groups[0].repositories, vec!["owner/original", "other/vendor", "someone/fork"]);
+        assert_eq!(none.report.forks, vec!["someone/fork"]);
+
+        let keep_first = deduplicate(&derives, &order, &forks, DedupMode::KeepFirst);
+        assert_eq!(repositories(&keep_first), vec!["owner/original", "someone/fork", "other/vendor"]);
+
+        let weight = deduplicate(&derives, &order, &forks, DedupMode::Weight);
+        assert_eq!(weight.statements.len(), 5);
+        assert!((weight.report.effective_statements - 3.0).abs() < 1e-9);
+
+        let exclude = deduplicate(&derives, &order, &forks, DedupMode::Exclude);
+        assert_eq!(repositories(&exclude), vec!["other/vendor"]);
+    }
+}
@@ -0,0 +1,217 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{Walk, WalkBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::generated;
+
+/// Paths skipped unless an include glob matches them: build output, vendored
+/// and third-party code, and tooling directories.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "**/target/**",
+    "**/node_modules/**",
+    "**/.git/**",
+    "**/.github/**",
+    "**/vendor/**",
+    "**/third_party/**",
+    "**/deps/**",
+    "**/build/**",
+];
+
+/// What a Rust file is for, judged from its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Src,
+    Test,
+    Bench,
+    Example,
+    BuildScript,
+    Generated,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Src => "src",
+            FileKind::Test => "test",
+            FileKind::Bench => "bench",
+            FileKind::Example => "example",
+            FileKind::BuildScript => "build_script",
+            FileKind::Generated => "generated",
+        }
+    }
+
+    /// Classifies a file by its path, or as `Generated` when [`generated::detect`]
+    /// named what generated it.
+    pub fn of(path: &str, generated_by: Option<&str>) -> Self {
+        match generated_by {
+            Some(_) => FileKind::Generated,
+            None => Self::classify(path),
+        }
+    }
+
+    /// Classifies a file by its path relative to the repository root. Files
+    /// only recognisable as generated by their content are classified `Src`.
+    pub fn classify(path: &str) -> Self {
+        let mut directories: Vec<&str> = path.split('/').collect();
+        let file_name = directories.pop().unwrap_or_default();
+        let stem = file_name.strip_suffix(".rs").unwrap_or(file_name);
+        let within = |names: &[&str]| directories.iter().any(|directory| names.contains(directory));
+
+        // A `build.rs` under `src/` is an ordinary module
+        if file_name == "build.rs" && !within(&["src"]) {
+            FileKind::BuildScript
+        } else if generated::path_generator(path).is_some() {
+            FileKind::Generated
+        } else if within(&["benches"]) {
+            FileKind::Bench
+        } else if within(&["examples"]) {
+            FileKind::Example
+        } else if within(&["tests", "test"]) || stem == "tests" || stem.ends_with("_test") || stem.ends_with("_tests") {
+            FileKind::Test
+        } else {
+            FileKind::Src
+        }
+    }
+}
+
+impl std::str::FromStr for FileKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "src" => Ok(FileKind::Src),
+            "test" => Ok(FileKind::Test),
+            "bench" => Ok(FileKind::Bench),
+            "example" => Ok(FileKind::Example),
+            "build_script" => Ok(FileKind::BuildScript),
+            "generated" => Ok(FileKind::Generated),
+            other => Err(format!("Unknown file kind: {}", other)),
+        }
+    }
+}
+
+/// Decides which Rust files of a repository are analysed, from globs over
+/// paths relative to the repository root.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        Self::new(&[], &[]).expect("default exclude globs are valid")
+    }
+}
+
+impl FileFilter {
+    /// Excludes [`DEFAULT_EXCLUDES`] and `exclude`; files matching an
+    /// `include` glob are analysed even when excluded.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+        let glob_set = |patterns: &mut dyn Iterator<Item = &str>| -> Result<GlobSet, globset::Error> {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                builder.add(Glob::new(pattern)?);
+            }
+            builder.build()
+        };
+
+        Ok(Self {
+            include: glob_set(&mut include.iter().map(String::as_str))?,
+            exclude: glob_set(&mut DEFAULT_EXCLUDES.iter().copied().chain(exclude.iter().map(String::as_str)))?,
+        })
+    }
+
+    /// Whether the file at `path`, relative to the repository root, is analysed.
+    pub fn accepts(&self, path: &str) -> bool {
+        path.ends_with(".rs") && (self.include.is_match(path) || !self.exclude.is_match(path))
+    }
+
+    /// Lists the analysed Rust files of a working tree, in path order. Files
+    /// ignored by the tree's `.gitignore` files are skipped, and symbolic
+    /// links are never followed.
+    pub fn rust_files(&self, root: &Path) -> Result<Vec<PathBuf>, ignore::Error> {
+        let mut rust_files = Vec::new();
+        for entry in working_tree_walker(root) {
+            let entry = entry?;
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            if self.accepts(&relative.to_string_lossy()) {
+                rust_files.push(entry.into_path());
+            }
+        }
+        rust_files.sort();
+        Ok(rust_files)
+    }
+}
+
+/// Walks a working tree the way git sees it: hidden files are included, only
+/// the tree's own `.gitignore` files apply, and `.git` is skipped.
+pub fn working_tree_walker(root: &Path) -> Walk {
+    WalkBuilder::new(root)
+        .hidden(false)
+        .parents(false)
+        .ignore(false)
+        .git_global(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            ("src/lib.rs", FileKind::Src),
+            ("crates/core/src/build.rs", FileKind::Src),
+            ("crates/core/build.rs", FileKind::BuildScript),
+            ("tests/integration.rs", FileKind::Test),
+            ("src/parser/tests.rs", FileKind::Test),
+            ("src/parser_test.rs", FileKind::Test),
+            ("benches/throughput.rs", FileKind::Bench),
+            ("examples/demo/main.rs", FileKind::Example),
+            ("src/generated/api.rs", FileKind::Generated),
+            ("src/proto/messages.pb.rs", FileKind::Generated),
+        ];
+        for (path, kind) in cases {
+            assert_eq!(FileKind::classify(path), kind, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_walks_with_gitignore_and_globs() {
+        let scratch = scratch_dir();
+        let root = scratch.path();
+        for dir in ["src", "build/helper/src", "examples", "ignored", "outside"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["src/lib.rs", "build/helper/src/lib.rs", "examples/demo.rs", "ignored/lib.rs", "outside/lib.rs"] {
+            std::fs::write(root.join(file), "struct A;\n").unwrap();
+        }
+        std::fs::write(root.join(".gitignore"), "ignored/\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("outside"), root.join("src/linked")).unwrap();
+
+        let relative = |filter: &FileFilter| -> Vec<String> {
+            filter.rust_files(root).unwrap().iter()
+                .map(|path| path.strip_prefix(root).unwrap().to_string_lossy().into_owned())
+                .filter(|path| !path.starts_with("outside"))
+                .collect()
+        };
+
+        assert_eq!(relative(&FileFilter::default()), vec!["examples/demo.rs", "src/lib.rs"]);
+
+        // Includes reach into excluded directories; excludes drop whole categories
+        let filter = FileFilter::new(&["build/**".to_string()], &["examples/**".to_string()]).unwrap();
+        assert_eq!(relative(&filter), vec!["build/helper/src/lib.rs", "src/lib.rs"]);
+        assert!(FileFilter::new(&["[".to_string()], &[]).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::DeriveStatement;
+
+/// Bytes at the start of a file searched for generator markers
+const HEADER_BYTES: usize = 4096;
+
+/// Signatures of specific generators, checked before the generic markers
+/// since their headers often contain those too. They only count in a file's
+/// leading comments or on an `@generated` line, so code that merely mentions
+/// a generator is not taken for its output.
+const GENERATOR_SIGNATURES: &[(&str, &str)] = &[
+    ("automatically generated by rust-bindgen", "bindgen"),
+    ("tonic-build", "tonic"),
+    ("prost-build", "prost"),
+];
+
+/// How statements from generated files count towards the analysis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratedMode {
+    /// Analyse them with everything else
+    #[default]
+    Include,
+    /// Leave them out of the analysis
+    Exclude,
+    /// Leave them out of the analysis and write their statistics separately
+    Separate,
+}
+
+/// Names what generated a file from its path alone: `out_dir` for build
+/// script output, `generated_path` for generated directories and file names.
+pub fn path_generator(path: &str) -> Option<&'static str> {
+    let mut directories: Vec<&str> = path.split('/').collect();
+    let file_name = directories.pop().unwrap_or_default();
+    let stem = file_name.strip_suffix(".rs").unwrap_or(file_name);
+
+    // Build scripts write to target/<profile>/build/<crate>-<hash>/out
+    let out = directories.iter().position(|directory| *directory == "out");
+    if out.is_some_and(|out| directories[..out].iter().any(|directory| *directory == "build" || *directory == "target")) {
+        return Some("out_dir");
+    }
+    if directories.contains(&"generated") || [".generated", "_generated", ".pb"].iter().any(|suffix| stem.ends_with(suffix)) {
+        return Some("generated_path");
+    }
+    None
+}
+
+/// Names what generated a file, from generator signatures and markers near
+/// the start of its content, then from its path.
+pub fn detect(path: &str, content: &str) -> Option<&'static str> {
+    content_generator(content).or_else(|| path_generator(path))
+}
+
+/// Names what generated a file from generator signatures and markers near
+/// the start of its content alone, whatever path it is found at.
+pub fn content_generator(content: &str) -> Option<&'static str> {
+    let mut end = content.len().min(HEADER_BYTES);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let header = &content[..end];
+
+    let leading = leading_comments(header);
+    let marked: Vec<&str> = header.lines().filter(|line| line.contains("@generated")).collect();
+    let signed = |signature: &str| leading.contains(signature) || marked.iter().any(|line| line.contains(signature));
+    if let Some((_, generator)) = GENERATOR_SIGNATURES.iter().find(|(signature, _)| signed(signature)) {
+        return Some(generator);
+    }
+    if header.contains("@generated") {
+        return Some("generated_marker");
+    }
+    if header.to_ascii_uppercase().contains("DO NOT EDIT") {
+        return Some("do_not_edit");
+    }
+    None
+}
+
+/// The comments and blank lines a file starts with, up to its first code or
+/// outer doc comment, which documents an item rather than the file.
+fn leading_comments(header: &str) -> &str {
+    let mut end = 0;
+    let mut in_block = false;
+    for line in header.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if in_block {
+            in_block = !trimmed.contains("*/");
+        } else if trimmed.starts_with("///") || (trimmed.starts_with("/**") && !trimmed.starts_with("/**/")) {
+            break;
+        } else if let Some(rest) = trimmed.strip_prefix("/*") {
+            in_block = !rest.contains("*/");
+        } else if !trimmed.is_empty() && !trimmed.starts_with("//") {
+            break;
+        }
+        end += line.len();
+    }
+    &header[..end]
+}
+
+/// Splits statements into those analysed and those from generated files
+/// reported separately, according to `mode`.
+pub fn split(derives: Vec<DeriveStatement>, mode: GeneratedMode) -> (Vec<DeriveStatement>, Vec<DeriveStatement>) {
+    let generated = derives.iter().filter(|derive| derive.generated_by.is_some()).count();
+    if generated > 0 {
+        info!("{} of {} derive statements come from generated files ({:?})", generated, derives.len(), mode);
+    }
+
+    match mode {
+        GeneratedMode::Include => (derives, Vec::new()),
+        GeneratedMode::Exclude => (derives.into_iter().filter(|derive| derive.generated_by.is_none()).collect(), Vec::new()),
+        GeneratedMode::Separate => derives.into_iter().partition(|derive| derive.generated_by.is_none()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let cases = [
+            ("src/lib.rs", "/* automatically generated by rust-bindgen 0.69.4 */\n", Some("bindgen")),
+            ("src/pb.rs", "// This file is @generated by prost-build.\n#[derive(Clone, PartialEq, ::prost::Message)]\n", Some("prost")),
+            ("src/message.rs", "#[derive(Clone, PartialEq, ::prost::Message)]\npub struct Hand {}\n", None),
+            ("src/api.rs", "// @generated by tonic-build\npub mod greeter_client {}\n", Some("tonic")),
+            ("src/table.rs", "// @generated by build.rs\n", Some("generated_marker")),
+            ("src/schema.rs", "// Code generated by schemagen. DO NOT EDIT.\n", Some("do_not_edit")),
+            ("target/debug/build/foo-1a2b/out/bindings.rs", "pub struct A;\n", Some("out_dir")),
+            ("src/generated/mod.rs", "pub struct A;\n", Some("generated_path")),
+            ("src/out/lib.rs", "#[derive(Debug)]\nstruct A;\n", None),
+        ];
+        for (path, content, generator) in cases {
+            assert_eq!(detect(path, content), generator, "{}", path);
+        }
+
+        // Generators named in code or item docs don't make a file generated
+        let mentions = [
+            "#[derive(Debug)]\npub struct Options;\n// Protos are compiled with prost-build in build.rs\n",
+            "//! Service plumbing\n\n/// Wraps a client built by tonic-build.\n#[derive(Clone)]\npub struct Client;\n",
+            "fn main() {\n    println!(\"cargo:warning=run tonic-build\");\n}\n",
+        ];
+        for content in mentions {
+            assert_eq!(detect("src/lib.rs", content), None, "{}", content);
+        }
+        assert_eq!(detect("src/lib.rs", "//! Messages\n/* automatically generated by rust-bindgen */\n"), Some("bindgen"));
+
+        // Markers far past the header are ordinary text
+        let late = format!("{}// @generated\n", "// padding\n".repeat(1000));
+        assert_eq!(detect("src/lib.rs", &late), None);
+    }
+}
@@ -0,0 +1,595 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::cloned_repo::Revision;
+
+/// Transfer progress of a clone or fetch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub received_objects: usize,
+    pub total_objects: usize,
+    pub received_bytes: usize,
+}
+
+/// Paths checked out by a sparse clone, as non-cone sparse-checkout patterns
+pub const SPARSE_PATTERNS: &[&str] = &["*.rs", "Cargo.toml"];
+
+/// How a clone or fetch should be performed.
+#[derive(Debug, Clone, Copy)]
+pub struct FetchDepth {
+    /// Fetch only the tip commit
+    pub shallow: bool,
+    /// Partial clone that fetches file contents on demand and checks out only
+    /// [`SPARSE_PATTERNS`]
+    pub sparse: bool,
+    /// No working tree; files are read from the object database
+    pub bare: bool,
+}
+
+#[derive(Debug)]
+pub enum GitError {
+    /// The repository or revision does not exist
+    NotFound(String),
+    /// The remote could not be reached or the transfer failed
+    Network(String),
+    /// The path is not a usable git checkout
+    InvalidRepository(String),
+    /// The backend cannot perform this operation, e.g. an unsupported protocol
+    Unsupported(String),
+    Io(String),
+    /// The git CLI exited unsuccessfully
+    Command {
+        args: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl GitError {
+    /// Whether another backend might succeed where this one failed.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, GitError::NotFound(_))
+    }
+}
+
+impl std::fmt::Display for GitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            GitError::Network(msg) => write!(f, "Network error: {}", msg),
+            GitError::InvalidRepository(msg) => write!(f, "Invalid repository: {}", msg),
+            GitError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            GitError::Io(msg) => write!(f, "I/O error: {}", msg),
+            GitError::Command { args, status, stderr } => match status {
+                Some(code) => write!(f, "git {} exited with {}: {}", args, code, stderr),
+                None => write!(f, "git {} was terminated: {}", args, stderr),
+            },
+        }
+    }
+}
+
+impl std::error::Error for GitError {}
+
+/// The git operations the repository cache needs. Implementations block, so
+/// callers on an async runtime should expect each call to take a while.
+pub trait GitBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Clones `url` into `dest`, checking out `branch` or the remote's default.
+    fn clone_repository(&self, url: &str, dest: &Path, branch: Option<&str>, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError>;
+
+    /// Checks out exactly `rev` (a commit, tag or branch) of `url` into an empty `dest`.
+    fn fetch_revision(&self, url: &str, dest: &Path, rev: &str, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError>;
+
+    /// Fetches the rest of the history of a shallow clone.
+    fn unshallow(&self, repo_path: &Path) -> Result<(), GitError>;
+
+    fn is_valid_repository(&self, repo_path: &Path) -> bool;
+
+    fn is_shallow(&self, repo_path: &Path) -> bool;
+
+    /// Whether `rev` names the commit checked out at `repo_path`.
+    fn is_at_revision(&self, repo_path: &Path, rev: &str) -> bool;
+
+    fn head_revision(&self, repo_path: &Path) -> Result<Revision, GitError>;
+}
+
+/// Which backend the cache uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// libgit2 in-process, falling back to the git CLI when it fails
+    Native,
+    /// The git command-line tool only
+    Cli,
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        if cfg!(feature = "native-git") {
+            BackendKind::Native
+        } else {
+            BackendKind::Cli
+        }
+    }
+}
+
+pub fn backend(kind: BackendKind) -> Box<dyn GitBackend> {
+    match kind {
+        #[cfg(feature = "native-git")]
+        BackendKind::Native => Box::new(WithFallback {
+            primary: native::NativeBackend,
+            fallback: CliBackend,
+        }),
+        #[cfg(not(feature = "native-git"))]
+        BackendKind::Native => {
+            warn!("Built without the native-git feature, using the git CLI");
+            Box::new(CliBackend)
+        }
+        BackendKind::Cli => Box::new(CliBackend),
+    }
+}
+
+/// Tries `primary`, retrying transfers with `fallback` after a failure that
+/// isn't definitive.
+pub struct WithFallback<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P, F> WithFallback<P, F> {
+    fn retry(&self, dest: &Path, operation: &str, error: GitError) -> Result<(), GitError>
+    where
+        P: GitBackend,
+    {
+        match error {
+            _ if !error.is_retryable() => return Err(error),
+            // Routine for requests the primary can't serve, such as partial clones with libgit2
+            GitError::Unsupported(_) => debug!("{} {} unsupported, falling back: {}", self.primary.name(), operation, error),
+            _ => warn!("{} {} failed, falling back: {}", self.primary.name(), operation, error),
+        }
+        // Start the fallback from an empty destination
+        if dest.exists() {
+            std::fs::remove_dir_all(dest).map_err(|e| GitError::Io(format!("Failed to remove {:?}: {}", dest, e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: GitBackend, F: GitBackend> GitBackend for WithFallback<P, F> {
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    fn clone_repository(&self, url: &str, dest: &Path, branch: Option<&str>, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError> {
+        match self.primary.clone_repository(url, dest, branch, depth, progress) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.retry(dest, "clone", e)?;
+                self.fallback.clone_repository(url, dest, branch, depth, progress)
+            }
+        }
+    }
+
+    fn fetch_revision(&self, url: &str, dest: &Path, rev: &str, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError> {
+        match self.primary.fetch_revision(url, dest, rev, depth, progress) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.retry(dest, "fetch", e)?;
+                self.fallback.fetch_revision(url, dest, rev, depth, progress)
+            }
+        }
+    }
+
+    fn unshallow(&self, repo_path: &Path) -> Result<(), GitError> {
+        self.primary.unshallow(repo_path).or_else(|e| {
+            warn!("{} unshallow failed, falling back: {}", self.primary.name(), e);
+            self.fallback.unshallow(repo_path)
+        })
+    }
+
+    fn is_valid_repository(&self, repo_path: &Path) -> bool {
+        self.primary.is_valid_repository(repo_path)
+    }
+
+    fn is_shallow(&self, repo_path: &Path) -> bool {
+        self.primary.is_shallow(repo_path)
+    }
+
+    fn is_at_revision(&self, repo_path: &Path, rev: &str) -> bool {
+        self.primary.is_at_revision(repo_path, rev)
+    }
+
+    fn head_revision(&self, repo_path: &Path) -> Result<Revision, GitError> {
+        self.primary.head_revision(repo_path).or_else(|_| self.fallback.head_revision(repo_path))
+    }
+}
+
+/// Runs the git command-line tool. Progress is only reported on completion.
+pub struct CliBackend;
+
+impl CliBackend {
+    /// Runs git in `dir`, returning its trimmed standard output.
+    fn run<I, S>(&self, dir: Option<&Path>, args: I) -> Result<String, GitError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<S> = args.into_iter().collect();
+        let mut command = Command::new("git");
+        command.args(&args);
+        if let Some(dir) = dir {
+            command.current_dir(dir);
+        }
+
+        let described = || args.iter().map(|a| a.as_ref().to_string_lossy()).collect::<Vec<_>>().join(" ");
+        let output = command
+            .output()
+            .map_err(|e| GitError::Io(format!("Failed to execute git {}: {}", described(), e)))?;
+
+        if !output.status.success() {
+            return Err(GitError::Command {
+                args: described(),
+                status: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Points HEAD at `rev`, checking it out unless the repository is bare.
+    fn detach_head(&self, repo_path: &Path, rev: &str, depth: FetchDepth) -> Result<(), GitError> {
+        if depth.bare {
+            let commit = self.run(Some(repo_path), ["rev-parse", "--verify", &format!("{}^{{commit}}", rev)])?;
+            self.run(Some(repo_path), ["update-ref", "--no-deref", "HEAD", &commit]).map(|_| ())
+        } else {
+            self.run(Some(repo_path), ["checkout", "--quiet", "--detach", rev]).map(|_| ())
+        }
+    }
+
+    fn set_sparse_patterns(&self, repo_path: &Path) -> Result<(), GitError> {
+        let args = ["sparse-checkout", "set", "--no-cone"].iter().chain(SPARSE_PATTERNS);
+        self.run(Some(repo_path), args).map(|_| ())
+    }
+}
+
+impl GitBackend for CliBackend {
+    fn name(&self) -> &'static str {
+        "git CLI"
+    }
+
+    fn clone_repository(&self, url: &str, dest: &Path, branch: Option<&str>, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError> {
+        let mut args: Vec<&OsStr> = ["clone", "--quiet", "--single-branch"].map(OsStr::new).to_vec();
+        if depth.shallow {
+            args.extend(["--depth", "1"].map(OsStr::new));
+        }
+        if depth.sparse {
+            args.extend(["--filter=blob:none", "--no-checkout"].map(OsStr::new));
+        }
+        if depth.bare {
+            args.push(OsStr::new("--bare"));
+        }
+        if let Some(branch) = branch {
+            args.extend([OsStr::new("--branch"), OsStr::new(branch)]);
+        }
+        // Paths go through as OS strings, so non-UTF-8 cache locations work
+        args.extend([OsStr::new(url), dest.as_os_str()]);
+        self.run(None, args)?;
+        if depth.sparse {
+            self.set_sparse_patterns(dest)?;
+            self.run(Some(dest), ["checkout", "--quiet", "HEAD"])?;
+        }
+        progress(Progress::default());
+        Ok(())
+    }
+
+    fn fetch_revision(&self, url: &str, dest: &Path, rev: &str, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError> {
+        std::fs::create_dir_all(dest).map_err(|e| GitError::Io(format!("Failed to create {:?}: {}", dest, e)))?;
+        self.run(Some(dest), if depth.bare { &["init", "--quiet", "--bare"][..] } else { &["init", "--quiet"] })?;
+        self.run(Some(dest), ["remote", "add", "origin", url])?;
+        let mut fetch = vec!["fetch", "--quiet"];
+        if depth.sparse {
+            // What `clone --filter` sets up, so the checkout can fetch contents on demand
+            self.run(Some(dest), ["config", "remote.origin.promisor", "true"])?;
+            self.run(Some(dest), ["config", "remote.origin.partialclonefilter", "blob:none"])?;
+            self.set_sparse_patterns(dest)?;
+            fetch.push("--filter=blob:none");
+        }
+
+        // Servers such as GitHub serve a single commit by full SHA, and tags and branches by name
+        let depth_args: &[&str] = if depth.shallow { &["--depth", "1"] } else { &[] };
+        let fetched = self.run(Some(dest), fetch.iter().chain(depth_args).chain(&["origin", rev]));
+        if let Err(e) = fetched {
+            // Abbreviated SHAs cannot be fetched directly; resolve them against the full history
+            warn!("Fetch of {} from {} failed, fetching full history: {}", rev, url, e);
+            self.run(Some(dest), fetch.iter().chain(&["--tags", "origin"]))?;
+            self.detach_head(dest, rev, depth)?;
+        } else {
+            self.detach_head(dest, "FETCH_HEAD", depth)?;
+        }
+
+        progress(Progress::default());
+        Ok(())
+    }
+
+    fn unshallow(&self, repo_path: &Path) -> Result<(), GitError> {
+        self.run(Some(repo_path), ["fetch", "--quiet", "--unshallow"]).map(|_| ())
+    }
+
+    fn is_valid_repository(&self, repo_path: &Path) -> bool {
+        // Checked first so git never finds a repository enclosing the cache instead
+        let is_repository = repo_path.join(".git").exists() || is_bare_repository(repo_path);
+        is_repository && self.run(Some(repo_path), ["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok()
+    }
+
+    fn is_shallow(&self, repo_path: &Path) -> bool {
+        self.run(Some(repo_path), ["rev-parse", "--is-shallow-repository"])
+            .map(|output| output == "true")
+            .unwrap_or(true)
+    }
+
+    fn is_at_revision(&self, repo_path: &Path, rev: &str) -> bool {
+        let head = self.run(Some(repo_path), ["rev-parse", "HEAD"]);
+        let target = self.run(Some(repo_path), ["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)]);
+        matches!((head, target), (Ok(head), Ok(target)) if head == target)
+    }
+
+    fn head_revision(&self, repo_path: &Path) -> Result<Revision, GitError> {
+        let stdout = self.run(Some(repo_path), ["log", "-1", "--format=%H%n%cI"])?;
+        let mut lines = stdout.lines();
+        let commit_sha = lines.next().unwrap_or("").trim().to_string();
+        if commit_sha.is_empty() {
+            return Err(GitError::InvalidRepository(format!("No HEAD commit in {:?}", repo_path)));
+        }
+        let commit_date = lines.next().map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+
+        // Fails on a detached HEAD, which has no branch to report
+        let branch = self.run(Some(repo_path), ["symbolic-ref", "--quiet", "--short", "HEAD"])
+            .ok()
+            .filter(|branch| !branch.is_empty());
+
+        Ok(Revision { commit_sha, commit_date, branch })
+    }
+}
+
+#[cfg(feature = "native-git")]
+mod native {
+    use git2::build::{CheckoutBuilder, RepoBuilder};
+    use git2::{ErrorClass, ErrorCode, FetchOptions, Oid, RemoteCallbacks, Repository};
+    use std::path::Path;
+
+    use super::{FetchDepth, GitBackend, GitError, Progress};
+    use crate::cloned_repo::Revision;
+
+    /// libgit2, built with HTTPS but not SSH support.
+    pub struct NativeBackend;
+
+    impl From<git2::Error> for GitError {
+        fn from(e: git2::Error) -> Self {
+            let msg = e.message().to_string();
+            match (e.code(), e.class()) {
+                (ErrorCode::NotFound, _) => GitError::NotFound(msg),
+                (_, ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssl) => GitError::Network(msg),
+                (_, ErrorClass::Ssh) => GitError::Unsupported(msg),
+                (_, ErrorClass::Repository) => GitError::InvalidRepository(msg),
+                (_, ErrorClass::Os) => GitError::Io(msg),
+                _ => GitError::Unsupported(msg),
+            }
+        }
+    }
+
+    fn fetch_options<'a>(depth: FetchDepth, progress: &'a dyn Fn(Progress)) -> FetchOptions<'a> {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.transfer_progress(move |stats| {
+            progress(Progress {
+                received_objects: stats.received_objects(),
+                total_objects: stats.total_objects(),
+                received_bytes: stats.received_bytes(),
+            });
+            true
+        });
+
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(callbacks);
+        if depth.shallow {
+            options.depth(1);
+        }
+        options
+    }
+
+    /// libgit2 has no partial clone support; the CLI fallback handles sparse requests.
+    fn reject_sparse(depth: FetchDepth) -> Result<(), GitError> {
+        if depth.sparse {
+            return Err(GitError::Unsupported("partial clone is not supported by libgit2".to_string()));
+        }
+        Ok(())
+    }
+
+    fn checkout_detached(repo: &Repository, oid: Oid) -> Result<(), GitError> {
+        let commit = repo.find_commit(oid)?;
+        if !repo.is_bare() {
+            repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))?;
+        }
+        repo.set_head_detached(oid)?;
+        Ok(())
+    }
+
+    impl GitBackend for NativeBackend {
+        fn name(&self) -> &'static str {
+            "libgit2"
+        }
+
+        fn clone_repository(&self, url: &str, dest: &Path, branch: Option<&str>, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError> {
+            reject_sparse(depth)?;
+            let mut builder = RepoBuilder::new();
+            builder.bare(depth.bare);
+            builder.fetch_options(fetch_options(depth, progress));
+            if let Some(branch) = branch {
+                // Like `--single-branch`, track only the branch being checked out
+                let refspec = format!("+refs/heads/{0}:refs/remotes/origin/{0}", branch);
+                builder.branch(branch);
+                builder.remote_create(move |repo, name, url| repo.remote_with_fetch(name, url, &refspec));
+            }
+            builder.clone(url, dest)?;
+            Ok(())
+        }
+
+        fn fetch_revision(&self, url: &str, dest: &Path, rev: &str, depth: FetchDepth, progress: &dyn Fn(Progress)) -> Result<(), GitError> {
+            reject_sparse(depth)?;
+            let repo = if depth.bare { Repository::init_bare(dest)? } else { Repository::init(dest)? };
+            let mut remote = repo.remote("origin", url)?;
+
+            if remote.fetch(&[rev], Some(&mut fetch_options(depth, progress)), None).is_ok() {
+                let fetched = repo.find_reference("FETCH_HEAD")?.peel_to_commit()?.id();
+                return checkout_detached(&repo, fetched);
+            }
+
+            // Abbreviated SHAs cannot be fetched directly; resolve them against the full history
+            let refspecs = ["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"];
+            remote.fetch(&refspecs, Some(&mut fetch_options(FetchDepth { shallow: false, ..depth }, progress)), None)?;
+            let resolved = repo.revparse_single(rev)?.peel_to_commit()?.id();
+            checkout_detached(&repo, resolved)
+        }
+
+        fn unshallow(&self, _repo_path: &Path) -> Result<(), GitError> {
+            Err(GitError::Unsupported("libgit2 cannot deepen a shallow clone".to_string()))
+        }
+
+        fn is_valid_repository(&self, repo_path: &Path) -> bool {
+            Repository::open(repo_path)
+                .map(|repo| repo.head().and_then(|head| head.peel_to_commit()).is_ok())
+                .unwrap_or(false)
+        }
+
+        fn is_shallow(&self, repo_path: &Path) -> bool {
+            Repository::open(repo_path).map(|repo| repo.is_shallow()).unwrap_or(true)
+        }
+
+        fn is_at_revision(&self, repo_path: &Path, rev: &str) -> bool {
+            let resolve = || -> Result<bool, git2::Error> {
+                let repo = Repository::open(repo_path)?;
+                let head = repo.head()?.peel_to_commit()?.id();
+                let target = repo.revparse_single(rev)?.peel_to_commit()?.id();
+                Ok(head == target)
+            };
+            resolve().unwrap_or(false)
+        }
+
+        fn head_revision(&self, repo_path: &Path) -> Result<Revision, GitError> {
+            let repo = Repository::open(repo_path)?;
+            let head = repo.head()?;
+            let commit = head.peel_to_commit()?;
+
+            let time = commit.committer().when();
+            let commit_date = chrono::FixedOffset::east_opt(time.offset_minutes() * 60)
+                .and_then(|offset| chrono::DateTime::from_timestamp(time.seconds(), 0).map(|utc| utc.with_timezone(&offset)))
+                .map(|date| date.to_rfc3339());
+            let branch = if head.is_branch() {
+                head.shorthand().map(String::from)
+            } else {
+                None
+            };
+
+            Ok(Revision {
+                commit_sha: commit.id().to_string(),
+                commit_date,
+                branch,
+            })
+        }
+    }
+}
+
+/// Whether `repo_path` is a bare repository, with no working tree.
+pub fn is_bare_repository(repo_path: &Path) -> bool {
+    !repo_path.join(".git").exists() && repo_path.join("HEAD").is_file() && repo_path.join("objects").is_dir()
+}
+
+/// Logs transfer progress at every quarter, for repositories with a known object count.
+pub fn log_progress(repository: &str) -> impl Fn(Progress) + '_ {
+    let last_quarter = std::sync::atomic::AtomicUsize::new(0);
+    move |progress: Progress| {
+        if progress.total_objects == 0 {
+            return;
+        }
+        let quarter = progress.received_objects * 4 / progress.total_objects;
+        if quarter > last_quarter.swap(quarter, std::sync::atomic::Ordering::Relaxed) {
+            info!("Fetching {}: {}/{} objects, {:.1} MB", repository, progress.received_objects,
+                  progress.total_objects, progress.received_bytes as f64 / (1024.0 * 1024.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, scratch_dir, FIXTURE_DATE};
+
+    #[test]
+    fn test_backends_clone_and_pin_revisions() {
+        let scratch = scratch_dir();
+        let root = scratch.path();
+        let origin = root.join("origin");
+        std::fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(&origin, &["config", "uploadpack.allowFilter", "true"]);
+        std::fs::write(origin.join("lib.rs"), "#[derive(Debug)]\nstruct A;\n").unwrap();
+        std::fs::write(origin.join("data.bin"), [0u8; 4096]).unwrap();
+        git(&origin, &["add", "-A"]);
+        git(&origin, &["commit", "-q", "-m", "first"]);
+        git(&origin, &["tag", "v1"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "second"]);
+
+        let url = format!("file://{}", origin.display());
+        let rev_parse = |rev: &str| CliBackend.run(Some(&origin), ["rev-parse", rev]).unwrap();
+        let (first, second) = (rev_parse("v1"), rev_parse("HEAD"));
+
+        for kind in [BackendKind::Native, BackendKind::Cli] {
+            let git = backend(kind);
+            let clone = root.join(format!("{:?}-clone", kind));
+            git.clone_repository(&url, &clone, Some("main"), FetchDepth { shallow: false, sparse: false, bare: false }, &|_| {}).unwrap();
+            assert!(git.is_valid_repository(&clone));
+            let revision = git.head_revision(&clone).unwrap();
+            assert_eq!(revision.commit_sha, second);
+            assert_eq!(revision.commit_date.as_deref(), Some(FIXTURE_DATE));
+            assert_eq!(revision.branch.as_deref(), Some("main"));
+
+            for rev in [first.as_str(), "v1"] {
+                let pinned = root.join(format!("{:?}-{}", kind, rev));
+                git.fetch_revision(&url, &pinned, rev, FetchDepth { shallow: true, sparse: false, bare: false }, &|_| {}).unwrap();
+                assert!(git.is_at_revision(&pinned, &first));
+                assert!(!git.is_at_revision(&pinned, &second));
+                assert_eq!(git.head_revision(&pinned).unwrap().branch, None);
+            }
+
+            // Sparse checkouts leave out everything but Rust sources and manifests
+            let sparse = root.join(format!("{:?}-sparse", kind));
+            git.clone_repository(&url, &sparse, Some("main"), FetchDepth { shallow: true, sparse: true, bare: false }, &|_| {}).unwrap();
+            assert_eq!(git.head_revision(&sparse).unwrap().commit_sha, second);
+            let pinned = root.join(format!("{:?}-sparse-v1", kind));
+            git.fetch_revision(&url, &pinned, "v1", FetchDepth { shallow: true, sparse: true, bare: false }, &|_| {}).unwrap();
+            assert!(git.is_at_revision(&pinned, &first));
+            for checkout in [&sparse, &pinned] {
+                assert!(checkout.join("lib.rs").exists() && !checkout.join("data.bin").exists());
+            }
+
+            // Bare repositories have HEAD at the requested revision and no working tree
+            let bare = FetchDepth { shallow: true, sparse: false, bare: true };
+            let bare_head = root.join(format!("{:?}-bare", kind));
+            git.clone_repository(&url, &bare_head, Some("main"), bare, &|_| {}).unwrap();
+            let bare_pinned = root.join(format!("{:?}-bare-v1", kind));
+            git.fetch_revision(&url, &bare_pinned, "v1", bare, &|_| {}).unwrap();
+            for (checkout, commit) in [(&bare_head, &second), (&bare_pinned, &first)] {
+                assert!(is_bare_repository(checkout) && git.is_valid_repository(checkout));
+                assert!(!checkout.join("lib.rs").exists());
+                assert_eq!(&git.head_revision(checkout).unwrap().commit_sha, commit);
+            }
+
+            assert!(!git.is_valid_repository(root));
+            assert!(git.clone_repository(&format!("{}-missing", url), &root.join("missing"), None,
+                                         FetchDepth { shallow: true, sparse: false, bare: false }, &|_| {}).is_err());
+        }
+    }
+}
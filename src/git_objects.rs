@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::file_filter::{FileFilter, FileKind};
+use crate::generated;
+use crate::git_backend::GitError;
+use crate::packages::{PackageIndex, CARGO_MANIFEST};
+use crate::parser::ParseStrategy;
+use crate::DeriveStatement;
+
+/// A Rust file in a commit's tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeFile {
+    pub oid: String,
+    pub path: String,
+}
+
+/// Reads trees and blobs from a repository's object database with the git
+/// CLI. Works on bare repositories, and never touches a working tree.
+#[derive(Debug, Clone)]
+pub struct ObjectDatabase {
+    repo_path: PathBuf,
+}
+
+impl ObjectDatabase {
+    pub fn new(repo_path: &Path) -> Self {
+        Self { repo_path: repo_path.to_path_buf() }
+    }
+
+    /// Lists the Rust files in the tree of `commit` that `filter` accepts.
+    /// Only tracked files are listed, so there is no `.gitignore` to apply.
+    pub async fn rust_files_at(&self, commit: &str, filter: &FileFilter) -> Result<Vec<TreeFile>, GitError> {
+        self.files_at(commit, |path| filter.accepts(path)).await
+    }
+
+    /// Indexes the Cargo packages of the tree of `commit` from its manifests.
+    pub async fn packages_at(&self, commit: &str) -> Result<PackageIndex, GitError> {
+        let is_manifest = |path: &str| path.rsplit('/').next() == Some(CARGO_MANIFEST);
+        let (manifests, sources): (Vec<TreeFile>, Vec<TreeFile>) = self
+            .files_at(commit, |path| is_manifest(path) || path.ends_with(".rs"))
+            .await?
+            .into_iter()
+            .partition(|file| is_manifest(&file.path));
+        let oids: Vec<&str> = manifests.iter().map(|file| file.oid.as_str()).collect();
+        let contents = self.read_blobs(&oids).await?;
+        let mut index = PackageIndex::from_manifests(
+            manifests.iter().zip(&contents).map(|(file, (_, content))| (file.path.as_str(), content.as_str())),
+        );
+
+        // The derives of the tree's own proc-macro packages are only named in their sources
+        let proc_macro_sources: Vec<&TreeFile> = sources
+            .iter()
+            .filter(|file| index.package_of(&file.path).is_some_and(|package| package.proc_macro))
+            .collect();
+        let oids: Vec<&str> = proc_macro_sources.iter().map(|file| file.oid.as_str()).collect();
+        for (file, (_, content)) in proc_macro_sources.iter().zip(self.read_blobs(&oids).await?) {
+            index.record_source(&file.path, &content);
+        }
+        Ok(index)
+    }
+
+    /// Lists the blobs in the tree of `commit` whose paths satisfy `wanted`.
+    async fn files_at(&self, commit: &str, wanted: impl Fn(&str) -> bool) -> Result<Vec<TreeFile>, GitError> {
+        let tree = self.git(&["ls-tree", "-r", "-z", commit]).await?;
+        Ok(tree
+            .split('\0')
+            .filter_map(|entry| {
+                let (meta, path) = entry.split_once('\t')?;
+                let mut fields = meta.split(' ');
+                let (_mode, kind, oid) = (fields.next()?, fields.next()?, fields.next()?);
+                (kind == "blob" && wanted(path)).then(|| TreeFile { oid: oid.to_string(), path: path.to_string() })
+            })
+            .collect())
+    }
+
+    /// Reads blobs through a single `git cat-file --batch` process, returning
+    /// `(oid, content)` in request order.
+    pub async fn read_blobs(&self, oids: &[&str]) -> Result<Vec<(String, String)>, GitError> {
+        if oids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut child = Command::new("git")
+            .args(["cat-file", "--batch"])
+            .current_dir(&self.repo_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| GitError::Io(format!("Failed to execute git cat-file: {}", e)))?;
+
+        // Feed requests concurrently so a full stdout pipe can't block them
+        let mut stdin = child.stdin.take().ok_or_else(|| GitError::Io("git cat-file has no stdin".to_string()))?;
+        let requests: String = oids.iter().map(|oid| format!("{}\n", oid)).collect();
+        let writer = tokio::spawn(async move { stdin.write_all(requests.as_bytes()).await });
+
+        let stdout = child.stdout.take().ok_or_else(|| GitError::Io("git cat-file has no stdout".to_string()))?;
+        let mut reader = BufReader::new(stdout);
+        let mut blobs = Vec::with_capacity(oids.len());
+        let mut header = String::new();
+        for _ in oids {
+            header.clear();
+            reader.read_line(&mut header).await.map_err(|e| GitError::Io(e.to_string()))?;
+            let mut fields = header.split_whitespace();
+            let (oid, size) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(oid), Some("blob"), Some(size)) => (oid.to_string(), size.parse::<usize>().unwrap_or(0)),
+                (Some(oid), Some("missing"), None) => return Err(GitError::NotFound(format!("Object {} is missing", oid))),
+                _ => return Err(GitError::InvalidRepository(format!("Unexpected git cat-file output: {}", header.trim()))),
+            };
+
+            let mut content = vec![0u8; size + 1];
+            reader.read_exact(&mut content).await.map_err(|e| GitError::Io(e.to_string()))?;
+            content.truncate(size);
+            blobs.push((oid, String::from_utf8_lossy(&content).into_owned()));
+        }
+
+        writer.await
+            .map_err(|e| GitError::Io(e.to_string()))?
+            .map_err(|e| GitError::Io(e.to_string()))?;
+        child.wait().await.map_err(|e| GitError::Io(e.to_string()))?;
+        Ok(blobs)
+    }
+
+    /// Runs git in the repository, returning its standard output.
+    pub async fn git(&self, args: &[&str]) -> Result<String, GitError> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.repo_path)
+            .output()
+            .await
+            .map_err(|e| GitError::Io(format!("Failed to execute git {}: {}", args[0], e)))?;
+
+        if !output.status.success() {
+            return Err(GitError::Command {
+                args: args.join(" "),
+                status: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// The derive statements of every Rust file in one commit's tree.
+#[derive(Debug, Clone, Default)]
+pub struct TreeExtraction {
+    pub rust_files: usize,
+    pub statements: Vec<DeriveStatement>,
+    /// Number of files extracted with each parse strategy
+    pub parse_strategies: BTreeMap<ParseStrategy, usize>,
+}
+
+/// What parsing a blob found, whichever path it is seen at.
+struct ParsedBlob {
+    statements: Vec<DeriveStatement>,
+    strategy: ParseStrategy,
+    /// Generator named by the content; the path may still name one
+    content_generator: Option<&'static str>,
+}
+
+/// Extracts derive statements from blobs, parsing each object ID only once
+/// so files shared between paths or unchanged between commits cost nothing.
+pub struct BlobExtractor {
+    objects: ObjectDatabase,
+    repository: String,
+    filter: FileFilter,
+    parsed_blobs: HashMap<String, ParsedBlob>,
+}
+
+impl BlobExtractor {
+    pub fn new(repo_path: &Path, repository: String) -> Self {
+        Self {
+            objects: ObjectDatabase::new(repo_path),
+            repository,
+            filter: FileFilter::default(),
+            parsed_blobs: HashMap::new(),
+        }
+    }
+
+    /// Chooses the files extracted, instead of the default exclusions.
+    pub fn with_filter(mut self, filter: FileFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn objects(&self) -> &ObjectDatabase {
+        &self.objects
+    }
+
+    /// Extracts the statements of every Rust file at `commit`, calling
+    /// `parse(content, repository, path)` for blobs not seen before, and
+    /// attributes them to the packages of the commit's manifests.
+    pub async fn extract_at<F>(&mut self, commit: &str, mut parse: F) -> Result<TreeExtraction, GitError>
+    where
+        F: FnMut(&str, &str, &str) -> (Vec<DeriveStatement>, ParseStrategy),
+    {
+        let files = self.objects.rust_files_at(commit, &self.filter).await?;
+
+        let mut missing: Vec<&str> = files
+            .iter()
+            .filter(|file| !self.parsed_blobs.contains_key(&file.oid))
+            .map(|file| file.oid.as_str())
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        debug!("{} of {} files in {} at {} not parsed before", missing.len(), files.len(), self.repository, commit);
+
+        for (oid, content) in self.objects.read_blobs(&missing).await? {
+            let path = files.iter().find(|file| file.oid == oid).map(|file| file.path.as_str()).unwrap_or_default();
+            let (statements, strategy) = parse(&content, &self.repository, path);
+            let content_generator = generated::content_generator(&content);
+            self.parsed_blobs.insert(oid, ParsedBlob { statements, strategy, content_generator });
+        }
+
+        let mut extraction = TreeExtraction { rust_files: files.len(), ..TreeExtraction::default() };
+        for file in &files {
+            let Some(parsed) = self.parsed_blobs.get(&file.oid) else { continue };
+            *extraction.parse_strategies.entry(parsed.strategy).or_insert(0) += 1;
+            // The blob was parsed under whichever path it was first seen at, so what depends on the path is redone
+            let generated_by = parsed.content_generator.or_else(|| generated::path_generator(&file.path));
+            extraction.statements.extend(parsed.statements.iter().map(|derive| DeriveStatement {
+                file_path: file.path.clone(),
+                generated_by: generated_by.map(String::from),
+                file_kind: Some(FileKind::of(&file.path, generated_by)),
+                ..derive.clone()
+            }));
+        }
+
+        let packages = self.objects.packages_at(commit).await?;
+        let attributed = packages.attribute(&mut extraction.statements);
+        debug!("Attributed {} of {} statements in {} at {} to {} packages",
+               attributed, extraction.statements.len(), self.repository, commit, packages.len());
+        Ok(extraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::RustParser;
+    use crate::test_support::{git, scratch_dir};
+
+    #[tokio::test]
+    async fn test_extract_from_bare_repository() {
+        let scratch = scratch_dir();
+        let root = scratch.path();
+        let origin = root.join("origin");
+        for dir in ["src", "examples", "target"] {
+            tokio::fs::create_dir_all(origin.join(dir)).await.unwrap();
+        }
+        let shared = "#[derive(Debug, Clone)]\nstruct A;\n";
+        tokio::fs::write(origin.join("src/lib.rs"), shared).await.unwrap();
+        tokio::fs::write(origin.join("examples/copy.rs"), shared).await.unwrap();
+        tokio::fs::write(origin.join("target/generated.rs"), shared).await.unwrap();
+        tokio::fs::write(origin.join("Cargo.toml"), "[package]\nname = \"fixture\"\nedition = \"2021\"\n").await.unwrap();
+        git(&origin, &["init", "-q"]);
+        git(&origin, &["add", "-A"]);
+        git(&origin, &["commit", "-q", "-m", "first"]);
+        git(root, &["clone", "-q", "--bare", "origin", "bare.git"]);
+
+        let parser = RustParser::new();
+        let mut parses = 0;
+        let mut extractor = BlobExtractor::new(&root.join("bare.git"), "fixture".to_string());
+        let extraction = extractor
+            .extract_at("HEAD", |content, repository, path| {
+                parses += 1;
+                parser.extract_derives_with_strategy(content, repository, path)
+            })
+            .await
+            .unwrap();
+
+        // Both copies are reported, but the shared blob is parsed once
+        assert_eq!(parses, 1);
+        assert_eq!(extraction.rust_files, 2);
+        let mut paths: Vec<_> = extraction.statements.iter().map(|s| s.file_path.as_str()).collect();
+        paths.sort_unstable();
+        assert_eq!(paths, vec!["examples/copy.rs", "src/lib.rs"]);
+        assert_eq!(extraction.parse_strategies.values().sum::<usize>(), 2);
+        assert!(extraction.statements.iter().all(|s| s.package.as_deref() == Some("fixture") && s.edition.as_deref() == Some("2021")));
+
+        // Parsed blobs are remembered across commits
+        extractor.extract_at("HEAD", |_, _, _| unreachable!()).await.unwrap();
+        assert!(matches!(extractor.objects().read_blobs(&["0000000000000000000000000000000000000001"]).await,
+                         Err(GitError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_shared_blob_is_classified_per_path() {
+        let scratch = scratch_dir();
+        let root = scratch.path();
+        let origin = root.join("origin");
+        tokio::fs::create_dir_all(origin.join("src/generated")).await.unwrap();
+        let shared = "#[derive(Debug, Clone)]\nstruct A;\n";
+        tokio::fs::write(origin.join("src/generated/model.rs"), shared).await.unwrap();
+        tokio::fs::write(origin.join("src/model.rs"), shared).await.unwrap();
+        tokio::fs::write(origin.join("src/marked.rs"), format!("// @generated by a script\n{}", shared)).await.unwrap();
+        git(&origin, &["init", "-q"]);
+        git(&origin, &["add", "-A"]);
+        git(&origin, &["commit", "-q", "-m", "first"]);
+
+        let parser = RustParser::new();
+        let mut extractor = BlobExtractor::new(&origin, "fixture".to_string());
+        let extraction = extractor
+            .extract_at("HEAD", |content, repository, path| parser.extract_derives_with_strategy(content, repository, path))
+            .await
+            .unwrap();
+
+        let mut classified: Vec<_> = extraction.statements
+            .iter()
+            .map(|s| (s.file_path.as_str(), s.generated_by.as_deref(), s.file_kind))
+            .collect();
+        classified.sort_unstable();
+        assert_eq!(classified, vec![
+            ("src/generated/model.rs", Some("generated_path"), Some(FileKind::Generated)),
+            ("src/marked.rs", Some("generated_marker"), Some(FileKind::Generated)),
+            ("src/model.rs", None, Some(FileKind::Src)),
+        ]);
+    }
+}
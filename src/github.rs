@@ -30,6 +30,12 @@ struct GitHubRepository {
     stargazers_count: u32,
     size: u32,
     default_branch: String,
+    fork: bool,
+}
+
+/// The repository search query, as passed in the `q` parameter.
+pub fn search_query(min_stars: u32) -> String {
+    format!("language:rust+stars:>={}+sort:stars+size:>10", min_stars)
 }
 
 #[derive(Clone)]
@@ -64,8 +70,8 @@ impl GitHubClient {
 
         while repositories.len() < desired && page <= max_pages {
             let url = format!(
-                "https://api.github.com/search/repositories?q=language:rust+stars:>={}+sort:stars+size:>10&sort=stars&order=desc&page={}&per_page={}",
-                min_stars, page, per_page
+                "https://api.github.com/search/repositories?q={}&sort=stars&order=desc&page={}&per_page={}",
+                search_query(min_stars), page, per_page
             );
 
             // Retry with exponential backoff on 403/429
@@ -135,6 +141,10 @@ impl GitHubClient {
                         clone_url: repo.clone_url,
                         language: repo.language,
                         stars: repo.stargazers_count,
+                        default_branch: Some(repo.default_branch),
+                        pinned_rev: None,
+                        size_kb: Some(repo.size as u64),
+                        fork: repo.fork,
                     });
                 }
             }
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::info;
+
+use crate::analysis::{DeriveStatistics, PairPreference};
+use crate::file_filter::FileFilter;
+use crate::git_backend::GitError;
+use crate::git_objects::BlobExtractor;
+use crate::parser::RustParser;
+use crate::DeriveStatement;
+
+/// Pair preferences kept per sample point, most frequent first
+const HISTORY_PAIR_PREFERENCES: usize = 20;
+
+/// How commits are chosen from a repository's history.
+#[derive(Debug, Clone, Copy)]
+pub enum Sampling {
+    /// Every tag, oldest first
+    Tags,
+    /// The last first-parent commit of each calendar month
+    Monthly,
+    /// Every Nth first-parent commit, always including HEAD
+    EveryN(usize),
+}
+
+/// A commit chosen for analysis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleCommit {
+    /// Tag name, month (`YYYY-MM`) or commit ordinal
+    pub label: String,
+    pub commit_sha: String,
+    /// Committer date, RFC 3339
+    pub commit_date: String,
+    #[serde(skip)]
+    timestamp: i64,
+}
+
+/// Ordering statistics for one sampled commit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPoint {
+    #[serde(flatten)]
+    pub commit: SampleCommit,
+    pub rust_files: usize,
+    pub derive_statements: usize,
+    pub multi_derive_statements: usize,
+    pub consistency_score: f64,
+    pub pair_preferences: Vec<PairPreference>,
+}
+
+/// Derive ordering of one repository over its history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryReport {
+    pub repository: String,
+    pub sampling: String,
+    pub points: Vec<HistoryPoint>,
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    GitError(String),
+    IoError(String),
+}
+
+impl std::fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryError::GitError(msg) => write!(f, "Git error: {}", msg),
+            HistoryError::IoError(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<GitError> for HistoryError {
+    fn from(e: GitError) -> Self {
+        match e {
+            GitError::Io(msg) => HistoryError::IoError(msg),
+            e => HistoryError::GitError(e.to_string()),
+        }
+    }
+}
+
+/// Walks the history of a local git repository, reading files straight from
+/// the object database so the working tree is never touched.
+pub struct HistoryWalker {
+    repository: String,
+    parser: RustParser,
+    /// Parses each blob once, so files unchanged between samples cost nothing
+    extractor: BlobExtractor,
+}
+
+impl HistoryWalker {
+    pub fn new(repo_path: &Path, repository: String, parser: RustParser) -> Self {
+        Self {
+            extractor: BlobExtractor::new(repo_path, repository.clone()),
+            repository,
+            parser,
+        }
+    }
+
+    /// Chooses the files analysed at each commit, instead of the default exclusions.
+    pub fn with_file_filter(mut self, filter: FileFilter) -> Self {
+        self.extractor = self.extractor.with_filter(filter);
+        self
+    }
+
+    /// Chooses commits to analyse, oldest first.
+    pub async fn sample_commits(&self, sampling: Sampling) -> Result<Vec<SampleCommit>, HistoryError> {
+        match sampling {
+            Sampling::Tags => {
+                let tags = self.git(&["for-each-ref", "--format=%(refname:short)", "refs/tags"]).await?;
+                let mut commits = Vec::new();
+                for tag in tags.lines().filter(|t| !t.is_empty()) {
+                    let log = self.git(&["log", "-1", "--format=%H%x09%ct%x09%cI", &format!("{}^{{commit}}", tag)]).await?;
+                    if let Some(commit) = parse_log_line(&log, tag.to_string()) {
+                        commits.push(commit);
+                    }
+                }
+                commits.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.label.cmp(&b.label)));
+                Ok(commits)
+            }
+            Sampling::Monthly => {
+                let mut commits: Vec<SampleCommit> = Vec::new();
+                for commit in self.first_parent_commits().await? {
+                    let month = commit.commit_date.get(..7).unwrap_or_default().to_string();
+                    match commits.last_mut() {
+                        Some(last) if last.label == month => *last = SampleCommit { label: month, ..commit },
+                        _ => commits.push(SampleCommit { label: month, ..commit }),
+                    }
+                }
+                Ok(commits)
+            }
+            Sampling::EveryN(n) => {
+                let all = self.first_parent_commits().await?;
+                let last = all.len().saturating_sub(1);
+                Ok(all
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, _)| index % n.max(1) == 0 || *index == last)
+                    .map(|(_, commit)| commit)
+                    .collect())
+            }
+        }
+    }
+
+    /// Extracts the derive statements of every Rust file at `commit`.
+    pub async fn extract_at(&mut self, commit: &SampleCommit) -> Result<(usize, Vec<DeriveStatement>), HistoryError> {
+        let parser = &self.parser;
+        let extraction = self.extractor
+            .extract_at(&commit.commit_sha, |content, repository, path| parser.extract_derives_with_strategy(content, repository, path))
+            .await?;
+
+        let statements = extraction.statements
+            .into_iter()
+            .map(|derive| DeriveStatement {
+                commit_sha: Some(commit.commit_sha.clone()),
+                commit_date: Some(commit.commit_date.clone()),
+                branch: None,
+                ..derive
+            })
+            .collect();
+        Ok((extraction.rust_files, statements))
+    }
+
+    /// Samples the history and computes ordering statistics at each point.
+    pub async fn analyse(&mut self, sampling: Sampling) -> Result<HistoryReport, HistoryError> {
+        let commits = self.sample_commits(sampling).await?;
+        info!("Sampled {} commits from {} ({:?})", commits.len(), self.repository, sampling);
+
+        let mut points = Vec::with_capacity(commits.len());
+        for commit in commits {
+            let (rust_files, statements) = self.extract_at(&commit).await?;
+            let mut statistics = DeriveStatistics::compute(&statements);
+            statistics.pair_preferences.truncate(HISTORY_PAIR_PREFERENCES);
+            info!("{} {} ({}): {} derive statements, consistency {:.3}",
+                  self.repository, commit.label, commit.commit_sha, statements.len(), statistics.mean_consistency);
+
+            points.push(HistoryPoint {
+                commit,
+                rust_files,
+                derive_statements: statistics.total_statements,
+                multi_derive_statements: statistics.multi_derive_statements,
+                consistency_score: statistics.mean_consistency,
+                pair_preferences: statistics.pair_preferences,
+            });
+        }
+
+        Ok(HistoryReport {
+            repository: self.repository.clone(),
+            sampling: format!("{:?}", sampling),
+            points,
+        })
+    }
+
+    async fn first_parent_commits(&self) -> Result<Vec<SampleCommit>, HistoryError> {
+        let log = self.git(&["log", "--first-parent", "--reverse", "--format=%H%x09%ct%x09%cI", "HEAD"]).await?;
+        Ok(log
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| parse_log_line(line, format!("commit {}", index + 1)))
+            .collect())
+    }
+
+    async fn git(&self, args: &[&str]) -> Result<String, HistoryError> {
+        Ok(self.extractor.objects().git(args).await?)
+    }
+}
+
+fn parse_log_line(line: &str, label: String) -> Option<SampleCommit> {
+    let mut fields = line.trim().split('\t');
+    let commit_sha = fields.next().filter(|sha| !sha.is_empty())?.to_string();
+    let timestamp = fields.next()?.parse().ok()?;
+    let commit_date = fields.next()?.to_string();
+    Some(SampleCommit { label, commit_sha, commit_date, timestamp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git_at, scratch_dir};
+
+    async fn commit(dir: &Path, file: &str, contents: &str, date: &str) {
+        tokio::fs::write(dir.join(file), contents).await.unwrap();
+        git_at(dir, &["add", "-A"], date);
+        git_at(dir, &["commit", "-q", "-m", file], date);
+    }
+
+    #[tokio::test]
+    async fn test_history_of_fixture_repository() {
+        let scratch = scratch_dir();
+        let dir = scratch.path();
+        tokio::fs::create_dir_all(dir.join("src")).await.unwrap();
+        git_at(dir, &["init", "-q"], "2020-01-01T00:00:00Z");
+
+        commit(dir, "src/a.rs", "#[derive(Debug, Clone)]\nstruct A;\n", "2020-01-05T00:00:00Z").await;
+        git_at(dir, &["tag", "v0.1"], "2020-01-05T00:00:00Z");
+        commit(dir, "src/b.rs", "#[derive(Debug, Clone)]\nstruct B;\n", "2020-01-20T00:00:00Z").await;
+        commit(dir, "src/c.rs", "#[derive(Clone, Debug)]\nstruct C;\n", "2020-02-10T00:00:00Z").await;
+        git_at(dir, &["tag", "-a", "-m", "release", "v0.2"], "2020-02-10T00:00:00Z");
+
+        let mut walker = HistoryWalker::new(dir, "fixture".to_string(), RustParser::new());
+
+        let labels = |commits: Vec<SampleCommit>| commits.into_iter().map(|c| c.label).collect::<Vec<_>>();
+        assert_eq!(labels(walker.sample_commits(Sampling::Tags).await.unwrap()), vec!["v0.1", "v0.2"]);
+        assert_eq!(labels(walker.sample_commits(Sampling::Monthly).await.unwrap()), vec!["2020-01", "2020-02"]);
+        assert_eq!(labels(walker.sample_commits(Sampling::EveryN(2)).await.unwrap()), vec!["commit 1", "commit 3"]);
+
+        let report = walker.analyse(Sampling::Monthly).await.unwrap();
+        let series: Vec<_> = report.points.iter().map(|p| (p.rust_files, p.derive_statements)).collect();
+        assert_eq!(series, vec![(2, 2), (3, 3)]);
+        assert_eq!(report.points[0].consistency_score, 1.0);
+        assert!(report.points[1].consistency_score < 1.0);
+        assert!(report.points[1].pair_preferences.iter().any(|p| p.forward == 1.0 && p.reverse == 2.0));
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::parallel_processor::RepositoryResult;
+use crate::RepositoryInfo;
+
+pub const LOCKFILE_NAME: &str = "repositories.lock";
+
+const GITHUB_PREFIX: &str = "https://github.com/";
+
+/// Parses a repository lockfile: one `owner/repo@rev` or `<clone url>@rev`
+/// per line, with `#` comments. The revision may be a commit, tag or branch;
+/// without one the repository's default branch is analysed.
+pub fn parse(text: &str) -> Result<Vec<RepositoryInfo>, String> {
+    let mut repositories = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        repositories.push(parse_entry(line).map_err(|e| format!("line {}: {}", idx + 1, e))?);
+    }
+    Ok(repositories)
+}
+
+fn parse_entry(entry: &str) -> Result<RepositoryInfo, String> {
+    // Only an `@` after the host ends the repository path; the user part of
+    // `https://user@host/owner/repo` or `git@host:owner/repo` is not a revision
+    let path_start = match entry.split_once("://") {
+        Some((scheme, rest)) => scheme.len() + 3 + rest.find('/').unwrap_or(rest.len()),
+        None => match (entry.find(':'), entry.find('/')) {
+            (Some(colon), Some(slash)) if colon < slash => colon + 1,
+            (Some(colon), None) => colon + 1,
+            _ => 0,
+        },
+    };
+    let (location, rev) = match entry[path_start..].split_once('@') {
+        Some((path, rev)) if path_start + path.len() > 0 => (&entry[..path_start + path.len()], Some(rev)),
+        _ => (entry, None),
+    };
+    if rev.is_some_and(|rev| rev.is_empty()) {
+        return Err(format!("empty revision in `{}`", entry));
+    }
+
+    let is_url = location.contains("://") || location.contains(':');
+    let full_name = if is_url {
+        let path = location.trim_end_matches('/').trim_end_matches(".git");
+        let mut segments = path.rsplit(['/', ':']);
+        match (segments.next(), segments.next()) {
+            (Some(repo), Some(owner)) if !repo.is_empty() && !owner.is_empty() => format!("{}/{}", owner, repo),
+            _ => return Err(format!("cannot determine repository name from `{}`", location)),
+        }
+    } else {
+        match location.split_once('/') {
+            Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => location.to_string(),
+            _ => return Err(format!("expected `owner/repo@rev` or `<clone url>@rev`, found `{}`", entry)),
+        }
+    };
+    let clone_url = if is_url {
+        location.to_string()
+    } else {
+        format!("{}{}.git", GITHUB_PREFIX, location)
+    };
+
+    Ok(RepositoryInfo {
+        name: full_name.rsplit('/').next().unwrap_or_default().to_string(),
+        full_name,
+        clone_url,
+        language: None,
+        stars: 0,
+        default_branch: None,
+        pinned_rev: rev.map(String::from),
+        size_kb: None,
+        fork: false,
+    })
+}
+
+/// Writes a lockfile pinning every repository of a run to the commit it was analysed at.
+/// Repositories whose commit is unknown are listed commented out.
+pub async fn write<P: AsRef<Path>>(
+    repositories: &[RepositoryInfo],
+    results: &[RepositoryResult],
+    path: P
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut contents = format!(
+        "# Repositories analysed by {} {} on {}\n# Re-run with --lockfile to analyse exactly these revisions\n",
+        env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), chrono::Utc::now().to_rfc3339()
+    );
+
+    let mut pinned = 0;
+    for repo in repositories {
+        let location = match repo.clone_url.strip_prefix(GITHUB_PREFIX) {
+            Some(rest) if rest.trim_end_matches(".git") == repo.full_name => repo.full_name.as_str(),
+            _ => repo.clone_url.as_str(),
+        };
+        let commit_sha = results.iter()
+            .find(|result| result.repo_name == repo.full_name)
+            .and_then(|result| result.revision.as_ref())
+            .map(|revision| revision.commit_sha.as_str());
+
+        match commit_sha {
+            Some(commit_sha) => {
+                contents.push_str(&format!("{}@{}\n", location, commit_sha));
+                pinned += 1;
+            }
+            None => contents.push_str(&format!("# {} (not analysed)\n", location)),
+        }
+    }
+
+    let mut file = File::create(path.as_ref()).await?;
+    file.write_all(contents.as_bytes()).await?;
+
+    info!("Saved lockfile pinning {} repositories to {}", pinned, path.as_ref().display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lockfile_entries() {
+        let text = "# pinned corpus\n\
+                    serde-rs/serde@v1.0.200\n\
+                    https://gitlab.com/group/project.git@4b825dc6\n\
+                    git@github.com:tokio-rs/tokio.git\n\
+                    https://ci@git.example.com/infra/tools.git\n\
+                    https://ci@git.example.com/infra/deploy.git@release/2\n\
+                    \n\
+                    rust-lang/log@feature/kv\n";
+        let repositories = parse(text).unwrap();
+        let summary: Vec<_> = repositories.iter()
+            .map(|r| (r.full_name.as_str(), r.clone_url.as_str(), r.pinned_rev.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("serde-rs/serde", "https://github.com/serde-rs/serde.git", Some("v1.0.200")),
+            ("group/project", "https://gitlab.com/group/project.git", Some("4b825dc6")),
+            ("tokio-rs/tokio", "git@github.com:tokio-rs/tokio.git", None),
+            ("infra/tools", "https://ci@git.example.com/infra/tools.git", None),
+            ("infra/deploy", "https://ci@git.example.com/infra/deploy.git", Some("release/2")),
+            ("rust-lang/log", "https://github.com/rust-lang/log.git", Some("feature/kv")),
+        ]);
+
+        assert!(parse("serde\n").unwrap_err().starts_with("line 1"));
+        assert!(parse("serde-rs/serde@\n").is_err());
+    }
+}
@@ -4,28 +4,100 @@ mod processor;
 mod persistence;
 mod error_handling;
 mod repo_cache;
+mod cache_index;
 mod cloned_repo;
 mod parallel_processor;
+mod sqlite_store;
+mod analysis;
+mod manifest;
+mod lockfile;
+mod git_objects;
+mod history;
+mod blame;
+mod git_backend;
+mod parse_cache;
+mod dedup;
+mod file_filter;
+mod generated;
+mod packages;
+mod provenance;
+mod taxonomy;
+#[cfg(test)]
+mod test_support;
 
 use leabharlann_logging::{LogConfig, LogLevel, LogFormat, init_logging};
 use leabharlann_string::ColoredString;
 use leabharlann_processing::*;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tracing::info;
 use error_handling::ErrorReporter;
-use repo_cache::CacheConfig;
+use repo_cache::{CacheConfig, CloneStrategy, RepositoryCache};
+use dedup::{DedupMode, Deduplication};
+use generated::GeneratedMode;
 use parallel_processor::{RepositoryTask, RepositoryProcessor};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use derive_order::{OrderSensitive, OrderingPolicy};
+use manifest::{ManifestRepository, RunManifest, ANALYSIS_MANIFEST_FILE, HISTORY_MANIFEST_FILE, MANIFEST_FILE};
+use taxonomy::Taxonomy;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct DeriveStatement {
     repository: String,
     file_path: String,
     line_number: usize,
     derives: Vec<String>,
     full_line: String,
+    /// Derives in this statement whose relative order is semantically significant
+    #[serde(default)]
+    order_sensitive: Vec<String>,
+    /// `struct`, `enum` or `union`, when the annotated item could be determined
+    #[serde(default)]
+    item_kind: Option<String>,
+    #[serde(default)]
+    item_name: Option<String>,
+    #[serde(default)]
+    item_line: Option<usize>,
+    /// Commit the repository was analysed at
+    #[serde(default)]
+    commit_sha: Option<String>,
+    /// Committer date of `commit_sha`, RFC 3339
+    #[serde(default)]
+    commit_date: Option<String>,
+    #[serde(default)]
+    branch: Option<String>,
+    /// Salted hash of the email of whoever last changed the line, with `--blame`
+    #[serde(default)]
+    author_hash: Option<String>,
+    /// Committer date of the commit that last changed the line, with `--blame`
+    #[serde(default)]
+    line_commit_date: Option<String>,
+    /// SHA-256 of the file's content, identifying files copied between repositories
+    #[serde(default)]
+    content_hash: Option<String>,
+    /// What the file is for, judged from its path
+    #[serde(default)]
+    file_kind: Option<file_filter::FileKind>,
+    /// Generator or marker that identified the file as generated, e.g. `bindgen` or `do_not_edit`
+    #[serde(default)]
+    generated_by: Option<String>,
+    /// Cargo package owning the file, from the nearest enclosing `Cargo.toml`
+    #[serde(default)]
+    package: Option<String>,
+    /// Edition of `package`, with workspace inheritance applied
+    #[serde(default)]
+    edition: Option<String>,
+    /// Minimum supported Rust version of `package`, when it declares one
+    #[serde(default)]
+    rust_version: Option<String>,
+    /// `package` is a proc-macro crate
+    #[serde(default)]
+    proc_macro: Option<bool>,
+    /// Crates the derives come from, for those that could be resolved
+    #[serde(default)]
+    origins: Vec<provenance::DeriveOrigin>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,11 +107,46 @@ struct RepositoryInfo {
     clone_url: String,
     language: Option<String>,
     stars: u32,
+    /// Default branch reported by GitHub at discovery time
+    #[serde(default)]
+    default_branch: Option<String>,
+    /// Revision to analyse instead of the default branch, from a lockfile
+    #[serde(default)]
+    pinned_rev: Option<String>,
+    /// Repository size in KB reported by GitHub, used to reserve cache space before cloning
+    #[serde(default)]
+    size_kb: Option<u64>,
+    /// GitHub reports the repository as a fork of another
+    #[serde(default)]
+    fork: bool,
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Rust Derive Analysis Tool - Analyzes derive macro usage across Rust repositories", long_about = None)]
-struct Args {
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    
+    /// Arguments for `collect`, which runs when no subcommand is given
+    #[command(flatten)]
+    collect: CollectArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Discover repositories on GitHub, extract their derive statements and analyse them
+    Collect(CollectArgs),
+    /// Run the statistics and summary stages on previously saved results
+    Analyze(AnalyzeArgs),
+    /// Track derive ordering across the git history of a local repository
+    History(HistoryArgs),
+    /// Reorder the derive lists of local Rust files into a canonical order
+    Fix(FixArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct CollectArgs {
     /// Output directory for analysis results
     #[arg(short, long, default_value = "data")]
     output: PathBuf,
@@ -56,6 +163,34 @@ struct Args {
     #[arg(short = 's', long, default_value_t = 5.0)]
     cache_size: f64,
     
+    /// Pause cloning while the cache's filesystem has less free space than this, in GB
+    #[arg(long, default_value_t = 1.0)]
+    min_free_space: f64,
+    
+    /// What to fetch and check out of each repository; `sparse` takes only Rust sources and manifests
+    #[arg(long, value_enum, default_value_t = CloneStrategy::default())]
+    clone_strategy: CloneStrategy,
+    
+    /// Parse every file again instead of reusing statements cached by content hash
+    #[arg(long)]
+    no_parse_cache: bool,
+    
+    /// How forks and files copied between repositories count towards the analysis
+    #[arg(long, value_enum, default_value_t = DedupMode::default())]
+    dedup: DedupMode,
+    
+    /// How statements from generated files count towards the analysis
+    #[arg(long, value_enum, default_value_t = GeneratedMode::default())]
+    generated: GeneratedMode,
+    
+    /// Analyse Rust files matching this glob even when excluded, e.g. `build/**` (repeatable)
+    #[arg(long)]
+    include: Vec<String>,
+    
+    /// Skip Rust files matching this glob, e.g. `examples/**` (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+    
     /// Number of worker threads for processing
     #[arg(short = 't', long, default_value_t = 4)]
     threads: usize,
@@ -64,6 +199,136 @@ struct Args {
     #[arg(long, default_value_t = 100)]
     min_stars: u32,
     
+    /// Analyse the `owner/repo@rev` entries in this lockfile instead of searching GitHub
+    #[arg(long)]
+    lockfile: Option<PathBuf>,
+    
+    /// Additional order-sensitive derives, one `path = reason` per line
+    #[arg(long)]
+    order_sensitive: Option<PathBuf>,
+    
+    /// Derive family mappings, one `pattern = std|ecosystem|local|other` per line
+    #[arg(long)]
+    derive_families: Option<PathBuf>,
+    
+    /// Git implementation used to clone repositories
+    #[arg(long, value_enum, default_value_t = git_backend::BackendKind::default())]
+    git_backend: git_backend::BackendKind,
+    
+    /// Attribute each derive line to an anonymised author with git blame (requires full clones).
+    /// Author hashes are salted with RDA_AUTHOR_SALT, which runs must share to be comparable
+    #[arg(long)]
+    blame: bool,
+    
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct AnalyzeArgs {
+    /// Saved results to analyse (.json, .ndjson, .csv, .parquet or .sqlite)
+    input: PathBuf,
+    
+    /// Output directory for the summary and statistics
+    #[arg(short, long, default_value = "data")]
+    output: PathBuf,
+    
+    /// How forks and files copied between repositories count towards the analysis
+    #[arg(long, value_enum, default_value_t = DedupMode::default())]
+    dedup: DedupMode,
+    
+    /// How statements from generated files count towards the analysis
+    #[arg(long, value_enum, default_value_t = GeneratedMode::default())]
+    generated: GeneratedMode,
+    
+    /// Derive family mappings, one `pattern = std|ecosystem|local|other` per line
+    #[arg(long)]
+    derive_families: Option<PathBuf>,
+    
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum SampleMode {
+    /// One sample per tag
+    Tags,
+    /// The last commit of each month
+    Monthly,
+    /// Every N commits, see `--every`
+    Commits,
+}
+
+#[derive(clap::Args, Debug)]
+struct HistoryArgs {
+    /// Local git repository with full history
+    repository: PathBuf,
+    
+    /// How to choose the commits to analyse
+    #[arg(long, value_enum, default_value_t = SampleMode::Monthly)]
+    sample: SampleMode,
+    
+    /// Commits between samples when sampling by commits
+    #[arg(long, default_value_t = 100)]
+    every: usize,
+    
+    /// Name to report the repository under (defaults to its directory name)
+    #[arg(long)]
+    name: Option<String>,
+    
+    /// Analyse Rust files matching this glob even when excluded, e.g. `build/**` (repeatable)
+    #[arg(long)]
+    include: Vec<String>,
+    
+    /// Skip Rust files matching this glob, e.g. `examples/**` (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+    
+    /// Output directory for the time series
+    #[arg(short, long, default_value = "data")]
+    output: PathBuf,
+    
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct FixArgs {
+    /// Rust files, or directories to fix every Rust file under
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    
+    /// Derives to put first, in this order (repeatable); the rest follow alphabetically
+    #[arg(long)]
+    precedence: Vec<String>,
+    
+    /// Move standard derives after their supertraits, e.g. `PartialEq` before `Eq`
+    #[arg(long)]
+    supertraits_first: bool,
+    
+    /// Additional order-sensitive derives, one `path = reason` per line
+    #[arg(long)]
+    order_sensitive: Option<PathBuf>,
+    
+    /// Reorder across order-sensitive derives instead of keeping them in place
+    #[arg(long)]
+    force: bool,
+    
+    /// Report the files that would change without writing them, failing if there are any
+    #[arg(long)]
+    check: bool,
+    
+    /// Analyse Rust files matching this glob even when excluded, e.g. `build/**` (repeatable)
+    #[arg(long)]
+    include: Vec<String>,
+    
+    /// Skip Rust files matching this glob, e.g. `examples/**` (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+    
     /// Verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -72,10 +337,17 @@ struct Args {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command line arguments
-    let args = Args::parse();
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Collect(cli.collect));
+    let verbose = match &command {
+        Command::Collect(args) => args.verbose,
+        Command::Analyze(args) => args.verbose,
+        Command::History(args) => args.verbose,
+        Command::Fix(args) => args.verbose,
+    };
     
     // Initialize logging with console output and file logging
-    let log_level = if args.verbose { LogLevel::Debug } else { LogLevel::Info };
+    let log_level = if verbose { LogLevel::Debug } else { LogLevel::Info };
     let config = LogConfig::new()
         .level(log_level)
         .console(true)
@@ -85,6 +357,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     init_logging(&config)?;
     
+    match command {
+        Command::Collect(args) => collect(args).await,
+        Command::Analyze(args) => analyze(args).await,
+        Command::History(args) => history(args).await,
+        Command::Fix(args) => fix(args).await,
+    }
+}
+
+async fn collect(args: CollectArgs) -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting Rust Derive Analysis Tool - Phase 1: Data Acquisition");
     info!("Configuration: repo_limit={}, cache_limit={}, cache_size={}GB, threads={}, min_stars={}, output_dir={:?}", 
           args.repo_limit, args.cache_limit, args.cache_size, args.threads, args.min_stars, args.output);
@@ -99,17 +380,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ErrorReporter::report_warning("GITHUB_TOKEN not set - API rate limits will be more restrictive");
     }
     
-    // Step 1: Discover Rust repositories
-    let github_client = github::GitHubClient::new(github_token.clone());
-    let repositories = github_client.search_rust_repositories(args.repo_limit, args.min_stars).await?;
-    info!("Discovered {} repositories for analysis", repositories.len());
-    ErrorReporter::report_info(&format!("Successfully discovered {} Rust repositories", repositories.len()));
+    // Step 1: Discover Rust repositories, or take them pinned from a lockfile
+    let repositories = match &args.lockfile {
+        Some(path) => {
+            let text = tokio::fs::read_to_string(path).await?;
+            match lockfile::parse(&text) {
+                Ok(repositories) => {
+                    ErrorReporter::report_info(&format!("Loaded {} pinned repositories from {}", repositories.len(), path.display()));
+                    repositories
+                }
+                Err(e) => {
+                    let error = error_handling::AnalysisError::Configuration(format!("Invalid lockfile {}: {}", path.display(), e));
+                    ErrorReporter::report_error(&error);
+                    return Err(error.into());
+                }
+            }
+        }
+        None => {
+            let github_client = github::GitHubClient::new(github_token.clone());
+            let repositories = github_client.search_rust_repositories(args.repo_limit, args.min_stars).await?;
+            info!("Discovered {} repositories for analysis", repositories.len());
+            ErrorReporter::report_info(&format!("Successfully discovered {} Rust repositories", repositories.len()));
+            repositories
+        }
+    };
     
     // Step 2: Process repositories in parallel using leabharlann-processing
     let cache_dir = args.output.join("cache");
     tokio::fs::create_dir_all(&cache_dir).await?;
     
     // Create repository tasks
+    let discovered = repositories.clone();
     let repository_tasks: Vec<RepositoryTask> = repositories
         .into_iter()
         .map(|repo| RepositoryTask { repo_info: repo })
@@ -122,13 +423,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     info!("Processing {} repositories using {} worker threads in parallel...", repository_tasks.len(), args.threads);
     
+    // Blame reads every version of the lines it attributes, which a partial clone fetches one object at a time
+    let clone_strategy = if args.blame { CloneStrategy::Full } else { args.clone_strategy };
+    
+    // Parsed statements are reused across runs and forks by content hash
+    let parse_cache = if args.no_parse_cache {
+        None
+    } else {
+        match parse_cache::ParseCache::open(cache_dir.join(parse_cache::PARSE_CACHE_FILE)) {
+            Ok(parse_cache) => Some(Arc::new(parse_cache)),
+            Err(e) => {
+                ErrorReporter::report_warning(&format!("Failed to open parse cache, parsing every file: {}", e));
+                None
+            }
+        }
+    };
+    
     // Set up parallel processing system
     let cache_config = CacheConfig {
         max_repositories: args.cache_limit,
         cache_root: cache_dir,
         max_size_gb: args.cache_size,
+        min_free_gb: args.min_free_space,
+        clone_strategy,
+        full_history: args.blame,
+        git_backend: args.git_backend,
     };
     
+    // One cache shared by all workers, taking over repositories left by earlier runs
+    let cache = Arc::new(RepositoryCache::new(cache_config));
+    if let Err(e) = cache.adopt_existing() {
+        ErrorReporter::report_warning(&format!("Failed to adopt cached repositories: {}", e));
+    }
+    
     let num_threads = args.threads;
     let system_config = SystemConfig::default();
     let system_metrics = Arc::new(SystemMetrics::new());
@@ -136,11 +463,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Create shared storage for results
     let results_storage = Arc::new(Mutex::new(Vec::new()));
     
+    // Statements are streamed here as each repository completes
+    let ndjson_output = args.output.join("derive_statements.ndjson");
+    let sink = Arc::new(Mutex::new(persistence::NdjsonSink::create(&ndjson_output)?));
+    
     // Create channel hub
     let (hub, work_receivers) = ChannelHub::new(num_threads, system_config);
     
+    let parser = parser::RustParser::with_order_sensitive(order_sensitive(args.order_sensitive.as_deref()).await?);
+    let taxonomy = taxonomy(args.derive_families.as_deref()).await?;
+    
     // Create processor with shared storage
-    let processor = RepositoryProcessor::new(cache_config, results_storage.clone(), args.output.clone());
+    let mut processor = RepositoryProcessor::new(cache, parser, results_storage.clone(), sink.clone());
+    if args.blame {
+        // Read from the environment so the salt never appears in the manifest's arguments
+        let salt = std::env::var("RDA_AUTHOR_SALT").unwrap_or_default();
+        if salt.is_empty() {
+            ErrorReporter::report_warning("RDA_AUTHOR_SALT not set - author hashes can be reversed by hashing known emails");
+        }
+        processor = processor.with_blame(blame::Blamer::new(salt));
+    }
+    if let Some(parse_cache) = &parse_cache {
+        processor = processor.with_parse_cache(parse_cache.clone());
+    }
+    processor = processor.with_file_filter(file_filter(&args.include, &args.exclude)?);
     info!("Processor configuration: {}", processor.config_info());
     
     // Spawn workers
@@ -196,8 +542,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let collector_stats = collector_handle.join().unwrap();
     info!("Collector completed");
     
+    // All workers are done, so the streamed output is complete
+    match sink.lock().map(|mut sink| sink.finish()) {
+        Ok(Ok(path)) => info!("NDJSON output finalised at {}", path.display()),
+        Ok(Err(e)) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to finalise NDJSON: {}", e));
+            ErrorReporter::report_error(&error);
+        }
+        Err(_) => ErrorReporter::report_warning("NDJSON sink lock poisoned; output left as .partial"),
+    }
+    
     let mut all_derives = Vec::new();
     let mut total_files_processed = 0;
+    let mut total_bytes_saved = 0;
     
     // Extract results from shared storage
     let repository_results = if let Ok(results) = results_storage.lock() {
@@ -219,6 +576,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         all_derives.extend(repo_result.derive_statements.clone());
         total_files_processed += repo_result.rust_files_processed;
+        total_bytes_saved += repo_result.bytes_saved.unwrap_or(0);
     }
     
     info!("Total: {} files processed, {} derive statements found across {} repositories",
@@ -226,11 +584,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     info!("Found {} total derive statements across all repositories", all_derives.len());
     
+    let sparse_checkouts = repository_results.iter().filter(|result| result.sparse_checkout).count();
+    if sparse_checkouts > 0 {
+        info!("{} of {} repositories were sparse checkouts, saving about {:.1}MB",
+              sparse_checkouts, repository_results.len(), total_bytes_saved as f64 / (1024.0 * 1024.0));
+    }
+    
+    let parse_cache_stats = parse_cache.as_ref().map(|parse_cache| parse_cache.stats());
+    if let Some(stats) = &parse_cache_stats {
+        info!("Parse cache: {} hits, {} misses ({:.1}% hit rate)",
+              stats.hits, stats.misses, stats.hit_rate() * 100.0);
+    }
+    
     let json_output = args.output.join("derive_statements.json");
     let csv_output = args.output.join("derive_statements.csv");
-    let summary_output = args.output.join("analysis_summary.json");
+    let parquet_output = args.output.join("derive_statements.parquet");
+    let sqlite_output = args.output.join("derive_statements.sqlite");
+    
+    // The database records every analysed repository, so one whose derives are all gone has none as of this run
+    let analysed: Vec<(&str, Option<&cloned_repo::Revision>)> = repository_results.iter()
+        .map(|result| (result.repo_name.as_str(), result.revision.as_ref()))
+        .collect();
+    match persistence::ResultsPersistence::save_to_sqlite(&all_derives, &analysed, &sqlite_output).await {
+        Ok(_) => ErrorReporter::report_info("SQLite output saved successfully"),
+        Err(e) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to save SQLite: {}", e));
+            ErrorReporter::report_error(&error);
+            return Err(e);
+        }
+    }
     
     // Save results in multiple formats
+    let mut analysis_outputs = Vec::new();
     if !all_derives.is_empty() {
         match persistence::ResultsPersistence::save_to_json(&all_derives, &json_output).await {
             Ok(_) => ErrorReporter::report_info("JSON output saved successfully"),
@@ -250,29 +635,341 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         
-        match persistence::ResultsPersistence::save_summary(&all_derives, &summary_output).await {
-            Ok(_) => ErrorReporter::report_info("Summary output saved successfully"),
+        match persistence::ResultsPersistence::save_to_parquet(&all_derives, &parquet_output).await {
+            Ok(_) => ErrorReporter::report_info("Parquet output saved successfully"),
             Err(e) => {
-                let error = error_handling::AnalysisError::Persistence(format!("Failed to save summary: {}", e));
+                let error = error_handling::AnalysisError::Persistence(format!("Failed to save Parquet: {}", e));
                 ErrorReporter::report_error(&error);
                 return Err(e);
             }
         }
         
-        ErrorReporter::report_success("Analysis results saved to JSON, CSV, and summary files");
+        // Forks rank after the repositories they copy, whatever their stars
+        let repository_order: Vec<String> = discovered.iter().map(|repo| repo.full_name.clone()).collect();
+        let forks: HashSet<String> = discovered.iter().filter(|repo| repo.fork).map(|repo| repo.full_name.clone()).collect();
+        let (analysed, generated) = generated::split(all_derives.clone(), args.generated);
+        let deduplication = dedup::deduplicate(&analysed, &repository_order, &forks, args.dedup);
+        analysis_outputs = write_analysis(&deduplication, &generated, &taxonomy, &args.output).await?;
+        
+        ErrorReporter::report_success("Analysis results saved to JSON, CSV, Parquet, SQLite, summary, and statistics files");
     } else {
         ErrorReporter::report_warning("No derive statements found in any repositories");
     }
     
+    // Pin this run's corpus so it can be re-analysed exactly with --lockfile
+    let lockfile_output = args.output.join(lockfile::LOCKFILE_NAME);
+    if let Err(e) = lockfile::write(&discovered, &repository_results, &lockfile_output).await {
+        ErrorReporter::report_warning(&format!("Failed to save lockfile: {}", e));
+    }
+    
+    // Describe the run so its outputs can be reproduced and verified later
+    let mut manifest = match &args.lockfile {
+        Some(path) => {
+            let mut manifest = RunManifest::new(None);
+            manifest.input = Some(manifest::describe_file(path)?);
+            manifest
+        }
+        None => RunManifest::new(Some(github::search_query(args.min_stars))),
+    };
+    manifest.repositories = discovered
+        .iter()
+        .map(|repo| {
+            let result = repository_results.iter().find(|result| result.repo_name == repo.full_name);
+            ManifestRepository {
+                full_name: repo.full_name.clone(),
+                clone_url: repo.clone_url.clone(),
+                stars: repo.stars,
+                default_branch: repo.default_branch.clone(),
+                pinned_rev: repo.pinned_rev.clone(),
+                revision: result.and_then(|result| result.revision.clone()),
+                fork: repo.fork,
+                sparse_checkout: result.is_some_and(|result| result.sparse_checkout),
+                bytes_saved: result.and_then(|result| result.bytes_saved),
+            }
+        })
+        .collect();
+    for repo_result in &repository_results {
+        for (strategy, count) in &repo_result.parse_strategies {
+            *manifest.parse_strategies.entry(*strategy).or_insert(0) += count;
+        }
+    }
+    manifest.parse_cache = parse_cache_stats;
+    for output in [&ndjson_output, &json_output, &csv_output, &parquet_output, &sqlite_output, &lockfile_output].into_iter().chain(&analysis_outputs) {
+        manifest.add_output(output)?;
+    }
+    manifest.save(args.output.join(MANIFEST_FILE)).await?;
+    
     let completion_msg = ColoredString::new(&format!(
         "✅ Analysis Complete! Processed {} repositories and found {} derive statements",
         collector_stats.successful, all_derives.len()
     )).green().bold();
     
     println!("{}", completion_msg);
-    info!("Output files: {}, {}, {}", 
-          json_output.display(), csv_output.display(), summary_output.display());
+    info!("Output files: {}, {}, {}, {}, {} (plus summary and statistics in {})", 
+          ndjson_output.display(), json_output.display(), csv_output.display(), parquet_output.display(),
+          sqlite_output.display(), args.output.display());
+    
+    Ok(())
+}
+
+/// Builds the file filter from `--include` and `--exclude` globs.
+fn file_filter(include: &[String], exclude: &[String]) -> Result<file_filter::FileFilter, Box<dyn std::error::Error>> {
+    file_filter::FileFilter::new(include, exclude).map_err(|e| {
+        let error = error_handling::AnalysisError::Configuration(format!("Invalid file glob: {}", e));
+        ErrorReporter::report_error(&error);
+        error.into()
+    })
+}
+
+/// Extends the built-in order-sensitive knowledge base with the entries in `path`.
+async fn order_sensitive(path: Option<&Path>) -> Result<OrderSensitive, Box<dyn std::error::Error>> {
+    let mut order_sensitive = OrderSensitive::builtin();
+    if let Some(path) = path {
+        let text = tokio::fs::read_to_string(path).await?;
+        if let Err(e) = order_sensitive.extend_from_str(&text) {
+            let error = error_handling::AnalysisError::Configuration(format!("Invalid order-sensitive list {}: {}", path.display(), e));
+            ErrorReporter::report_error(&error);
+            return Err(error.into());
+        }
+    }
+    Ok(order_sensitive)
+}
+
+/// Extends the built-in derive taxonomy with the mappings in `path`.
+async fn taxonomy(path: Option<&Path>) -> Result<Taxonomy, Box<dyn std::error::Error>> {
+    let mut taxonomy = Taxonomy::builtin();
+    if let Some(path) = path {
+        let text = tokio::fs::read_to_string(path).await?;
+        if let Err(e) = taxonomy.extend_from_str(&text) {
+            let error = error_handling::AnalysisError::Configuration(format!("Invalid derive family mappings {}: {}", path.display(), e));
+            ErrorReporter::report_error(&error);
+            return Err(error.into());
+        }
+    }
+    Ok(taxonomy)
+}
+
+/// Runs the summary, statistics and family stages over collected or loaded
+/// statements, and the statistics stage over `generated` statements set aside
+/// for separate reporting, returning the files written.
+async fn write_analysis(deduplication: &Deduplication, generated: &[DeriveStatement], taxonomy: &Taxonomy, output: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let summary_output = output.join("analysis_summary.json");
+    let statistics_output = output.join("analysis_statistics.json");
+    let families_output = output.join("analysis_families.json");
+    let duplication_output = output.join("duplication_report.json");
+    let derives = &deduplication.statements;
+    
+    let report = &deduplication.report;
+    info!("Found {} files shared between repositories and {} forks; {} of {} statements are copies ({:?} kept {}, weighing {:.1})",
+          report.duplicated_files, report.forks.len(), report.duplicate_statements, report.statements,
+          report.mode, report.retained_statements, report.effective_statements);
+    if report.unhashed_statements > 0 {
+        ErrorReporter::report_warning(&format!("{} statements have no content hash and can't be matched to copies",
+                                               report.unhashed_statements));
+    }
+    
+    match persistence::ResultsPersistence::save_duplication_report(report, &duplication_output).await {
+        Ok(_) => ErrorReporter::report_info("Duplication report saved successfully"),
+        Err(e) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to save duplication report: {}", e));
+            ErrorReporter::report_error(&error);
+            return Err(e);
+        }
+    }
+    
+    match persistence::ResultsPersistence::save_summary(derives, &summary_output).await {
+        Ok(_) => ErrorReporter::report_info("Summary output saved successfully"),
+        Err(e) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to save summary: {}", e));
+            ErrorReporter::report_error(&error);
+            return Err(e);
+        }
+    }
+    
+    match persistence::ResultsPersistence::save_statistics(derives, &deduplication.weights, &statistics_output).await {
+        Ok(_) => ErrorReporter::report_info("Statistics output saved successfully"),
+        Err(e) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to save statistics: {}", e));
+            ErrorReporter::report_error(&error);
+            return Err(e);
+        }
+    }
+    
+    match persistence::ResultsPersistence::save_families(derives, &deduplication.weights, taxonomy, &families_output).await {
+        Ok(_) => ErrorReporter::report_info("Derive family statistics saved successfully"),
+        Err(e) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to save derive family statistics: {}", e));
+            ErrorReporter::report_error(&error);
+            return Err(e);
+        }
+    }
+    
+    let mut outputs = vec![summary_output, statistics_output, families_output, duplication_output];
+    if !generated.is_empty() {
+        let generated_output = output.join("analysis_statistics_generated.json");
+        match persistence::ResultsPersistence::save_statistics(generated, &vec![1.0; generated.len()], &generated_output).await {
+            Ok(_) => ErrorReporter::report_info("Generated code statistics saved successfully"),
+            Err(e) => {
+                let error = error_handling::AnalysisError::Persistence(format!("Failed to save generated code statistics: {}", e));
+                ErrorReporter::report_error(&error);
+                return Err(e);
+            }
+        }
+        outputs.push(generated_output);
+    }
+    
+    Ok(outputs)
+}
+
+async fn analyze(args: AnalyzeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Starting Rust Derive Analysis Tool - offline analysis of {:?}", args.input);
+    
+    let all_derives = match persistence::ResultsPersistence::load(&args.input).await {
+        Ok(derives) => derives,
+        Err(e) => {
+            let error = error_handling::AnalysisError::Persistence(format!("Failed to load {}: {}", args.input.display(), e));
+            ErrorReporter::report_error(&error);
+            return Err(e);
+        }
+    };
+    
+    if all_derives.is_empty() {
+        ErrorReporter::report_warning("No derive statements found in input");
+        return Ok(());
+    }
+    
+    // Discovery order and forks come from the manifest of the run that produced the input, when present
+    let manifest_path = args.input.with_file_name(MANIFEST_FILE);
+    let (repository_order, forks) = if manifest_path.exists() {
+        match RunManifest::load(&manifest_path).await {
+            Ok(manifest) => (
+                manifest.repositories.iter().map(|repo| repo.full_name.clone()).collect(),
+                manifest.repositories.iter().filter(|repo| repo.fork).map(|repo| repo.full_name.clone()).collect(),
+            ),
+            Err(e) => {
+                ErrorReporter::report_warning(&format!("Ignoring unreadable manifest {}: {}", manifest_path.display(), e));
+                (Vec::new(), HashSet::new())
+            }
+        }
+    } else {
+        (Vec::new(), HashSet::new())
+    };
+    
+    tokio::fs::create_dir_all(&args.output).await?;
+    let taxonomy = taxonomy(args.derive_families.as_deref()).await?;
+    let (analysed, generated) = generated::split(all_derives.clone(), args.generated);
+    let deduplication = dedup::deduplicate(&analysed, &repository_order, &forks, args.dedup);
+    let analysis_outputs = write_analysis(&deduplication, &generated, &taxonomy, &args.output).await?;
+    
+    let mut manifest = RunManifest::new(None);
+    manifest.input = Some(manifest::describe_file(&args.input)?);
+    for output in &analysis_outputs {
+        manifest.add_output(output)?;
+    }
+    manifest.save(args.output.join(ANALYSIS_MANIFEST_FILE)).await?;
+    
+    let completion_msg = ColoredString::new(&format!(
+        "✅ Analysis Complete! Analysed {} derive statements from {}",
+        all_derives.len(), args.input.display()
+    )).green().bold();
+    
+    println!("{}", completion_msg);
+    Ok(())
+}
+
+async fn history(args: HistoryArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let repository = args.name.clone().unwrap_or_else(|| {
+        args.repository.canonicalize().unwrap_or_else(|_| args.repository.clone())
+            .file_name().unwrap_or_default().to_string_lossy().into_owned()
+    });
+    let sampling = match args.sample {
+        SampleMode::Tags => history::Sampling::Tags,
+        SampleMode::Monthly => history::Sampling::Monthly,
+        SampleMode::Commits => history::Sampling::EveryN(args.every),
+    };
+    info!("Analysing history of {} at {:?} ({:?})", repository, args.repository, sampling);
+    
+    let mut walker = history::HistoryWalker::new(&args.repository, repository, parser::RustParser::new())
+        .with_file_filter(file_filter(&args.include, &args.exclude)?);
+    let report = match walker.analyse(sampling).await {
+        Ok(report) => {
+            ErrorReporter::report_info(&format!("Analysed {} sampled commits", report.points.len()));
+            report
+        }
+        Err(e) => {
+            let error = error_handling::AnalysisError::Processing(format!("Failed to analyse history of {}: {}", args.repository.display(), e));
+            ErrorReporter::report_error(&error);
+            return Err(error.into());
+        }
+    };
+    
+    tokio::fs::create_dir_all(&args.output).await?;
+    let json_output = args.output.join("history.json");
+    let csv_output = args.output.join("history.csv");
+    persistence::ResultsPersistence::save_history_to_json(&report, &json_output).await?;
+    persistence::ResultsPersistence::save_history_to_csv(&report, &csv_output).await?;
+    
+    let mut manifest = RunManifest::new(None);
+    for output in [&json_output, &csv_output] {
+        manifest.add_output(output)?;
+    }
+    manifest.save(args.output.join(HISTORY_MANIFEST_FILE)).await?;
     
+    ErrorReporter::report_success(&format!("History saved to {} and {}", json_output.display(), csv_output.display()));
     Ok(())
 }
 
+async fn fix(args: FixArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = OrderingPolicy {
+        order_sensitive: order_sensitive(args.order_sensitive.as_deref()).await?,
+        force: args.force,
+        supertraits_first: args.supertraits_first,
+        ..OrderingPolicy::with_precedence(args.precedence.iter().cloned())
+    };
+    let filter = file_filter(&args.include, &args.exclude)?;
+    
+    let mut files = Vec::new();
+    for path in &args.paths {
+        if path.is_dir() {
+            files.extend(filter.rust_files(path)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    
+    let mut changed = 0;
+    for file in &files {
+        let source = tokio::fs::read_to_string(file).await?;
+        let edits = match derive_order::check(&source, &policy) {
+            Ok(edits) => edits,
+            Err(e) => {
+                let error = error_handling::AnalysisError::Parser(format!("Skipping {}: {}", file.display(), e));
+                ErrorReporter::report_warning(&error.to_string());
+                continue;
+            }
+        };
+        if edits.is_empty() {
+            continue;
+        }
+        
+        changed += 1;
+        if args.check {
+            println!("{}: {} derive lists out of order", file.display(), edits.len());
+        } else {
+            tokio::fs::write(file, derive_order::apply_edits(&source, &edits)).await?;
+            info!("Reordered {} derive lists in {}", edits.len(), file.display());
+        }
+    }
+    
+    if !args.check {
+        ErrorReporter::report_success(&format!("Checked {} files, reordered derives in {}", files.len(), changed));
+    } else if changed > 0 {
+        // Like `rustfmt --check`, anything to change fails the run
+        let error = error_handling::AnalysisError::Processing(format!("{} of {} files would have their derives reordered", changed, files.len()));
+        ErrorReporter::report_error(&error);
+        return Err(error.into());
+    } else {
+        ErrorReporter::report_success(&format!("Checked {} files, none would have their derives reordered", files.len()));
+    }
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::cloned_repo::Revision;
+use crate::file_filter::FileKind;
+use crate::generated;
+use crate::parse_cache::ParseCacheStats;
+use crate::parser::ParseStrategy;
+use crate::provenance;
+use crate::DeriveStatement;
+
+/// Version of the derive statement output schema.
+///
+/// 1: the original outputs, written without a manifest
+/// 2: adds `order_sensitive` and the `item_*` fields
+/// 3: adds `commit_sha`, `commit_date` and `branch`
+/// 4: adds `author_hash` and `line_commit_date`
+/// 5: adds `content_hash`
+/// 6: adds `file_kind`
+/// 7: adds `generated_by`
+/// 8: adds `package`, `edition`, `rust_version` and `proc_macro`
+/// 9: adds `origins`
+/// 10: marks origins in the repository's own proc-macro packages as `local`
+pub const SCHEMA_VERSION: u32 = 10;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Manifests of `analyze` and `history` runs, named apart so that running them
+/// in a `collect` output directory leaves its manifest in place
+pub const ANALYSIS_MANIFEST_FILE: &str = "analysis_manifest.json";
+pub const HISTORY_MANIFEST_FILE: &str = "history_manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolInfo {
+    pub name: String,
+    pub version: String,
+    pub git_sha: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestRepository {
+    pub full_name: String,
+    pub clone_url: String,
+    pub stars: u32,
+    pub default_branch: Option<String>,
+    /// Revision requested by the input lockfile
+    #[serde(default)]
+    pub pinned_rev: Option<String>,
+    /// Commit analysed; `None` if the repository could not be cloned
+    pub revision: Option<Revision>,
+    /// GitHub reports the repository as a fork
+    #[serde(default)]
+    pub fork: bool,
+    /// Only Rust sources and manifests were checked out
+    #[serde(default)]
+    pub sparse_checkout: bool,
+    /// Bytes the sparse checkout saved against GitHub's reported size, which includes
+    /// the packed history a shallow clone would not fetch either
+    #[serde(default)]
+    pub bytes_saved: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputFile {
+    /// Path relative to the manifest
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Describes one run of the tool: what produced the outputs, from which
+/// corpus, and checksums to verify them against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub schema_version: u32,
+    pub tool: ToolInfo,
+    pub created_at: String,
+    pub arguments: Vec<String>,
+    /// GitHub search query used to discover repositories
+    pub discovery_query: Option<String>,
+    /// Input lockfile, or previously saved results for `analyze` runs
+    pub input: Option<OutputFile>,
+    pub repositories: Vec<ManifestRepository>,
+    pub parse_strategies: BTreeMap<ParseStrategy, usize>,
+    /// Files served from the parse cache, when it was enabled
+    #[serde(default)]
+    pub parse_cache: Option<ParseCacheStats>,
+    pub outputs: Vec<OutputFile>,
+}
+
+impl RunManifest {
+    pub fn new(discovery_query: Option<String>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            tool: ToolInfo {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                git_sha: env!("GIT_SHA").to_string(),
+            },
+            created_at: chrono::Utc::now().to_rfc3339(),
+            arguments: std::env::args().collect(),
+            discovery_query,
+            input: None,
+            repositories: Vec::new(),
+            parse_strategies: BTreeMap::new(),
+            parse_cache: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Records `path` as an output of this run. Files that were not written are skipped.
+    pub fn add_output<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        if path.as_ref().exists() {
+            self.outputs.push(describe_file(path.as_ref())?);
+        }
+        Ok(())
+    }
+
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let manifest_json = serde_json::to_string_pretty(self)?;
+        let mut file = File::create(path.as_ref()).await?;
+        file.write_all(manifest_json.as_bytes()).await?;
+
+        info!("Saved run manifest to {}", path.as_ref().display());
+        Ok(())
+    }
+
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let manifest_json = tokio::fs::read_to_string(path.as_ref()).await?;
+        Ok(serde_json::from_str(&manifest_json)?)
+    }
+}
+
+pub fn describe_file(path: &Path) -> std::io::Result<OutputFile> {
+    let mut hasher = Sha256::new();
+    let mut file = std::fs::File::open(path)?;
+    let bytes = std::io::copy(&mut file, &mut hasher)?;
+
+    Ok(OutputFile {
+        path: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        bytes,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+/// Statement fields in the order schema versions added them, with the version
+/// that added each
+const VERSIONED_FIELDS: &[(&str, u32)] = &[
+    ("order_sensitive", 2),
+    ("commit_sha", 3),
+    ("author_hash", 4),
+    ("content_hash", 5),
+    ("file_kind", 6),
+    ("generated_by", 7),
+    ("package", 8),
+    ("origins", 9),
+];
+
+/// Schema version of a JSON, NDJSON or CSV output, judged from the field names
+/// or columns its statements carry, since these formats record no version of
+/// their own: the newest version whose fields, and every earlier version's,
+/// are all present. Version 10 only changed the values of `origins`, so files
+/// with every field count as current.
+pub fn schema_version_of_fields<'a>(fields: impl IntoIterator<Item = &'a str>) -> u32 {
+    let fields: Vec<&str> = fields.into_iter().collect();
+    let missing = VERSIONED_FIELDS.iter().find(|(field, _)| !fields.contains(field));
+    match missing {
+        Some((_, version)) => version - 1,
+        None => SCHEMA_VERSION,
+    }
+}
+
+/// Brings statements loaded from an older schema up to [`SCHEMA_VERSION`].
+pub fn migrate(derives: &mut [DeriveStatement], from_version: u32) {
+    if from_version < 2 {
+        // Version 1 had no order-sensitivity flags; derive them from the built-in knowledge base
+        let order_sensitive = derive_order::OrderSensitive::builtin();
+        for derive in derives.iter_mut().filter(|derive| derive.order_sensitive.is_empty()) {
+            derive.order_sensitive = order_sensitive
+                .matching(&derive.derives)
+                .into_iter()
+                .map(String::from)
+                .collect();
+        }
+    }
+
+    if from_version < 6 {
+        // File kinds are judged from paths alone, so older statements can be classified afresh
+        for derive in derives.iter_mut().filter(|derive| derive.file_kind.is_none()) {
+            derive.file_kind = Some(FileKind::classify(&derive.file_path));
+        }
+    }
+
+    if from_version < 7 {
+        // Content markers can't be recovered, but generated paths still can
+        for derive in derives.iter_mut().filter(|derive| derive.generated_by.is_none()) {
+            derive.generated_by = generated::path_generator(&derive.file_path).map(String::from);
+        }
+    }
+
+    if from_version < 9 {
+        // Without the manifests, only standard derives and paths resolve
+        for derive in derives.iter_mut().filter(|derive| derive.origins.is_empty()) {
+            derive.origins = provenance::resolve_all(&derive.derives, None, None);
+        }
+    }
+
+    if from_version < SCHEMA_VERSION {
+        info!("Migrated {} derive statements from schema version {} to {}",
+              derives.len(), from_version, SCHEMA_VERSION);
+    }
+}
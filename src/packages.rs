@@ -0,0 +1,481 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use toml::{Table, Value};
+use tracing::warn;
+
+use crate::file_filter::working_tree_walker;
+use crate::provenance;
+use crate::DeriveStatement;
+
+pub const CARGO_MANIFEST: &str = "Cargo.toml";
+
+/// Edition cargo assumes for packages that don't name one
+const DEFAULT_EDITION: &str = "2015";
+
+/// Dependency tables whose crates can supply derive macros to the package's
+/// code; build dependencies only reach the build script.
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "dev_dependencies"];
+
+/// A Cargo package of a repository, with the fields a workspace may supply
+/// already inherited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Package {
+    pub name: String,
+    /// Directory of the package's manifest relative to the repository root, empty for the root
+    pub root: String,
+    pub edition: String,
+    /// Minimum supported Rust version, from `rust-version`
+    pub rust_version: Option<String>,
+    pub proc_macro: bool,
+    /// Dependencies by the name the package's code uses for them, with `-` as `_`
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+    /// Derives a proc-macro package defines, once its sources are recorded
+    #[serde(default)]
+    pub exported_derives: Vec<String>,
+}
+
+/// A dependency of a package, with workspace inheritance applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dependency {
+    /// Name of the crate depended on, which may differ from the name it is used under
+    pub package: String,
+    /// Version requirement as written; `None` for path and git dependencies without one
+    pub version: Option<String>,
+    pub default_features: bool,
+    /// Features enabled by the dependency itself or by any of the package's features
+    pub features: Vec<String>,
+    /// Directory of a path dependency relative to the tree's root
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// The workspace a package belongs to.
+struct Workspace<'a> {
+    /// Directory of the workspace's manifest relative to the tree's root
+    root: String,
+    table: &'a Table,
+}
+
+/// The packages of one tree, used to attribute each file to the package whose
+/// manifest is in its nearest enclosing directory, as cargo does.
+#[derive(Debug, Clone, Default)]
+pub struct PackageIndex {
+    packages: BTreeMap<String, Package>,
+}
+
+impl PackageIndex {
+    /// Builds the index from `(path, content)` of every `Cargo.toml` in a
+    /// tree, with paths relative to its root. Unparseable manifests are
+    /// skipped, leaving their files to an enclosing package if there is one.
+    pub fn from_manifests<'a>(manifests: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut tables: BTreeMap<String, Table> = BTreeMap::new();
+        for (path, content) in manifests {
+            match content.parse::<Table>() {
+                Ok(table) => {
+                    tables.insert(parent_directory(path).to_string(), table);
+                }
+                Err(e) => warn!("Skipping unparseable manifest {}: {}", path, e),
+            }
+        }
+
+        let packages = tables
+            .iter()
+            .filter_map(|(root, table)| {
+                // `[project]` is the pre-1.0 spelling of `[package]`
+                let package = table.get("package").or_else(|| table.get("project"))?.as_table()?;
+                let name = package.get("name")?.as_str()?.to_string();
+                let workspace = workspace_of(&tables, root, package);
+                let field = |key: &str| inherited(package, key, workspace.as_ref()).and_then(Value::as_str).map(String::from);
+                let lib = table.get("lib").and_then(Value::as_table);
+                // `proc_macro` is an old alias cargo still accepts
+                let proc_macro = lib
+                    .and_then(|lib| lib.get("proc-macro").or_else(|| lib.get("proc_macro")))
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+
+                let package = Package {
+                    name,
+                    root: root.clone(),
+                    edition: field("edition").unwrap_or_else(|| DEFAULT_EDITION.to_string()),
+                    rust_version: field("rust-version"),
+                    proc_macro,
+                    dependencies: dependencies(table, root, workspace.as_ref()),
+                    exported_derives: Vec::new(),
+                };
+                Some((root.clone(), package))
+            })
+            .collect();
+        Self { packages }
+    }
+
+    /// Reads every `Cargo.toml` of a working tree that its `.gitignore` files
+    /// don't ignore, and the sources of its proc-macro packages.
+    pub fn from_working_tree(root: &Path) -> Result<Self, ignore::Error> {
+        let mut manifests = Vec::new();
+        let mut sources = Vec::new();
+        for entry in working_tree_walker(root) {
+            let entry = entry?;
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path()).to_string_lossy().into_owned();
+            if entry.path().extension().is_some_and(|extension| extension == "rs") {
+                sources.push(relative);
+                continue;
+            }
+            if entry.file_name() != CARGO_MANIFEST {
+                continue;
+            }
+            match std::fs::read_to_string(entry.path()) {
+                Ok(content) => manifests.push((relative, content)),
+                Err(e) => warn!("Failed to read {}: {}", entry.path().display(), e),
+            }
+        }
+        let mut index = Self::from_manifests(manifests.iter().map(|(path, content)| (path.as_str(), content.as_str())));
+
+        // Derive names of bare derives can only be traced to a local proc-macro crate through its sources
+        for relative in sources {
+            if !index.package_of(&relative).is_some_and(|package| package.proc_macro) {
+                continue;
+            }
+            if let Ok(content) = std::fs::read_to_string(root.join(&relative)) {
+                index.record_source(&relative, &content);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Notes the derives a proc-macro package's source file at `path` defines
+    /// with `#[proc_macro_derive(Name)]`.
+    pub fn record_source(&mut self, path: &str, content: &str) {
+        let Some(root) = self.package_of(path).filter(|package| package.proc_macro).map(|package| package.root.clone()) else {
+            return;
+        };
+        let Some(package) = self.packages.get_mut(&root) else { return };
+        for (index, attribute) in content.match_indices("proc_macro_derive") {
+            let rest = &content[index + attribute.len()..];
+            let Some(arguments) = rest.trim_start().strip_prefix('(') else { continue };
+            let name: String = arguments.trim_start().chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+            if !name.is_empty() && !package.exported_derives.contains(&name) {
+                package.exported_derives.push(name);
+            }
+        }
+    }
+
+    /// The proc-macro package of this tree that `dependency` refers to by path.
+    pub fn local_proc_macro(&self, dependency: &Dependency) -> Option<&Package> {
+        self.packages.get(dependency.path.as_deref()?).filter(|package| package.proc_macro)
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    /// The package owning the file at `path`, relative to the tree's root.
+    pub fn package_of(&self, path: &str) -> Option<&Package> {
+        let mut directory = parent_directory(path);
+        loop {
+            if let Some(package) = self.packages.get(directory) {
+                return Some(package);
+            }
+            if directory.is_empty() {
+                return None;
+            }
+            directory = parent_directory(directory);
+        }
+    }
+
+    /// Records each statement's package and the crates its derives come
+    /// from, returning how many statements were attributed to a package.
+    pub fn attribute(&self, derives: &mut [DeriveStatement]) -> usize {
+        let mut attributed = 0;
+        for derive in derives.iter_mut() {
+            let package = self.package_of(&derive.file_path);
+            derive.origins = provenance::resolve_all(&derive.derives, package, Some(self));
+            if let Some(package) = package {
+                derive.package = Some(package.name.clone());
+                derive.edition = Some(package.edition.clone());
+                derive.rust_version = package.rust_version.clone();
+                derive.proc_macro = Some(package.proc_macro);
+                attributed += 1;
+            }
+        }
+        attributed
+    }
+}
+
+/// Everything before the last `/`, or the empty string at the root.
+fn parent_directory(path: &str) -> &str {
+    path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or_default()
+}
+
+/// The workspace a package belongs to: the one at `package.workspace` when
+/// given, otherwise the nearest enclosing manifest with a `[workspace]` table,
+/// provided the package is one of its members.
+fn workspace_of<'a>(tables: &'a BTreeMap<String, Table>, root: &str, package: &Table) -> Option<Workspace<'a>> {
+    let workspace = |directory: &str| {
+        let table = tables.get(directory)?.get("workspace")?.as_table()?;
+        Some(Workspace { root: directory.to_string(), table })
+    };
+
+    if let Some(path) = package.get("workspace").and_then(Value::as_str) {
+        return workspace(&normalise(&format!("{}/{}", root, path)));
+    }
+
+    let mut directory = root;
+    loop {
+        if let Some(workspace) = workspace(directory) {
+            return is_member(tables, &workspace, root).then_some(workspace);
+        }
+        if directory.is_empty() {
+            return None;
+        }
+        directory = parent_directory(directory);
+    }
+}
+
+/// Whether the package at `root` is a member of `workspace`, as cargo decides
+/// it: the workspace's own package, packages matching a `members` glob, and
+/// packages that members depend on by path unless `exclude` names them.
+fn is_member(tables: &BTreeMap<String, Table>, workspace: &Workspace, root: &str) -> bool {
+    let relative = |root: &str| {
+        let relative = root.strip_prefix(workspace.root.as_str())?;
+        (workspace.root.is_empty() || relative.is_empty() || relative.starts_with('/')).then(|| relative.trim_start_matches('/').to_string())
+    };
+    let paths = |key: &str| workspace.table.get(key).and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str).map(normalise);
+    let mut members = GlobSetBuilder::new();
+    for pattern in paths("members") {
+        match GlobBuilder::new(&pattern).literal_separator(true).build() {
+            Ok(glob) => {
+                members.add(glob);
+            }
+            Err(e) => warn!("Skipping workspace member pattern {}: {}", pattern, e),
+        }
+    }
+    let members = members.build().unwrap_or_else(|_| GlobSet::empty());
+    let explicit_member = |root: &str| relative(root).is_some_and(|relative| relative.is_empty() || members.is_match(&relative));
+
+    if explicit_member(root) {
+        return true;
+    }
+    let Some(package) = relative(root) else { return false };
+    let excluded = paths("exclude").any(|path| package == path || package.starts_with(&format!("{}/", path)));
+    if excluded {
+        return false;
+    }
+
+    tables
+        .iter()
+        .filter(|(directory, _)| explicit_member(directory))
+        .any(|(directory, table)| {
+            dependencies(table, directory, Some(workspace)).values().any(|dependency| dependency.path.as_deref() == Some(root))
+        })
+}
+
+/// A package field, following `key.workspace = true` to `[workspace.package]`.
+fn inherited<'a>(package: &'a Table, key: &str, workspace: Option<&Workspace<'a>>) -> Option<&'a Value> {
+    let value = package.get(key)?;
+    let inherits = value.as_table().and_then(|table| table.get("workspace")).and_then(Value::as_bool) == Some(true);
+    if !inherits {
+        return Some(value);
+    }
+    workspace?.table.get("package")?.as_table()?.get(key)
+}
+
+/// Collects a manifest's dependencies, including target-specific ones, and
+/// the dependency features its `[features]` table enables.
+fn dependencies(table: &Table, root: &str, workspace: Option<&Workspace>) -> BTreeMap<String, Dependency> {
+    let targets = table.get("target").and_then(Value::as_table);
+    let tables = std::iter::once(table)
+        .chain(targets.into_iter().flat_map(|targets| targets.values().filter_map(Value::as_table)))
+        .flat_map(|table| DEPENDENCY_TABLES.iter().filter_map(|key| table.get(*key)?.as_table()));
+    let inherited_dependencies = workspace
+        .and_then(|workspace| workspace.table.get("dependencies"))
+        .and_then(Value::as_table);
+
+    let mut dependencies: BTreeMap<String, Dependency> = BTreeMap::new();
+    let mut keys: BTreeMap<&str, String> = BTreeMap::new();
+    for dependency_table in tables {
+        for (key, spec) in dependency_table {
+            let inherits = spec.get("workspace").and_then(Value::as_bool) == Some(true);
+            let base = if inherits { inherited_dependencies.and_then(|table| table.get(key)) } else { None };
+            let lookup = |field: &str| spec.get(field).or_else(|| base.and_then(|base| base.get(field)));
+
+            let version = match (spec, base) {
+                (Value::String(version), _) | (_, Some(Value::String(version))) => Some(version.clone()),
+                _ => lookup("version").and_then(Value::as_str).map(String::from),
+            };
+            let mut features: Vec<String> = [spec.get("features"), base.and_then(|base| base.get("features"))]
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_array)
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect();
+            // `default_features` is the older spelling, rejected by cargo since edition 2024
+            let default_features = lookup("default-features").or_else(|| lookup("default_features"))
+                .and_then(Value::as_bool)
+                .unwrap_or(true);
+            // Paths are relative to the manifest that gives them
+            let path = match (spec.get("path"), base.and_then(|base| base.get("path")), workspace) {
+                (Some(path), _, _) => path.as_str().map(|path| normalise(&format!("{}/{}", root, path))),
+                (None, Some(path), Some(workspace)) => path.as_str().map(|path| normalise(&format!("{}/{}", workspace.root, path))),
+                _ => None,
+            };
+            let name = key.replace('-', "_");
+
+            // The same crate may appear in several tables; what they enable adds up
+            let dependency = dependencies.entry(name.clone()).or_insert_with(|| Dependency {
+                package: lookup("package").and_then(Value::as_str).unwrap_or(key).to_string(),
+                version,
+                default_features: false,
+                features: Vec::new(),
+                path,
+            });
+            dependency.default_features |= default_features;
+            dependency.features.append(&mut features);
+            keys.insert(key, name);
+        }
+    }
+
+    // `name/feature` and `name?/feature` enable a dependency's features from the package's own
+    let package_features = table.get("features").and_then(Value::as_table);
+    for enabled in package_features.into_iter().flat_map(|features| features.values()).filter_map(Value::as_array).flatten() {
+        let Some((key, feature)) = enabled.as_str().and_then(|enabled| enabled.split_once('/')) else { continue };
+        let dependency = keys.get(key.trim_end_matches('?')).and_then(|name| dependencies.get_mut(name));
+        if let Some(dependency) = dependency {
+            dependency.features.push(feature.to_string());
+        }
+    }
+
+    for dependency in dependencies.values_mut() {
+        dependency.features.sort();
+        dependency.features.dedup();
+    }
+    dependencies
+}
+
+/// Resolves `.` and `..` components of a relative path.
+fn normalise(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes_files_to_workspace_members() {
+        let manifests = [
+            ("Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"tools/standalone\"]\n\n[workspace.package]\nedition = \"2021\"\nrust-version = \"1.70\"\n"),
+            ("crates/core/Cargo.toml", "[package]\nname = \"core\"\nedition.workspace = true\nrust-version.workspace = true\n"),
+            ("crates/derive/Cargo.toml", "[package]\nname = \"core-derive\"\nedition = \"2018\"\n\n[lib]\nproc-macro = true\n"),
+            ("crates/derive/tests/fixtures/Cargo.toml", "not [valid toml"),
+            ("tools/standalone/Cargo.toml", "[package]\nname = \"standalone\"\nedition = { workspace = true }\n"),
+        ];
+        let index = PackageIndex::from_manifests(manifests);
+        assert_eq!(index.len(), 3);
+
+        let core = index.package_of("crates/core/src/model/mod.rs").unwrap();
+        assert_eq!((core.name.as_str(), core.edition.as_str(), core.rust_version.as_deref()), ("core", "2021", Some("1.70")));
+        assert!(!core.proc_macro);
+
+        let derive = index.package_of("crates/derive/tests/fixtures/src/lib.rs").unwrap();
+        assert_eq!((derive.name.as_str(), derive.edition.as_str(), derive.proc_macro), ("core-derive", "2018", true));
+
+        // Excluded from the workspace, so there is nothing to inherit
+        let standalone = index.package_of("tools/standalone/src/main.rs").unwrap();
+        assert_eq!(standalone.edition, DEFAULT_EDITION);
+
+        // The root manifest is virtual, so files outside the members have no package
+        assert!(index.package_of("examples/demo.rs").is_none());
+    }
+
+    #[test]
+    fn test_workspace_members() {
+        let manifests = [
+            ("Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.package]\nedition = \"2021\"\n"),
+            ("crates/core/Cargo.toml", "[package]\nname = \"core\"\nedition.workspace = true\n\n[dependencies]\nhelper = { path = \"../../vendor/helper\" }\n"),
+            ("crates/core/fuzz/Cargo.toml", "[package]\nname = \"core-fuzz\"\nedition.workspace = true\n"),
+            ("vendor/helper/Cargo.toml", "[package]\nname = \"helper\"\nedition.workspace = true\n"),
+            ("examples/demo/Cargo.toml", "[package]\nname = \"demo\"\nedition.workspace = true\n"),
+        ];
+        let index = PackageIndex::from_manifests(manifests);
+        let edition = |path: &str| index.package_of(path).unwrap().edition.clone();
+
+        assert_eq!(edition("crates/core/src/lib.rs"), "2021");
+        // Path dependencies of members are members too
+        assert_eq!(edition("vendor/helper/src/lib.rs"), "2021");
+        // `*` doesn't cross directories, and other nested packages are outside the workspace
+        assert_eq!(edition("crates/core/fuzz/src/main.rs"), DEFAULT_EDITION);
+        assert_eq!(edition("examples/demo/src/main.rs"), DEFAULT_EDITION);
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let manifests = [
+            ("Cargo.toml", "[workspace]\nmembers = [\"app\"]\n\n[workspace.dependencies]\nserde = { version = \"1.0.197\", default-features = false }\n"),
+            ("app/Cargo.toml", concat!(
+                "[package]\nname = \"app\"\n\n",
+                "[dependencies]\nserde = { workspace = true, features = [\"std\"] }\nclap = \"4\"\n",
+                "strum-crate = { package = \"strum\", version = \"0.26\", optional = true }\n\n",
+                "[target.'cfg(unix)'.dev-dependencies]\nlocal-derive = { path = \"../local-derive\" }\n\n",
+                "[build-dependencies]\ncc = \"1\"\n\n",
+                "[features]\nserde = [\"serde/derive\"]\nenum-names = [\"strum-crate?/derive\"]\n",
+            )),
+        ];
+        let index = PackageIndex::from_manifests(manifests);
+        let dependencies = &index.package_of("app/src/main.rs").unwrap().dependencies;
+        assert_eq!(dependencies.keys().collect::<Vec<_>>(), vec!["clap", "local_derive", "serde", "strum_crate"]);
+
+        let serde = &dependencies["serde"];
+        assert_eq!((serde.version.as_deref(), serde.default_features), (Some("1.0.197"), false));
+        assert_eq!(serde.features, vec!["derive", "std"]);
+        assert_eq!(dependencies["clap"].version.as_deref(), Some("4"));
+
+        let strum = &dependencies["strum_crate"];
+        assert_eq!((strum.package.as_str(), strum.features.as_slice()), ("strum", ["derive".to_string()].as_slice()));
+        assert_eq!(dependencies["local_derive"].version, None);
+        assert_eq!(dependencies["local_derive"].path.as_deref(), Some("local-derive"));
+    }
+
+    #[test]
+    fn test_local_proc_macros() {
+        let manifests = [
+            ("Cargo.toml", "[workspace]\nmembers = [\"app\", \"macros\"]\n\n[workspace.dependencies]\napp-macros = { path = \"macros\", version = \"0.1\" }\n"),
+            ("app/Cargo.toml", "[package]\nname = \"app\"\n\n[dependencies]\napp-macros.workspace = true\nserde = { version = \"1\", features = [\"derive\"] }\n"),
+            ("macros/Cargo.toml", "[package]\nname = \"app-macros\"\n\n[lib]\nproc-macro = true\n"),
+        ];
+        let mut index = PackageIndex::from_manifests(manifests);
+        index.record_source("macros/src/lib.rs", "#[proc_macro_derive(Register, attributes(register))]\npub fn register(input: TokenStream) -> TokenStream { input }\n");
+        // Sources of other packages define nothing for them
+        index.record_source("app/src/lib.rs", "#[proc_macro_derive(Stray)]\n");
+        assert_eq!(index.package_of("macros/src/lib.rs").unwrap().exported_derives, vec!["Register"]);
+
+        let mut derives = vec![DeriveStatement {
+            repository: "owner/app".to_string(),
+            file_path: "app/src/model.rs".to_string(),
+            line_number: 1,
+            derives: vec!["Debug".to_string(), "Serialize".to_string(), "Register".to_string(), "app_macros::Audit".to_string()],
+            full_line: "#[derive(Debug, Serialize, Register, app_macros::Audit)]".to_string(),
+            ..DeriveStatement::default()
+        }];
+        index.attribute(&mut derives);
+        let origins: Vec<String> = derives[0].origins.iter().map(ToString::to_string).collect();
+        assert_eq!(origins, vec!["Debug=std", "Serialize=serde@1", "Register=app-macros+local@0.1", "app_macros::Audit=app-macros+local@0.1"]);
+    }
+}
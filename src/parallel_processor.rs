@@ -1,9 +1,18 @@
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
-use std::path::PathBuf;
 use leabharlann_processing::*;
 use tracing::{info, warn};
 
-use crate::{RepositoryInfo, DeriveStatement, parser::RustParser, repo_cache::{RepositoryCache, CacheConfig}, persistence::ResultsPersistence};
+use crate::{RepositoryInfo, DeriveStatement, parser::{ParseStrategy, RustParser}, repo_cache::RepositoryCache, persistence::NdjsonSink};
+use crate::blame::Blamer;
+use crate::cloned_repo::{ClonedRepository, Revision};
+use crate::git_backend::is_bare_repository;
+use crate::git_objects::{BlobExtractor, TreeExtraction};
+use crate::dedup::content_hash;
+use crate::file_filter::{FileFilter, FileKind};
+use crate::generated;
+use crate::packages::PackageIndex;
+use crate::parse_cache::ParseCache;
 
 #[derive(Debug, Clone)]
 pub struct RepositoryTask {
@@ -15,6 +24,15 @@ pub struct RepositoryResult {
     pub repo_name: String,
     pub derive_statements: Vec<DeriveStatement>,
     pub rust_files_processed: usize,
+    /// Commit of the analysed checkout
+    pub revision: Option<Revision>,
+    /// Number of files extracted with each parse strategy
+    pub parse_strategies: BTreeMap<ParseStrategy, usize>,
+    /// Only Rust sources and manifests were checked out
+    pub sparse_checkout: bool,
+    /// Bytes the sparse checkout saved against a full clone, when GitHub reported the
+    /// repository's size; see [`CacheEntry::bytes_saved`](crate::cache_index::CacheEntry::bytes_saved)
+    pub bytes_saved: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -36,30 +54,75 @@ impl std::error::Error for ProcessingError {}
 
 #[derive(Clone)]
 pub struct RepositoryProcessor {
-    cache_config: CacheConfig,
+    cache: Arc<RepositoryCache>,
     parser: RustParser,
     results_storage: Arc<Mutex<Vec<RepositoryResult>>>,
-    output_dir: PathBuf,
+    sink: Arc<Mutex<NdjsonSink>>,
+    blamer: Option<Blamer>,
+    parse_cache: Option<Arc<ParseCache>>,
+    file_filter: FileFilter,
 }
 
 impl RepositoryProcessor {
-    pub fn new(cache_config: CacheConfig, results_storage: Arc<Mutex<Vec<RepositoryResult>>>, output_dir: PathBuf) -> Self {
+    pub fn new(cache: Arc<RepositoryCache>, parser: RustParser, results_storage: Arc<Mutex<Vec<RepositoryResult>>>, sink: Arc<Mutex<NdjsonSink>>) -> Self {
         Self {
-            cache_config,
-            parser: RustParser::new(),
+            cache,
+            parser,
             results_storage,
-            output_dir,
+            sink,
+            blamer: None,
+            parse_cache: None,
+            file_filter: FileFilter::default(),
         }
     }
 
-    fn process_file_safely(&self, content: &str, repository: &str, file_path: &str) -> Result<Vec<DeriveStatement>, ProcessingError> {
+    /// Attributes each statement's line to its author; the cache must keep full history.
+    pub fn with_blame(mut self, blamer: Blamer) -> Self {
+        self.blamer = Some(blamer);
+        self
+    }
+
+    /// Chooses the files analysed in each repository, instead of the default exclusions.
+    pub fn with_file_filter(mut self, file_filter: FileFilter) -> Self {
+        self.file_filter = file_filter;
+        self
+    }
+
+    /// Reuses statements already extracted from identical file contents.
+    pub fn with_parse_cache(mut self, parse_cache: Arc<ParseCache>) -> Self {
+        self.parse_cache = Some(parse_cache);
+        self
+    }
+
+    fn process_file_safely(&self, content: &str, repository: &str, file_path: &str) -> Result<(Vec<DeriveStatement>, ParseStrategy), ProcessingError> {
         // For files that are likely to cause issues, use text-based parsing only
-        if self.should_use_text_only_parsing(content, file_path) {
-            return Ok(self.parser.extract_derives_text_only(content, repository, file_path));
-        }
+        let text_only = self.should_use_text_only_parsing(content, file_path);
+        let parse = || {
+            if text_only {
+                return (self.parser.extract_derives_text_only(content, repository, file_path), ParseStrategy::TextOnly);
+            }
 
-        // For normal files, use the standard parser with fallback
-        Ok(self.parser.extract_derives(content, repository, file_path))
+            // For normal files, use the standard parser with fallback
+            self.parser.extract_derives_with_strategy(content, repository, file_path)
+        };
+
+        let content_hash = content_hash(content);
+        let (mut derives, strategy) = match &self.parse_cache {
+            // The text-only decision depends on the path, so it is part of the key
+            Some(parse_cache) => {
+                let parser = format!("{}{}", self.parser.fingerprint(), if text_only { "-text" } else { "" });
+                parse_cache.get_or_parse(&content_hash, &parser, repository, file_path, parse)
+            }
+            None => parse(),
+        };
+
+        // Identical contents in other repositories are found by this hash before analysis
+        let generated_by = generated::detect(file_path, content);
+        for derive in &mut derives {
+            derive.content_hash = Some(content_hash.clone());
+            derive.generated_by = generated_by.map(String::from);
+        }
+        Ok((derives, strategy))
     }
 
     fn should_use_text_only_parsing(&self, content: &str, file_path: &str) -> bool {
@@ -99,62 +162,25 @@ impl RepositoryProcessor {
     }
 }
 
-impl Processor<RepositoryTask, RepositoryResult, ProcessingError> for RepositoryProcessor {
-    fn process(&self, task: RepositoryTask) -> Result<RepositoryResult, ProcessingError> {
-        let repo = &task.repo_info;
-        info!("Processing repository: {}", repo.full_name);
-
-        // Create a thread-local cache for this repository
-        let mut cache = RepositoryCache::new(self.cache_config.clone());
-        
-        // Clone the repository
-        let repo_path = match tokio::runtime::Runtime::new() {
-            Ok(rt) => rt.block_on(cache.ensure_repository(repo))
-                .map_err(|e| ProcessingError::CloneError(format!("Failed to clone {}: {}", repo.full_name, e)))?,
-            Err(e) => return Err(ProcessingError::CloneError(format!("Failed to create tokio runtime: {}", e))),
-        };
-
-        // Find all Rust files
-        let rust_files = cache.find_rust_files(&repo_path)
-            .map_err(|e| ProcessingError::FileAccessError(format!("Failed to find Rust files in {}: {}", repo.full_name, e)))?;
-
-        if rust_files.is_empty() {
-            info!("No Rust files found in {}, skipping", repo.full_name);
-            return Ok(RepositoryResult {
-                repo_name: repo.full_name.clone(),
-                derive_statements: Vec::new(),
-                rust_files_processed: 0,
-            });
-        }
-
-        info!("Found {} Rust files in {}", rust_files.len(), repo.full_name);
+impl RepositoryProcessor {
+    /// Reads and parses every Rust file of a checkout's working tree.
+    fn extract_from_working_tree(&self, cloned: &ClonedRepository) -> TreeExtraction {
+        info!("Found {} Rust files in {}", cloned.rust_files.len(), cloned.full_name);
 
         // Process all files in this repository
-        let mut all_derives = Vec::new();
-        let mut files_processed = 0;
-
-        for rust_file in &rust_files {
+        let mut extraction = TreeExtraction::default();
+        for rust_file in &cloned.rust_files {
             match std::fs::read_to_string(rust_file) {
                 Ok(content) => {
                     // Convert absolute path to relative path for reporting
-                    let relative_path = rust_file.strip_prefix(&repo_path)
+                    let relative_path = rust_file.strip_prefix(&cloned.local_path)
                         .unwrap_or(rust_file)
                         .to_string_lossy();
 
-                    // Process each file safely with timeout and error isolation
-                    match self.process_file_safely(&content, &repo.full_name, &relative_path) {
-                        Ok(derives) => {
-                            if !derives.is_empty() {
-                                info!("Found {} derive statements in {}/{}", 
-                                      derives.len(), repo.full_name, relative_path);
-                                all_derives.extend(derives);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Failed to process file {}/{}: {}", repo.full_name, relative_path, e);
-                        }
-                    }
-                    files_processed += 1;
+                    let (derives, strategy) = self.parse_blob(&content, &cloned.full_name, &relative_path);
+                    *extraction.parse_strategies.entry(strategy).or_insert(0) += 1;
+                    extraction.statements.extend(derives);
+                    extraction.rust_files += 1;
                 }
                 Err(e) => {
                     warn!("Failed to read {}: {}", rust_file.display(), e);
@@ -162,40 +188,130 @@ impl Processor<RepositoryTask, RepositoryResult, ProcessingError> for Repository
             }
         }
 
+        match PackageIndex::from_working_tree(&cloned.local_path) {
+            Ok(packages) => {
+                let attributed = packages.attribute(&mut extraction.statements);
+                info!("Attributed {} of {} derive statements in {} to {} packages",
+                      attributed, extraction.statements.len(), cloned.full_name, packages.len());
+            }
+            Err(e) => warn!("Failed to find the Cargo manifests of {}: {}", cloned.full_name, e),
+        }
+        extraction
+    }
+
+    /// Parses one file's content, isolating failures to that file.
+    fn parse_blob(&self, content: &str, repository: &str, file_path: &str) -> (Vec<DeriveStatement>, ParseStrategy) {
+        // Process each file safely with timeout and error isolation
+        match self.process_file_safely(content, repository, file_path) {
+            Ok((derives, strategy)) => {
+                if !derives.is_empty() {
+                    info!("Found {} derive statements in {}/{}", derives.len(), repository, file_path);
+                }
+                (derives, strategy)
+            }
+            Err(e) => {
+                warn!("Failed to process file {}/{}: {}", repository, file_path, e);
+                (Vec::new(), ParseStrategy::TextOnly)
+            }
+        }
+    }
+}
+
+impl Processor<RepositoryTask, RepositoryResult, ProcessingError> for RepositoryProcessor {
+    fn process(&self, task: RepositoryTask) -> Result<RepositoryResult, ProcessingError> {
+        let repo = &task.repo_info;
+        info!("Processing repository: {}", repo.full_name);
+
+        // Check the repository out of the shared cache; the lease keeps it from eviction until processed
+        let lease = self.cache.ensure_repository(repo)
+            .map_err(|e| ProcessingError::CloneError(format!("Failed to clone {}: {}", repo.full_name, e)))?;
+        let repo_path = lease.path().to_path_buf();
+        let sparse_checkout = lease.entry().is_some_and(|entry| entry.sparse);
+        let bytes_saved = lease.entry().and_then(|entry| entry.bytes_saved);
+        let revision = match self.cache.head_revision(&repo_path) {
+            Ok(revision) => {
+                info!("Analysing {} at {} ({})", repo.full_name, revision.commit_sha,
+                      revision.branch.as_deref().unwrap_or("detached HEAD"));
+                Some(revision)
+            }
+            Err(e) => {
+                warn!("Could not determine the analysed commit of {}: {}", repo.full_name, e);
+                None
+            }
+        };
+
+        let rt = tokio::runtime::Runtime::new()
+            .map_err(|e| ProcessingError::FileAccessError(format!("Failed to create tokio runtime: {}", e)))?;
+
+        let extraction = if is_bare_repository(&repo_path) {
+            // Bare clones have no working tree, so files are read straight from the object database
+            let mut extractor = BlobExtractor::new(&repo_path, repo.full_name.clone()).with_filter(self.file_filter.clone());
+            rt.block_on(extractor.extract_at("HEAD", |content, repository, path| self.parse_blob(content, repository, path)))
+                .map_err(|e| ProcessingError::FileAccessError(format!("Failed to read Rust files of {}: {}", repo.full_name, e)))?
+        } else {
+            // Find all Rust files
+            let rust_files = self.file_filter.rust_files(&repo_path)
+                .map_err(|e| ProcessingError::FileAccessError(format!("Failed to find Rust files in {}: {}", repo.full_name, e)))?;
+            let cloned = ClonedRepository {
+                repo_name: repo.name.clone(),
+                full_name: repo.full_name.clone(),
+                local_path: repo_path.clone(),
+                rust_files,
+                revision: revision.clone(),
+            };
+            self.extract_from_working_tree(&cloned)
+        };
+
+        // Still stored below, so the lockfile and manifest pin the commit that had no Rust files
+        if extraction.rust_files == 0 {
+            info!("No Rust files found in {}", repo.full_name);
+        }
+
+        let TreeExtraction { rust_files: files_processed, statements: mut all_derives, parse_strategies } = extraction;
         info!("Finished processing {} ({} files, {} derive statements)", 
               repo.full_name, files_processed, all_derives.len());
 
+        // Tie every statement to the commit it was extracted from
+        if let Some(revision) = &revision {
+            for derive in &mut all_derives {
+                derive.commit_sha = Some(revision.commit_sha.clone());
+                derive.commit_date = revision.commit_date.clone();
+                derive.branch = revision.branch.clone();
+            }
+        }
+        for derive in &mut all_derives {
+            derive.file_kind = Some(FileKind::of(&derive.file_path, derive.generated_by.as_deref()));
+        }
+
+        if let Some(blamer) = &self.blamer {
+            let attributed = rt.block_on(blamer.annotate(&repo_path, &mut all_derives));
+            info!("Attributed {} of {} derive statements in {} to their authors", 
+                  attributed, all_derives.len(), repo.full_name);
+        }
+
         let result = RepositoryResult {
             repo_name: repo.full_name.clone(),
             derive_statements: all_derives,
             rust_files_processed: files_processed,
+            revision,
+            parse_strategies,
+            sparse_checkout,
+            bytes_saved,
         };
 
+        // Append this repository's statements to the NDJSON output as soon as it completes
+        if let Ok(mut sink) = self.sink.lock() {
+            if let Err(e) = sink.append(&result.derive_statements) {
+                warn!("Failed to append results for {} to NDJSON output: {}", repo.full_name, e);
+            } else {
+                info!("Appended {} derive statements from {} to NDJSON output", 
+                      result.derive_statements.len(), repo.full_name);
+            }
+        }
+
         // Store the result in shared storage
         if let Ok(mut storage) = self.results_storage.lock() {
             storage.push(result.clone());
-            
-            // Persist all current results to disk after each repository completion
-            let all_derives: Vec<DeriveStatement> = storage.iter()
-                .flat_map(|repo_result| repo_result.derive_statements.iter())
-                .cloned()
-                .collect();
-            
-            if !all_derives.is_empty() {
-                let json_output = self.output_dir.join("derive_statements_incremental.json");
-                
-                // Use blocking runtime to save the results
-                if let Err(e) = tokio::runtime::Runtime::new()
-                    .map_err(|e| ProcessingError::FileAccessError(format!("Failed to create runtime: {}", e)))
-                    .and_then(|rt| rt.block_on(ResultsPersistence::save_to_json(&all_derives, &json_output))
-                        .map_err(|e| ProcessingError::FileAccessError(format!("Failed to save incremental results: {}", e))))
-                {
-                    warn!("Failed to save incremental results after {}: {}", repo.full_name, e);
-                } else {
-                    info!("Saved incremental results after processing {} ({} total derives)", 
-                          repo.full_name, all_derives.len());
-                }
-            }
         }
 
         Ok(result)
@@ -212,7 +328,7 @@ impl Processor<RepositoryTask, RepositoryResult, ProcessingError> for Repository
 
     fn config_info(&self) -> String {
         format!("RepositoryProcessor: cache_limit={}, cache_size={}GB", 
-                self.cache_config.max_repositories, self.cache_config.max_size_gb)
+                self.cache.config().max_repositories, self.cache.config().max_size_gb)
     }
 }
 
@@ -0,0 +1,173 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::parser::ParseStrategy;
+use crate::DeriveStatement;
+
+pub const PARSE_CACHE_FILE: &str = "parse_cache.sqlite";
+
+/// A file's statements and how they were extracted
+type Parsed = (Vec<DeriveStatement>, ParseStrategy);
+
+/// One row per distinct file content and parser configuration. Statements are
+/// stored without their repository and path, which are filled in on reuse.
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS parsed_files (
+    content_hash TEXT NOT NULL,
+    parser TEXT NOT NULL,
+    strategy TEXT NOT NULL,
+    statements TEXT NOT NULL,
+    PRIMARY KEY (content_hash, parser)
+);
+"#;
+
+/// How often a run found a file's statements in the parse cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl ParseCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 }
+    }
+}
+
+/// Derive statements extracted from file contents, keyed by the content's
+/// SHA-256 and the parser's fingerprint, so repeat runs and forks skip syn.
+pub struct ParseCache {
+    conn: Mutex<Connection>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ParseCache {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        // Concurrent runs may share a cache directory
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the cached statements for the content hashed as `content_hash`
+    /// (see [`crate::dedup::content_hash`]) under `parser`, calling
+    /// `parse` and recording its result on a miss. Cache failures only cost
+    /// the reuse; the file is parsed as if there were no cache.
+    pub fn get_or_parse<F>(&self, content_hash: &str, parser: &str, repository: &str, file_path: &str, parse: F) -> Parsed
+    where
+        F: FnOnce() -> Parsed,
+    {
+        match self.lookup(content_hash, parser) {
+            Ok(Some((statements, strategy))) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                let statements = statements
+                    .into_iter()
+                    .map(|statement| DeriveStatement {
+                        repository: repository.to_string(),
+                        file_path: file_path.to_string(),
+                        ..statement
+                    })
+                    .collect();
+                return (statements, strategy);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read parse cache for {}/{}: {}", repository, file_path, e),
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let (statements, strategy) = parse();
+        if let Err(e) = self.store(content_hash, parser, &statements, strategy) {
+            warn!("Failed to cache parse of {}/{}: {}", repository, file_path, e);
+        }
+        (statements, strategy)
+    }
+
+    pub fn stats(&self) -> ParseCacheStats {
+        ParseCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lookup(&self, content_hash: &str, parser: &str) -> Result<Option<Parsed>, Box<dyn std::error::Error>> {
+        let conn = self.conn.lock().map_err(|_| "parse cache lock poisoned")?;
+        let row: Option<(String, String)> = conn
+            .query_row(
+                "SELECT strategy, statements FROM parsed_files WHERE content_hash = ?1 AND parser = ?2",
+                params![content_hash, parser],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        match row {
+            Some((strategy, statements)) => Ok(Some((serde_json::from_str(&statements)?, serde_json::from_str(&strategy)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, content_hash: &str, parser: &str, statements: &[DeriveStatement], strategy: ParseStrategy) -> Result<(), Box<dyn std::error::Error>> {
+        let detached: Vec<DeriveStatement> = statements
+            .iter()
+            .map(|statement| DeriveStatement {
+                repository: String::new(),
+                file_path: String::new(),
+                ..statement.clone()
+            })
+            .collect();
+
+        let conn = self.conn.lock().map_err(|_| "parse cache lock poisoned")?;
+        conn.execute(
+            "INSERT OR REPLACE INTO parsed_files (content_hash, parser, strategy, statements) VALUES (?1, ?2, ?3, ?4)",
+            params![content_hash, parser, serde_json::to_string(&strategy)?, serde_json::to_string(&detached)?],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::content_hash;
+    use crate::parser::RustParser;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn test_reuses_parses_by_content() {
+        let scratch = scratch_dir();
+        let path = scratch.path().join("parse-cache.sqlite");
+        let cache = ParseCache::open(&path).unwrap();
+        let parser = RustParser::new();
+        let content = "#[derive(Debug, Clone)]\nstruct A;\n";
+        let hash = content_hash(content);
+
+        let (first, strategy) = cache.get_or_parse(&hash, &parser.fingerprint(), "owner/repo", "src/lib.rs", || {
+            parser.extract_derives_with_strategy(content, "owner/repo", "src/lib.rs")
+        });
+        assert_eq!(strategy, ParseStrategy::Syn);
+
+        // A fork's copy of the file is served from the cache, under its own name and path
+        let (copy, strategy) = cache.get_or_parse(&hash, &parser.fingerprint(), "fork/repo", "lib.rs", || unreachable!());
+        assert_eq!(strategy, ParseStrategy::Syn);
+        assert_eq!(copy.len(), first.len());
+        assert_eq!(copy[0].repository, "fork/repo");
+        assert_eq!(copy[0].file_path, "lib.rs");
+        assert_eq!(copy[0].derives, first[0].derives);
+
+        // A different parser configuration parses again
+        cache.get_or_parse(&hash, "v0-other", "owner/repo", "src/lib.rs", || (Vec::new(), ParseStrategy::TextOnly));
+        assert_eq!(cache.stats(), ParseCacheStats { hits: 1, misses: 2 });
+    }
+}
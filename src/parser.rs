@@ -1,176 +1,112 @@
-use syn::{parse_file, Item, Attribute, Meta, MetaList};
-use quote::ToTokens;
+use derive_order::{extract_syn, extract_text, DeriveAttribute, Error as ExtractError, OrderSensitive};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::{debug, warn};
-use std::panic;
 use crate::DeriveStatement;
 
+/// How a file's derive statements were extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseStrategy {
+    /// Parsed with syn
+    Syn,
+    /// syn failed or panicked, so the line scanner was used instead
+    TextFallback,
+    /// The file looked too complex for syn and went straight to the line scanner
+    TextOnly,
+}
+
+/// Version of the extraction logic; bump it whenever the statements extracted
+/// from the same content change, so cached parses are not reused.
+pub const PARSER_VERSION: u32 = 2;
+
 #[derive(Clone)]
-pub struct RustParser;
+pub struct RustParser {
+    order_sensitive: OrderSensitive,
+}
 
 impl RustParser {
     pub fn new() -> Self {
-        Self
+        Self::with_order_sensitive(OrderSensitive::builtin())
+    }
+
+    pub fn with_order_sensitive(order_sensitive: OrderSensitive) -> Self {
+        Self { order_sensitive }
+    }
+
+    /// Identifies everything besides a file's content that shapes its statements.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for entry in self.order_sensitive.entries() {
+            hasher.update(entry.path.as_bytes());
+            hasher.update([0]);
+        }
+        let digest = hasher.finalize();
+        let registry: String = digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("v{}-{}", PARSER_VERSION, registry)
     }
 
     pub fn extract_derives(&self, content: &str, repository: &str, file_path: &str) -> Vec<DeriveStatement> {
-        let mut derives = Vec::new();
-        
+        self.extract_derives_with_strategy(content, repository, file_path).0
+    }
+
+    pub fn extract_derives_with_strategy(&self, content: &str, repository: &str, file_path: &str) -> (Vec<DeriveStatement>, ParseStrategy) {
         // Skip files that are likely to cause stack overflow
         if self.is_likely_problematic_file(content, file_path) {
             debug!("Skipping potentially problematic file {}/{} (too complex for syn parser)", repository, file_path);
-            self.extract_derives_text_based(content, repository, file_path, &mut derives);
-            return derives;
+            return (self.to_statements(extract_text(content), repository, file_path), ParseStrategy::TextOnly);
         }
         
         // Try to parse the file as Rust syntax with error handling
-        let content_clone = content.to_string();
-        match panic::catch_unwind(move || parse_file(&content_clone)) {
-            Ok(Ok(file)) => {
-                for item in file.items {
-                    self.extract_derives_from_item(&item, &mut derives, repository, file_path, content);
-                }
-            }
-            Ok(Err(e)) => {
+        let (attributes, strategy) = match extract_syn(content) {
+            Ok(attributes) => (attributes, ParseStrategy::Syn),
+            Err(ExtractError::Parse(e)) => {
                 warn!("Failed to parse Rust file {}/{}: {}", repository, file_path, e);
                 // Fallback to text-based extraction
-                self.extract_derives_text_based(content, repository, file_path, &mut derives);
+                (extract_text(content), ParseStrategy::TextFallback)
             }
-            Err(_) => {
+            Err(ExtractError::Panicked) => {
                 warn!("Parser panicked on file {}/{}, using text-based fallback", repository, file_path);
                 // Fallback to text-based extraction
-                self.extract_derives_text_based(content, repository, file_path, &mut derives);
+                (extract_text(content), ParseStrategy::TextFallback)
             }
-        }
+        };
         
-        derives
-    }
-
-    fn extract_derives_from_item(
-        &self,
-        item: &Item,
-        derives: &mut Vec<DeriveStatement>,
-        repository: &str,
-        file_path: &str,
-        content: &str,
-    ) {
-        match item {
-            Item::Struct(item_struct) => {
-                self.extract_derives_from_attrs(&item_struct.attrs, derives, repository, file_path, content);
-            }
-            Item::Enum(item_enum) => {
-                self.extract_derives_from_attrs(&item_enum.attrs, derives, repository, file_path, content);
-            }
-            Item::Union(item_union) => {
-                self.extract_derives_from_attrs(&item_union.attrs, derives, repository, file_path, content);
-            }
-            _ => {}
-        }
-    }
-
-    fn extract_derives_from_attrs(
-        &self,
-        attrs: &[Attribute],
-        derives: &mut Vec<DeriveStatement>,
-        repository: &str,
-        file_path: &str,
-        content: &str,
-    ) {
-        for attr in attrs {
-            if attr.path().is_ident("derive") {
-                let meta = attr.meta.clone();
-                match meta {
-                    Meta::List(MetaList { tokens, .. }) => {
-                        let derive_list = self.parse_derive_tokens(&tokens.to_string());
-                        if !derive_list.is_empty() {
-                            let line_number = self.find_line_number(content, &attr.to_token_stream().to_string());
-                            let full_line = self.get_line_at_number(content, line_number);
-                            
-                            derives.push(DeriveStatement {
-                                repository: repository.to_string(),
-                                file_path: file_path.to_string(),
-                                line_number,
-                                derives: derive_list.clone(),
-                                full_line,
-                            });
-                            
-                            debug!("Found derive in {}/{} at line {}: {:?}", 
-                                  repository, file_path, line_number, derive_list);
-                        }
-                    }
-                    _ => {
-                        warn!("Unexpected derive format in {}/{}", repository, file_path);
-                    }
-                }
-            }
-        }
-    }
-
-    fn parse_derive_tokens(&self, tokens: &str) -> Vec<String> {
-        // Parse the derive list from tokens like "Clone , Copy , Debug"
-        tokens
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_' || c == ':'))
-            .collect()
+        (self.to_statements(attributes, repository, file_path), strategy)
     }
 
-    fn extract_derives_text_based(
-        &self,
-        content: &str,
-        repository: &str,
-        file_path: &str,
-        derives: &mut Vec<DeriveStatement>,
-    ) {
-        // Fallback text-based parsing for files that can't be parsed syntactically
-        for (line_num, line) in content.lines().enumerate() {
-            let trimmed = line.trim();
-            if trimmed.starts_with("#[derive(") && trimmed.ends_with(")]") {
-                let derive_content = &trimmed[9..trimmed.len()-2]; // Remove "#[derive(" and ")]"
-                let derive_list: Vec<String> = derive_content
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
+    fn to_statements(&self, attributes: Vec<DeriveAttribute>, repository: &str, file_path: &str) -> Vec<DeriveStatement> {
+        attributes
+            .into_iter()
+            .map(|attribute| {
+                debug!("Found derive in {}/{} at line {}: {:?}", 
+                      repository, file_path, attribute.line, attribute.derives);
+                
+                let order_sensitive = self.order_sensitive
+                    .matching(&attribute.derives)
+                    .into_iter()
+                    .map(String::from)
                     .collect();
                 
-                if !derive_list.is_empty() {
-                    derives.push(DeriveStatement {
-                        repository: repository.to_string(),
-                        file_path: file_path.to_string(),
-                        line_number: line_num + 1,
-                        derives: derive_list,
-                        full_line: line.to_string(),
-                    });
-                    
-                    debug!("Found derive (text-based) in {}/{} at line {}", 
-                          repository, file_path, line_num + 1);
+                DeriveStatement {
+                    repository: repository.to_string(),
+                    file_path: file_path.to_string(),
+                    line_number: attribute.line,
+                    derives: attribute.derives,
+                    full_line: attribute.full_line,
+                    order_sensitive,
+                    item_kind: attribute.target.as_ref().map(|t| t.kind.as_str().to_string()),
+                    item_name: attribute.target.as_ref().map(|t| t.name.clone()),
+                    item_line: attribute.target.as_ref().map(|t| t.line),
+                    ..DeriveStatement::default()
                 }
-            }
-        }
-    }
-
-    fn find_line_number(&self, content: &str, _target: &str) -> usize {
-        // Simple heuristic to find line number - could be improved
-        for (line_num, line) in content.lines().enumerate() {
-            if line.contains("derive") {
-                return line_num + 1;
-            }
-        }
-        1
-    }
-
-    fn get_line_at_number(&self, content: &str, line_number: usize) -> String {
-        content
-            .lines()
-            .nth(line_number.saturating_sub(1))
-            .unwrap_or("")
-            .to_string()
+            })
+            .collect()
     }
 
     pub fn extract_derives_text_only(&self, content: &str, repository: &str, file_path: &str) -> Vec<DeriveStatement> {
-        let mut derives = Vec::new();
         debug!("Using text-only parsing for potentially problematic file {}/{}", repository, file_path);
-        self.extract_derives_text_based(content, repository, file_path, &mut derives);
-        derives
+        self.to_statements(extract_text(content), repository, file_path)
     }
 
     fn is_likely_problematic_file(&self, content: &str, file_path: &str) -> bool {
@@ -234,4 +170,15 @@ pub struct ComplexStruct {
         assert!(derives[0].derives.contains(&"Debug".to_string()));
         assert!(derives[0].derives.contains(&"Clone".to_string()));
     }
+
+    #[test]
+    fn test_flags_order_sensitive_derives() {
+        let parser = RustParser::new();
+        let content = r#"
+#[derive(Debug, ambassador::Delegate)]
+pub struct Wrapper(Inner);
+"#;
+        let derives = parser.extract_derives(content, "test/repo", "src/lib.rs");
+        assert_eq!(derives[0].order_sensitive, vec!["ambassador::Delegate"]);
+    }
 }
\ No newline at end of file
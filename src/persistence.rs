@@ -1,13 +1,118 @@
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_array::cast::AsArray;
+use arrow_array::types::UInt64Type;
+use arrow_array::{Array, ArrayRef, BooleanArray, ListArray, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
 use csv::Writer;
-use std::path::Path;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tracing::info;
+use serde::de::{Deserializer, IgnoredAny, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
 
+use crate::analysis::{DeriveStatistics, FamilyReport};
+use crate::cloned_repo::Revision;
+use crate::dedup::DuplicationReport;
+use crate::history::HistoryReport;
+use crate::manifest::{self, SCHEMA_VERSION};
+use crate::taxonomy::Taxonomy;
 use crate::DeriveStatement;
 
+/// Rows per Parquet record batch, and so per row group
+const PARQUET_BATCH_SIZE: usize = 65_536;
+
 pub struct ResultsPersistence;
 
+/// Errors of writers run on the blocking pool, which must cross threads.
+type BlockingError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Runs a writer doing blocking file I/O on tokio's blocking pool, keeping it
+/// off the runtime's threads.
+async fn run_blocking(write: impl FnOnce() -> Result<(), BlockingError> + Send + 'static) -> Result<(), Box<dyn std::error::Error>> {
+    tokio::task::spawn_blocking(write).await?.map_err(|e| e as Box<dyn std::error::Error>)
+}
+
+/// The fields of the first statement of a JSON array, read without
+/// deserialising the statements.
+struct FirstFields(BTreeMap<String, IgnoredAny>);
+
+impl<'de> Deserialize<'de> for FirstFields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FirstFieldsVisitor;
+        
+        impl<'de> Visitor<'de> for FirstFieldsVisitor {
+            type Value = FirstFields;
+            
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an array of derive statements")
+            }
+            
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let first = seq.next_element()?.unwrap_or_default();
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                Ok(FirstFields(first))
+            }
+        }
+        
+        deserializer.deserialize_seq(FirstFieldsVisitor)
+    }
+}
+
+/// A row of `derive_statements.csv`. Columns added after the first release are
+/// optional so older files still load.
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    repository: String,
+    file_path: String,
+    line_number: usize,
+    derives: String,
+    full_line: String,
+    #[serde(default)]
+    order_sensitive: String,
+    #[serde(default)]
+    item_kind: Option<String>,
+    #[serde(default)]
+    item_name: Option<String>,
+    #[serde(default)]
+    item_line: Option<usize>,
+    #[serde(default)]
+    commit_sha: Option<String>,
+    #[serde(default)]
+    commit_date: Option<String>,
+    #[serde(default)]
+    branch: Option<String>,
+    #[serde(default)]
+    author_hash: Option<String>,
+    #[serde(default)]
+    line_commit_date: Option<String>,
+    #[serde(default)]
+    content_hash: Option<String>,
+    #[serde(default)]
+    file_kind: Option<String>,
+    #[serde(default)]
+    generated_by: Option<String>,
+    #[serde(default)]
+    package: Option<String>,
+    #[serde(default)]
+    edition: Option<String>,
+    #[serde(default)]
+    rust_version: Option<String>,
+    #[serde(default)]
+    proc_macro: Option<bool>,
+    #[serde(default)]
+    origins: String,
+}
+
 impl ResultsPersistence {
     pub async fn save_to_json<P: AsRef<Path>>(
         derives: &[DeriveStatement], 
@@ -25,28 +130,174 @@ impl ResultsPersistence {
         derives: &[DeriveStatement], 
         path: P
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut wtr = Writer::from_path(path.as_ref())?;
+        let (derives, path) = (derives.to_vec(), path.as_ref().to_path_buf());
+        run_blocking(move || Self::write_csv(&derives, &path)).await
+    }
+    
+    fn write_csv(derives: &[DeriveStatement], path: &Path) -> Result<(), BlockingError> {
+        let mut wtr = Writer::from_path(path)?;
         
         // Write header
-        wtr.write_record(&["repository", "file_path", "line_number", "derives", "full_line"])?;
+        wtr.write_record(&["repository", "file_path", "line_number", "derives", "full_line", "order_sensitive",
+                           "item_kind", "item_name", "item_line", "commit_sha", "commit_date", "branch",
+                           "author_hash", "line_commit_date", "content_hash", "file_kind", "generated_by",
+                           "package", "edition", "rust_version", "proc_macro", "origins"])?;
         
         // Write data rows
         for derive in derives {
             let derives_str = derive.derives.join(", ");
+            let order_sensitive_str = derive.order_sensitive.join(", ");
+            // Version requirements may contain commas, so origins are separated by semicolons
+            let origins_str = derive.origins.iter().map(|origin| origin.to_string()).collect::<Vec<_>>().join("; ");
             wtr.write_record(&[
                 &derive.repository,
                 &derive.file_path,
                 &derive.line_number.to_string(),
                 &derives_str,
                 &derive.full_line,
+                &order_sensitive_str,
+                derive.item_kind.as_deref().unwrap_or(""),
+                derive.item_name.as_deref().unwrap_or(""),
+                &derive.item_line.map(|l| l.to_string()).unwrap_or_default(),
+                derive.commit_sha.as_deref().unwrap_or(""),
+                derive.commit_date.as_deref().unwrap_or(""),
+                derive.branch.as_deref().unwrap_or(""),
+                derive.author_hash.as_deref().unwrap_or(""),
+                derive.line_commit_date.as_deref().unwrap_or(""),
+                derive.content_hash.as_deref().unwrap_or(""),
+                derive.file_kind.map(|kind| kind.as_str()).unwrap_or(""),
+                derive.generated_by.as_deref().unwrap_or(""),
+                derive.package.as_deref().unwrap_or(""),
+                derive.edition.as_deref().unwrap_or(""),
+                derive.rust_version.as_deref().unwrap_or(""),
+                &derive.proc_macro.map(|p| p.to_string()).unwrap_or_default(),
+                &origins_str,
             ])?;
         }
         
         wtr.flush()?;
-        info!("Saved {} derive statements to {}", derives.len(), path.as_ref().display());
+        info!("Saved {} derive statements to {}", derives.len(), path.display());
+        Ok(())
+    }
+    
+    pub async fn save_to_parquet<P: AsRef<Path>>(
+        derives: &[DeriveStatement], 
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (derives, path) = (derives.to_vec(), path.as_ref().to_path_buf());
+        run_blocking(move || Self::write_parquet(&derives, &path)).await
+    }
+    
+    fn write_parquet(derives: &[DeriveStatement], path: &Path) -> Result<(), BlockingError> {
+        let schema = Self::parquet_schema();
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(PARQUET_BATCH_SIZE)
+            .set_key_value_metadata(Some(vec![
+                KeyValue::new("schema_version".to_string(), SCHEMA_VERSION.to_string()),
+            ]))
+            .build();
+        
+        let file = std::fs::File::create(path)?;
+        let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+        
+        // Write in fixed-size batches so large corpora never need one giant batch in memory
+        for chunk in derives.chunks(PARQUET_BATCH_SIZE) {
+            let origins: Vec<Vec<String>> = chunk.iter()
+                .map(|d| d.origins.iter().map(ToString::to_string).collect())
+                .collect();
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from_iter_values(chunk.iter().map(|d| d.repository.as_str()))),
+                Arc::new(StringArray::from_iter_values(chunk.iter().map(|d| d.file_path.as_str()))),
+                Arc::new(UInt64Array::from_iter_values(chunk.iter().map(|d| d.line_number as u64))),
+                Arc::new(Self::string_list_array(chunk.iter().map(|d| &d.derives))),
+                Arc::new(StringArray::from_iter_values(chunk.iter().map(|d| d.full_line.as_str()))),
+                Arc::new(Self::string_list_array(chunk.iter().map(|d| &d.order_sensitive))),
+                Arc::new(chunk.iter().map(|d| d.item_kind.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.item_name.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.item_line.map(|l| l as u64)).collect::<UInt64Array>()),
+                Arc::new(chunk.iter().map(|d| d.commit_sha.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.commit_date.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.branch.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.author_hash.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.line_commit_date.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.content_hash.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.file_kind.map(|kind| kind.as_str())).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.generated_by.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.package.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.edition.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.rust_version.as_deref()).collect::<StringArray>()),
+                Arc::new(chunk.iter().map(|d| d.proc_macro).collect::<BooleanArray>()),
+                Arc::new(Self::string_list_array(origins.iter())),
+            ];
+            let batch = RecordBatch::try_new(schema.clone(), columns)?;
+            writer.write(&batch)?;
+        }
+        
+        writer.close()?;
+        info!("Saved {} derive statements to {}", derives.len(), path.display());
         Ok(())
     }
     
+    fn parquet_schema() -> SchemaRef {
+        let string_list = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+        Arc::new(Schema::new(vec![
+            Field::new("repository", DataType::Utf8, false),
+            Field::new("file_path", DataType::Utf8, false),
+            Field::new("line_number", DataType::UInt64, false),
+            Field::new("derives", string_list.clone(), false),
+            Field::new("full_line", DataType::Utf8, false),
+            Field::new("order_sensitive", string_list.clone(), false),
+            Field::new("item_kind", DataType::Utf8, true),
+            Field::new("item_name", DataType::Utf8, true),
+            Field::new("item_line", DataType::UInt64, true),
+            Field::new("commit_sha", DataType::Utf8, true),
+            Field::new("commit_date", DataType::Utf8, true),
+            Field::new("branch", DataType::Utf8, true),
+            Field::new("author_hash", DataType::Utf8, true),
+            Field::new("line_commit_date", DataType::Utf8, true),
+            Field::new("content_hash", DataType::Utf8, true),
+            Field::new("file_kind", DataType::Utf8, true),
+            Field::new("generated_by", DataType::Utf8, true),
+            Field::new("package", DataType::Utf8, true),
+            Field::new("edition", DataType::Utf8, true),
+            Field::new("rust_version", DataType::Utf8, true),
+            Field::new("proc_macro", DataType::Boolean, true),
+            Field::new("origins", string_list, false),
+        ]))
+    }
+    
+    fn string_list_array<'a>(rows: impl Iterator<Item = &'a Vec<String>>) -> ListArray {
+        let mut builder = ListBuilder::new(StringBuilder::new());
+        for row in rows {
+            for value in row {
+                builder.values().append_value(value);
+            }
+            builder.append(true);
+        }
+        builder.finish()
+    }
+    
+    /// Appends a run to the SQLite database. `analysed` lists every repository
+    /// of the run with its revision, including those without statements.
+    pub async fn save_to_sqlite<P: AsRef<Path>>(
+        derives: &[DeriveStatement], 
+        analysed: &[(&str, Option<&Revision>)],
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (derives, path) = (derives.to_vec(), path.as_ref().to_path_buf());
+        let analysed: Vec<(String, Option<Revision>)> = analysed.iter()
+            .map(|(repository, revision)| (repository.to_string(), revision.cloned()))
+            .collect();
+        run_blocking(move || {
+            let analysed: Vec<(&str, Option<&Revision>)> = analysed.iter()
+                .map(|(repository, revision)| (repository.as_str(), revision.as_ref()))
+                .collect();
+            crate::sqlite_store::save_run(&derives, &analysed, path)?;
+            Ok(())
+        }).await
+    }
+    
     pub async fn save_summary<P: AsRef<Path>>(
         derives: &[DeriveStatement], 
         path: P
@@ -56,10 +307,22 @@ impl ResultsPersistence {
         // Analyze derive patterns
         let mut derive_counts: HashMap<String, usize> = HashMap::new();
         let mut repo_counts: HashMap<String, usize> = HashMap::new();
+        let mut order_sensitive_counts: HashMap<String, usize> = HashMap::new();
+        let mut generated_counts: HashMap<String, usize> = HashMap::new();
         let mut total_statements = 0;
+        let mut order_sensitive_statements = 0;
         
         for derive_stmt in derives {
             total_statements += 1;
+            if !derive_stmt.order_sensitive.is_empty() {
+                order_sensitive_statements += 1;
+            }
+            for derive in &derive_stmt.order_sensitive {
+                *order_sensitive_counts.entry(derive.clone()).or_insert(0) += 1;
+            }
+            if let Some(generated_by) = &derive_stmt.generated_by {
+                *generated_counts.entry(generated_by.clone()).or_insert(0) += 1;
+            }
             repo_counts.entry(derive_stmt.repository.clone())
                 .and_modify(|e| *e += 1)
                 .or_insert(1);
@@ -78,6 +341,13 @@ impl ResultsPersistence {
         let mut sorted_repos: Vec<_> = repo_counts.into_iter().collect();
         sorted_repos.sort_by(|a, b| b.1.cmp(&a.1));
         
+        let mut sorted_order_sensitive: Vec<_> = order_sensitive_counts.into_iter().collect();
+        sorted_order_sensitive.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        
+        let mut sorted_generated: Vec<_> = generated_counts.into_iter().collect();
+        sorted_generated.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let generated_statements: usize = sorted_generated.iter().map(|(_, count)| count).sum();
+        
         // Create summary
         let summary = serde_json::json!({
             "total_derive_statements": total_statements,
@@ -85,6 +355,10 @@ impl ResultsPersistence {
             "total_unique_derives": sorted_derives.len(),
             "most_common_derives": sorted_derives.into_iter().take(20).collect::<Vec<_>>(),
             "repositories_by_derive_count": sorted_repos.into_iter().take(20).collect::<Vec<_>>(),
+            "order_sensitive_statements": order_sensitive_statements,
+            "order_sensitive_derives": sorted_order_sensitive,
+            "generated_statements": generated_statements,
+            "generated_statements_by_generator": sorted_generated,
             "analysis_timestamp": chrono::Utc::now().to_rfc3339()
         });
         
@@ -95,4 +369,529 @@ impl ResultsPersistence {
         info!("Saved analysis summary to {}", path.as_ref().display());
         Ok(())
     }
-}
\ No newline at end of file
+    
+    /// Writes ordering statistics, counting each statement by its weight in `weights`.
+    pub async fn save_statistics<P: AsRef<Path>>(
+        derives: &[DeriveStatement], 
+        weights: &[f64],
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let statistics = DeriveStatistics::compute_weighted(derives, weights);
+        let statistics_json = serde_json::to_string_pretty(&statistics)?;
+        let mut file = File::create(path.as_ref()).await?;
+        file.write_all(statistics_json.as_bytes()).await?;
+        
+        info!("Saved ordering statistics for {} repositories to {}", 
+              statistics.repositories.len(), path.as_ref().display());
+        Ok(())
+    }
+    
+    pub async fn save_families<P: AsRef<Path>>(
+        derives: &[DeriveStatement], 
+        weights: &[f64],
+        taxonomy: &Taxonomy,
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let report = FamilyReport::compute_weighted(derives, weights, taxonomy);
+        let report_json = serde_json::to_string_pretty(&report)?;
+        let mut file = File::create(path.as_ref()).await?;
+        file.write_all(report_json.as_bytes()).await?;
+        
+        info!("Saved derive family statistics to {}", path.as_ref().display());
+        Ok(())
+    }
+    
+    pub async fn save_duplication_report<P: AsRef<Path>>(
+        report: &DuplicationReport, 
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let report_json = serde_json::to_string_pretty(report)?;
+        let mut file = File::create(path.as_ref()).await?;
+        file.write_all(report_json.as_bytes()).await?;
+        
+        info!("Saved duplication report to {}", path.as_ref().display());
+        Ok(())
+    }
+    
+    pub async fn save_history_to_json<P: AsRef<Path>>(
+        report: &HistoryReport, 
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let history_json = serde_json::to_string_pretty(report)?;
+        let mut file = File::create(path.as_ref()).await?;
+        file.write_all(history_json.as_bytes()).await?;
+        
+        info!("Saved {} history points to {}", report.points.len(), path.as_ref().display());
+        Ok(())
+    }
+    
+    /// Writes the time series without per-point pair preferences, one row per sampled commit.
+    pub async fn save_history_to_csv<P: AsRef<Path>>(
+        report: &HistoryReport, 
+        path: P
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (report, path) = (report.clone(), path.as_ref().to_path_buf());
+        run_blocking(move || Self::write_history_csv(&report, &path)).await
+    }
+    
+    fn write_history_csv(report: &HistoryReport, path: &Path) -> Result<(), BlockingError> {
+        let mut wtr = Writer::from_path(path)?;
+        wtr.write_record(["repository", "label", "commit_sha", "commit_date", "rust_files",
+                           "derive_statements", "multi_derive_statements", "consistency_score"])?;
+        
+        for point in &report.points {
+            wtr.write_record([
+                &report.repository,
+                &point.commit.label,
+                &point.commit.commit_sha,
+                &point.commit.commit_date,
+                &point.rust_files.to_string(),
+                &point.derive_statements.to_string(),
+                &point.multi_derive_statements.to_string(),
+                &format!("{:.6}", point.consistency_score),
+            ])?;
+        }
+        
+        wtr.flush()?;
+        info!("Saved {} history points to {}", report.points.len(), path.display());
+        Ok(())
+    }
+    
+    /// Loads previously saved statements, choosing the format from the file
+    /// extension and migrating them from older schema versions.
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Vec<DeriveStatement>, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let (mut derives, schema_version) = match extension {
+            "json" => {
+                let json_data = tokio::fs::read_to_string(path).await?;
+                (serde_json::from_str(&json_data)?, Self::json_schema_version(&json_data)?)
+            }
+            "ndjson" | "jsonl" | "partial" => {
+                let content = tokio::fs::read_to_string(path).await?;
+                (Self::parse_ndjson(&content, path)?, Self::ndjson_schema_version(&content))
+            }
+            "csv" => (Self::load_from_csv(path).await?, Self::csv_schema_version(path)?),
+            "parquet" => (Self::load_from_parquet(path).await?, Self::parquet_schema_version(path)?),
+            "sqlite" | "db" => (Self::load_from_sqlite(path).await?, crate::sqlite_store::schema_version(path)?),
+            _ => return Err(format!("Unrecognised results format: {}", path.display()).into()),
+        };
+        
+        if schema_version > SCHEMA_VERSION {
+            return Err(format!("{} uses schema version {}, newer than supported version {}", 
+                               path.display(), schema_version, SCHEMA_VERSION).into());
+        }
+        manifest::migrate(&mut derives, schema_version);
+        
+        info!("Loaded {} derive statements from {}", derives.len(), path.display());
+        Ok(derives)
+    }
+    
+    /// Schema version of a JSON array of statements, from the fields of its first statement.
+    fn json_schema_version(json_data: &str) -> Result<u32, Box<dyn std::error::Error>> {
+        let FirstFields(fields) = serde_json::from_str(json_data)?;
+        Ok(manifest::schema_version_of_fields(fields.keys().map(String::as_str)))
+    }
+    
+    /// Schema version of JSON Lines output, from the fields of its first line.
+    fn ndjson_schema_version(content: &str) -> u32 {
+        let fields: BTreeMap<String, IgnoredAny> = match content.lines().find(|l| !l.trim().is_empty()) {
+            // A file cut short on its first line has nothing to migrate
+            Some(line) => serde_json::from_str(line).unwrap_or_default(),
+            None => BTreeMap::new(),
+        };
+        manifest::schema_version_of_fields(fields.keys().map(String::as_str))
+    }
+    
+    fn csv_schema_version(path: &Path) -> Result<u32, Box<dyn std::error::Error>> {
+        let mut rdr = csv::Reader::from_path(path)?;
+        Ok(manifest::schema_version_of_fields(rdr.headers()?.iter()))
+    }
+    
+    /// Schema version recorded in a Parquet file's metadata, or else judged
+    /// from its columns like the formats that record none.
+    fn parquet_schema_version(path: &Path) -> Result<u32, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let recorded = builder.metadata().file_metadata().key_value_metadata()
+            .and_then(|kv| kv.iter().find(|entry| entry.key == "schema_version"))
+            .and_then(|entry| entry.value.as_deref())
+            .and_then(|value| value.parse().ok());
+        let version = recorded.unwrap_or_else(|| {
+            manifest::schema_version_of_fields(builder.schema().fields().iter().map(|field| field.name().as_str()))
+        });
+        Ok(version)
+    }
+    
+    /// Parses JSON Lines output read from `path`, which only names it in
+    /// errors, including an unfinished `.partial` file whose last line may
+    /// have been cut short.
+    fn parse_ndjson(content: &str, path: &Path) -> Result<Vec<DeriveStatement>, Box<dyn std::error::Error>> {
+        let complete = content.ends_with('\n');
+        let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+        
+        let mut derives = Vec::with_capacity(lines.len());
+        for (idx, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(derive) => derives.push(derive),
+                Err(e) if idx + 1 == lines.len() && !complete => {
+                    warn!("Ignoring truncated final line of {}: {}", path.display(), e);
+                }
+                Err(e) => return Err(format!("{}:{}: {}", path.display(), idx + 1, e).into()),
+            }
+        }
+        Ok(derives)
+    }
+    
+    pub async fn load_from_csv<P: AsRef<Path>>(path: P) -> Result<Vec<DeriveStatement>, Box<dyn std::error::Error>> {
+        let mut rdr = csv::Reader::from_path(path.as_ref())?;
+        let mut derives = Vec::new();
+        
+        for row in rdr.deserialize() {
+            let row: CsvRow = row?;
+            derives.push(DeriveStatement {
+                repository: row.repository,
+                file_path: row.file_path,
+                line_number: row.line_number,
+                derives: Self::split_list(&row.derives),
+                full_line: row.full_line,
+                order_sensitive: Self::split_list(&row.order_sensitive),
+                item_kind: row.item_kind,
+                item_name: row.item_name,
+                item_line: row.item_line,
+                commit_sha: row.commit_sha,
+                commit_date: row.commit_date,
+                branch: row.branch,
+                author_hash: row.author_hash,
+                line_commit_date: row.line_commit_date,
+                content_hash: row.content_hash,
+                file_kind: row.file_kind.and_then(|kind| kind.parse().ok()),
+                generated_by: row.generated_by,
+                package: row.package,
+                edition: row.edition,
+                rust_version: row.rust_version,
+                proc_macro: row.proc_macro,
+                origins: row.origins.split(';').filter_map(|origin| origin.trim().parse().ok()).collect(),
+            });
+        }
+        Ok(derives)
+    }
+    
+    pub async fn load_from_parquet<P: AsRef<Path>>(path: P) -> Result<Vec<DeriveStatement>, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path.as_ref())?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+        let mut derives = Vec::new();
+        
+        for batch in reader {
+            let batch = batch?;
+            let strings = |name: &str| batch.column_by_name(name).and_then(|c| c.as_string_opt::<i32>());
+            let lists = |name: &str| batch.column_by_name(name).and_then(|c| c.as_list_opt::<i32>());
+            let numbers = |name: &str| batch.column_by_name(name).and_then(|c| c.as_primitive_opt::<UInt64Type>());
+            
+            let repository = strings("repository").ok_or("Parquet file has no repository column")?;
+            let file_path = strings("file_path").ok_or("Parquet file has no file_path column")?;
+            let line_number = numbers("line_number").ok_or("Parquet file has no line_number column")?;
+            let derive_lists = lists("derives").ok_or("Parquet file has no derives column")?;
+            let full_line = strings("full_line").ok_or("Parquet file has no full_line column")?;
+            let order_sensitive = lists("order_sensitive");
+            let item_kind = strings("item_kind");
+            let item_name = strings("item_name");
+            let item_line = numbers("item_line");
+            let commit_sha = strings("commit_sha");
+            let commit_date = strings("commit_date");
+            let branch = strings("branch");
+            let author_hash = strings("author_hash");
+            let line_commit_date = strings("line_commit_date");
+            let content_hash = strings("content_hash");
+            let file_kind = strings("file_kind");
+            let generated_by = strings("generated_by");
+            let package = strings("package");
+            let edition = strings("edition");
+            let rust_version = strings("rust_version");
+            let proc_macro = batch.column_by_name("proc_macro").and_then(|c| c.as_boolean_opt());
+            let origins = lists("origins");
+            
+            let list_values = |list: &ListArray, row: usize| -> Vec<String> {
+                let values = list.value(row);
+                values.as_string::<i32>().iter().flatten().map(String::from).collect()
+            };
+            let optional_string = |column: Option<&StringArray>, row: usize| {
+                column.filter(|c| c.is_valid(row)).map(|c| c.value(row).to_string())
+            };
+            
+            for row in 0..batch.num_rows() {
+                derives.push(DeriveStatement {
+                    repository: repository.value(row).to_string(),
+                    file_path: file_path.value(row).to_string(),
+                    line_number: line_number.value(row) as usize,
+                    derives: list_values(derive_lists, row),
+                    full_line: full_line.value(row).to_string(),
+                    order_sensitive: order_sensitive.map(|l| list_values(l, row)).unwrap_or_default(),
+                    item_kind: optional_string(item_kind, row),
+                    item_name: optional_string(item_name, row),
+                    item_line: item_line.filter(|c| c.is_valid(row)).map(|c| c.value(row) as usize),
+                    commit_sha: optional_string(commit_sha, row),
+                    commit_date: optional_string(commit_date, row),
+                    branch: optional_string(branch, row),
+                    author_hash: optional_string(author_hash, row),
+                    line_commit_date: optional_string(line_commit_date, row),
+                    content_hash: optional_string(content_hash, row),
+                    file_kind: optional_string(file_kind, row).and_then(|kind| kind.parse().ok()),
+                    generated_by: optional_string(generated_by, row),
+                    package: optional_string(package, row),
+                    edition: optional_string(edition, row),
+                    rust_version: optional_string(rust_version, row),
+                    proc_macro: proc_macro.filter(|c| c.is_valid(row)).map(|c| c.value(row)),
+                    origins: origins
+                        .filter(|l| l.is_valid(row))
+                        .map(|l| list_values(l, row).iter().filter_map(|origin| origin.parse().ok()).collect())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        Ok(derives)
+    }
+    
+    /// Loads each repository's statements from the most recent run that analysed it.
+    pub async fn load_from_sqlite<P: AsRef<Path>>(path: P) -> Result<Vec<DeriveStatement>, Box<dyn std::error::Error>> {
+        Ok(crate::sqlite_store::load_latest(path)?)
+    }
+    
+    fn split_list(joined: &str) -> Vec<String> {
+        joined
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+}
+
+/// Append-only JSON Lines output, one derive statement per line.
+///
+/// Lines go to `<path>.partial`, which is fsynced after every append so a crash
+/// loses at most the repository being written. `finish` atomically renames the
+/// partial file to `path`.
+pub struct NdjsonSink {
+    path: PathBuf,
+    partial_path: PathBuf,
+    file: Option<std::fs::File>,
+    statements_written: usize,
+}
+
+impl NdjsonSink {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut partial_name = path.file_name().unwrap_or_default().to_os_string();
+        partial_name.push(".partial");
+        let partial_path = path.with_file_name(partial_name);
+        
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&partial_path)?;
+        
+        Ok(Self {
+            path,
+            partial_path,
+            file: Some(file),
+            statements_written: 0,
+        })
+    }
+    
+    /// Appends a batch of statements, typically one repository's worth.
+    pub fn append(&mut self, derives: &[DeriveStatement]) -> Result<(), Box<dyn std::error::Error>> {
+        let file = self.file.as_mut().ok_or("NDJSON sink already finished")?;
+        
+        // Serialise the whole batch first so it reaches the file in a single write
+        let mut buffer = Vec::new();
+        for derive in derives {
+            serde_json::to_writer(&mut buffer, derive)?;
+            buffer.push(b'\n');
+        }
+        
+        file.write_all(&buffer)?;
+        file.sync_data()?;
+        self.statements_written += derives.len();
+        Ok(())
+    }
+    
+    /// Flushes the partial file and moves it into place.
+    pub fn finish(&mut self) -> std::io::Result<PathBuf> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+            drop(file);
+            std::fs::rename(&self.partial_path, &self.path)?;
+            
+            // Persist the rename itself
+            #[cfg(unix)]
+            if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::File::open(parent)?.sync_all()?;
+            }
+            
+            info!("Saved {} derive statements to {}", self.statements_written, self.path.display());
+        }
+        Ok(self.path.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_filter::FileKind;
+    use crate::test_support::scratch_dir;
+
+    fn sample() -> Vec<DeriveStatement> {
+        vec![
+            // Every field set, so the round trips cover each column
+            DeriveStatement {
+                repository: "owner/repo".to_string(),
+                file_path: "src/lib.rs".to_string(),
+                line_number: 3,
+                derives: vec!["Debug".to_string(), "serde::Serialize".to_string()],
+                full_line: "#[derive(Debug, serde::Serialize)]".to_string(),
+                order_sensitive: Vec::new(),
+                item_kind: Some("struct".to_string()),
+                item_name: Some("Config".to_string()),
+                item_line: Some(4),
+                commit_sha: Some("4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string()),
+                commit_date: Some("2025-01-02T03:04:05+00:00".to_string()),
+                branch: Some("main".to_string()),
+                author_hash: Some("9f86d081884c7d65".to_string()),
+                line_commit_date: Some("2024-11-30T12:00:00+00:00".to_string()),
+                content_hash: Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string()),
+                file_kind: Some(FileKind::Generated),
+                generated_by: Some("prost".to_string()),
+                package: Some("repo".to_string()),
+                edition: Some("2021".to_string()),
+                rust_version: Some("1.70".to_string()),
+                proc_macro: Some(false),
+                origins: vec![
+                    "Debug=std".parse().unwrap(),
+                    "serde::Serialize=serde@>=1.0.100, <2".parse().unwrap(),
+                ],
+            },
+            DeriveStatement {
+                repository: "owner/other".to_string(),
+                file_path: "src/main.rs".to_string(),
+                line_number: 10,
+                derives: vec!["Clone".to_string(), "ambassador::Delegate".to_string(), "Accessors".to_string()],
+                full_line: "#[derive(Clone, ambassador::Delegate, Accessors)]".to_string(),
+                order_sensitive: vec!["ambassador::Delegate".to_string()],
+                commit_sha: Some("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391".to_string()),
+                file_kind: Some(FileKind::Src),
+                package: Some("other-derive".to_string()),
+                edition: Some("2015".to_string()),
+                proc_macro: Some(true),
+                origins: vec![
+                    "Clone=std".parse().unwrap(),
+                    "ambassador::Delegate=ambassador".parse().unwrap(),
+                    "Accessors=other-macros+local@0.1".parse().unwrap(),
+                ],
+                ..DeriveStatement::default()
+            },
+        ]
+    }
+
+    fn assert_same(loaded: &[DeriveStatement], expected: &[DeriveStatement]) {
+        let key = |d: &DeriveStatement| (d.repository.clone(), d.file_path.clone(), d.line_number);
+        let mut loaded = loaded.to_vec();
+        let mut expected = expected.to_vec();
+        loaded.sort_by_key(key);
+        expected.sort_by_key(key);
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_all_formats() {
+        let scratch = scratch_dir();
+        let dir = scratch.path();
+        let derives = sample();
+
+        ResultsPersistence::save_to_json(&derives, dir.join("d.json")).await.unwrap();
+        ResultsPersistence::save_to_csv(&derives, dir.join("d.csv")).await.unwrap();
+        ResultsPersistence::save_to_parquet(&derives, dir.join("d.parquet")).await.unwrap();
+        ResultsPersistence::save_to_sqlite(&derives, &[], dir.join("d.sqlite")).await.unwrap();
+        let mut sink = NdjsonSink::create(dir.join("d.ndjson")).unwrap();
+        sink.append(&derives).unwrap();
+        sink.finish().unwrap();
+
+        for name in ["d.json", "d.csv", "d.parquet", "d.sqlite", "d.ndjson"] {
+            let loaded = ResultsPersistence::load(dir.join(name)).await.unwrap();
+            assert_same(&loaded, &derives);
+        }
+
+        // An interrupted run leaves a partial file whose last line may be cut short
+        let mut partial = tokio::fs::read_to_string(dir.join("d.ndjson")).await.unwrap();
+        partial.push_str("{\"repository\":\"own");
+        tokio::fs::write(dir.join("d.ndjson.partial"), partial).await.unwrap();
+        let loaded = ResultsPersistence::load(dir.join("d.ndjson.partial")).await.unwrap();
+        assert_same(&loaded, &derives);
+    }
+
+    #[tokio::test]
+    async fn test_schema_version_from_file_contents() {
+        let scratch = scratch_dir();
+        let dir = scratch.path();
+
+        // Current output keeps user order-sensitive entries, manifest or not
+        let mut derives = sample();
+        derives[0].order_sensitive = vec!["serde::Serialize".to_string()];
+        ResultsPersistence::save_to_json(&derives, dir.join("d.json")).await.unwrap();
+        ResultsPersistence::save_to_csv(&derives, dir.join("d.csv")).await.unwrap();
+        for name in ["d.json", "d.csv"] {
+            assert_same(&ResultsPersistence::load(dir.join(name)).await.unwrap(), &derives);
+        }
+
+        // The first release had no order-sensitivity flags to keep
+        let original = r#"[{"repository":"o/r","file_path":"src/lib.rs","line_number":1,"derives":["Clone","ambassador::Delegate"],"full_line":""}]"#;
+        tokio::fs::write(dir.join("v1.json"), original).await.unwrap();
+        let loaded = ResultsPersistence::load(dir.join("v1.json")).await.unwrap();
+        assert_eq!(loaded[0].order_sensitive, vec!["ambassador::Delegate"]);
+        assert_eq!(loaded[0].origins.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_parquet_schema_version_from_columns() {
+        let scratch = scratch_dir();
+        let dir = scratch.path();
+        let derives = vec!["Clone".to_string(), "ambassador::Delegate".to_string()];
+
+        // Parquet files written without the version in their metadata
+        for (name, order_sensitive) in [("v1.parquet", None), ("v2.parquet", Some(Vec::new()))] {
+            let string_list = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+            let mut fields = vec![
+                Field::new("repository", DataType::Utf8, false),
+                Field::new("file_path", DataType::Utf8, false),
+                Field::new("line_number", DataType::UInt64, false),
+                Field::new("derives", string_list.clone(), false),
+                Field::new("full_line", DataType::Utf8, false),
+            ];
+            let mut columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from(vec!["o/r"])),
+                Arc::new(StringArray::from(vec!["src/lib.rs"])),
+                Arc::new(UInt64Array::from(vec![1])),
+                Arc::new(ResultsPersistence::string_list_array([&derives].into_iter())),
+                Arc::new(StringArray::from(vec![""])),
+            ];
+            if let Some(order_sensitive) = &order_sensitive {
+                fields.push(Field::new("order_sensitive", string_list, false));
+                columns.push(Arc::new(ResultsPersistence::string_list_array([order_sensitive].into_iter())));
+            }
+            let schema = Arc::new(Schema::new(fields));
+            let batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+            let mut writer = ArrowWriter::try_new(std::fs::File::create(dir.join(name)).unwrap(), schema, None).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+        }
+
+        // Only files without the column have their flags filled in; an empty list was written as such
+        let loaded = ResultsPersistence::load(dir.join("v1.parquet")).await.unwrap();
+        assert_eq!(loaded[0].order_sensitive, vec!["ambassador::Delegate"]);
+        let loaded = ResultsPersistence::load(dir.join("v2.parquet")).await.unwrap();
+        assert!(loaded[0].order_sensitive.is_empty());
+    }
+}
@@ -2,14 +2,14 @@ use leabharlann_processing::Processor;
 use std::path::PathBuf;
 use tracing::{info, warn};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 use crate::{RepositoryInfo, DeriveStatement, parser::RustParser, repo_cache::RepositoryCache};
+use crate::file_filter::FileFilter;
 
 #[derive(Clone)]
 pub struct RustFileProcessor {
     parser: RustParser,
-    cache: Arc<Mutex<RepositoryCache>>,
+    cache: Arc<RepositoryCache>,
 }
 
 impl RustFileProcessor {
@@ -17,19 +17,14 @@ impl RustFileProcessor {
     async fn process_repository(&self, repo: &RepositoryInfo) -> Result<Vec<DeriveStatement>, ProcessingError> {
         info!("Processing repository: {}", repo.full_name);
         
-        // Clone or get cached repository
-        let repo_path = {
-            let mut cache = self.cache.lock().await;
-            cache.ensure_repository(repo).await
-                .map_err(|e| ProcessingError::CacheError(e.to_string()))?
-        };
+        // Clone or get cached repository, kept from eviction until its files are read
+        let lease = self.cache.ensure_repository(repo)
+            .map_err(|e| ProcessingError::CacheError(e.to_string()))?;
+        let repo_path = lease.path().to_path_buf();
         
         // Find all Rust files in the repository
-        let rust_files = {
-            let cache = self.cache.lock().await;
-            cache.find_rust_files(&repo_path)
-                .map_err(|e| ProcessingError::CacheError(e.to_string()))?
-        };
+        let rust_files = FileFilter::default().rust_files(&repo_path)
+            .map_err(|e| ProcessingError::CacheError(e.to_string()))?;
         
         info!("Found {} Rust files in {}", rust_files.len(), repo.full_name);
         
@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+
+use crate::packages::{Dependency, Package, PackageIndex};
+
+/// Origin recorded for the derives built into the compiler
+pub const STD_CRATE: &str = "std";
+
+/// Derives of the standard library, resolved without looking at dependencies
+const STD_DERIVES: &[&str] = &["Debug", "Clone", "Copy", "PartialEq", "Eq", "PartialOrd", "Ord", "Hash", "Default"];
+
+/// Crate paths that name the standard library
+const STD_PATHS: &[&str] = &["std", "core", "alloc"];
+
+/// When a crate exports a derive macro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Export {
+    Always,
+    /// Behind a feature enabled by default
+    DefaultFeature(&'static str),
+    /// Behind a feature that must be enabled
+    Feature(&'static str),
+}
+
+/// Derive macros of widely used crates, by the name they are invoked under.
+/// Names exported by several crates are resolved through the package's
+/// dependencies.
+const KNOWN_DERIVES: &[(&str, &str, Export)] = &[
+    ("Serialize", "serde", Export::Feature("derive")),
+    ("Deserialize", "serde", Export::Feature("derive")),
+    ("Error", "thiserror", Export::Always),
+    ("Snafu", "snafu", Export::Always),
+    ("Diagnostic", "miette", Export::Feature("derive")),
+    ("Parser", "clap", Export::Feature("derive")),
+    ("Subcommand", "clap", Export::Feature("derive")),
+    ("Args", "clap", Export::Feature("derive")),
+    ("ValueEnum", "clap", Export::Feature("derive")),
+    ("StructOpt", "structopt", Export::Always),
+    ("Display", "strum", Export::Feature("derive")),
+    ("Display", "derive_more", Export::Feature("display")),
+    ("Display", "parse_display", Export::Always),
+    ("EnumString", "strum", Export::Feature("derive")),
+    ("EnumIter", "strum", Export::Feature("derive")),
+    ("AsRefStr", "strum", Export::Feature("derive")),
+    ("IntoStaticStr", "strum", Export::Feature("derive")),
+    ("EnumCount", "strum", Export::Feature("derive")),
+    ("From", "derive_more", Export::Feature("from")),
+    ("Into", "derive_more", Export::Feature("into")),
+    ("Deref", "derive_more", Export::Feature("deref")),
+    ("Constructor", "derive_more", Export::Feature("constructor")),
+    ("Builder", "derive_builder", Export::Always),
+    ("TypedBuilder", "typed_builder", Export::Always),
+    ("Getters", "getset", Export::Always),
+    ("Setters", "getset", Export::Always),
+    ("Message", "prost", Export::DefaultFeature("derive")),
+    ("Enumeration", "prost", Export::DefaultFeature("derive")),
+    ("Oneof", "prost", Export::DefaultFeature("derive")),
+    ("Pod", "bytemuck", Export::Feature("derive")),
+    ("Zeroable", "bytemuck", Export::Feature("derive")),
+    ("Zeroize", "zeroize", Export::Feature("derive")),
+    ("ZeroizeOnDrop", "zeroize", Export::Feature("derive")),
+    ("FromBytes", "zerocopy", Export::Feature("derive")),
+    ("IntoBytes", "zerocopy", Export::Feature("derive")),
+    ("FromRow", "sqlx", Export::DefaultFeature("macros")),
+    ("Type", "sqlx", Export::DefaultFeature("macros")),
+    ("Queryable", "diesel", Export::Always),
+    ("Insertable", "diesel", Export::Always),
+    ("Component", "bevy", Export::Always),
+    ("Resource", "bevy", Export::Always),
+    ("Encode", "parity_scale_codec", Export::Feature("derive")),
+    ("Decode", "parity_scale_codec", Export::Feature("derive")),
+    ("JsonSchema", "schemars", Export::DefaultFeature("derive")),
+    ("ToSchema", "utoipa", Export::Always),
+    ("Arbitrary", "arbitrary", Export::Feature("derive")),
+    ("Validate", "validator", Export::Feature("derive")),
+    ("Delegate", "ambassador", Export::Always),
+    ("Educe", "educe", Export::Always),
+    ("Derivative", "derivative", Export::Always),
+];
+
+/// Proc-macro crates behind the crates of [`KNOWN_DERIVES`], which export
+/// the same derives unconditionally when depended on directly.
+const MACRO_CRATES: &[(&str, &str)] = &[
+    ("serde_derive", "serde"),
+    ("strum_macros", "strum"),
+    ("clap_derive", "clap"),
+    ("thiserror_impl", "thiserror"),
+    ("derive_more_impl", "derive_more"),
+    ("prost_derive", "prost"),
+    ("bytemuck_derive", "bytemuck"),
+    ("zeroize_derive", "zeroize"),
+    ("zerocopy_derive", "zerocopy"),
+    ("schemars_derive", "schemars"),
+];
+
+/// The crate a derive in a statement comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeriveOrigin {
+    /// The derive as written in the statement
+    pub derive: String,
+    /// Crate name as published, `std` for built-in derives
+    pub crate_name: String,
+    /// Version requirement of the dependency, from the owning package's manifest
+    #[serde(default)]
+    pub version: Option<String>,
+    /// The crate is a proc-macro package of the same repository
+    #[serde(default)]
+    pub local: bool,
+}
+
+impl std::fmt::Display for DeriveOrigin {
+    /// `derive=crate` or `derive=crate@version`, as written to CSV, with
+    /// `+local` after the crate of a workspace-local one
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.derive, self.crate_name)?;
+        if self.local {
+            write!(f, "+local")?;
+        }
+        if let Some(version) = &self.version {
+            write!(f, "@{}", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for DeriveOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (derive, origin) = s.split_once('=').ok_or_else(|| format!("Invalid derive origin: {}", s))?;
+        let (crate_name, version) = match origin.split_once('@') {
+            Some((crate_name, version)) => (crate_name, Some(version.to_string())),
+            None => (origin, None),
+        };
+        let (crate_name, local) = match crate_name.strip_suffix("+local") {
+            Some(crate_name) => (crate_name, true),
+            None => (crate_name, false),
+        };
+        Ok(DeriveOrigin { derive: derive.to_string(), crate_name: crate_name.to_string(), version, local })
+    }
+}
+
+/// Resolves the origins of a statement's derives, leaving out those that
+/// can't be told apart with what the owning package declares.
+pub fn resolve_all(derives: &[String], package: Option<&Package>, index: Option<&PackageIndex>) -> Vec<DeriveOrigin> {
+    derives.iter().filter_map(|derive| resolve(derive, package, index)).collect()
+}
+
+/// Whether `crate_name` is one of the crates of [`KNOWN_DERIVES`] or the
+/// proc-macro crate behind one.
+pub fn is_known_crate(crate_name: &str) -> bool {
+    let name = crate_name.replace('-', "_");
+    KNOWN_DERIVES.iter().any(|(_, known, _)| *known == name) || MACRO_CRATES.iter().any(|(macro_crate, _)| *macro_crate == name)
+}
+
+/// Whether a bare derive name is exported by one of the crates of [`KNOWN_DERIVES`].
+pub fn is_known_derive(name: &str) -> bool {
+    KNOWN_DERIVES.iter().any(|(known, _, _)| *known == name)
+}
+
+/// Resolves which crate `derive` comes from.
+///
+/// A path names its crate in the first segment, which may be a renamed
+/// dependency. A bare name is a standard derive, or one of the
+/// [`KNOWN_DERIVES`] of the package's dependencies; when several
+/// dependencies export it, those with the exporting feature enabled win,
+/// and if that still leaves more than one crate the derive is unresolved.
+/// Proc-macro crates such as `serde_derive` count as the crate they serve.
+/// With the repository's `index`, dependencies on its own proc-macro
+/// packages resolve as local, bare names through the derives those define.
+/// Without a package, only standard derives and paths resolve.
+pub fn resolve(derive: &str, package: Option<&Package>, index: Option<&PackageIndex>) -> Option<DeriveOrigin> {
+    let path = derive.trim_start_matches("::");
+    let origin = |crate_name: &str, version: Option<&String>| DeriveOrigin {
+        derive: derive.to_string(),
+        crate_name: crate_name.to_string(),
+        version: version.cloned(),
+        local: false,
+    };
+    let local_origin = |dependency: &Dependency| DeriveOrigin {
+        local: true,
+        ..origin(&dependency.package, dependency.version.as_ref())
+    };
+    let local_proc_macro = |dependency: &Dependency| index.and_then(|index| index.local_proc_macro(dependency));
+
+    if let Some((first, _)) = path.split_once("::") {
+        if STD_PATHS.contains(&first) {
+            return Some(origin(STD_CRATE, None));
+        }
+        // Derives re-exported by the crate itself name nothing a manifest can resolve
+        if ["crate", "self", "super"].contains(&first) {
+            return None;
+        }
+        let dependency = package.and_then(|package| package.dependencies.get(first));
+        if let Some(dependency) = dependency.filter(|dependency| local_proc_macro(dependency).is_some()) {
+            return Some(local_origin(dependency));
+        }
+        let (crate_name, version) = match dependency {
+            Some(dependency) => (dependency.package.as_str(), dependency.version.as_ref()),
+            None => (first, None),
+        };
+        let facade = MACRO_CRATES.iter().find(|(macro_crate, _)| *macro_crate == crate_name.replace('-', "_"));
+        return Some(origin(facade.map(|(_, facade)| *facade).unwrap_or(crate_name), version));
+    }
+
+    if STD_DERIVES.contains(&path) {
+        return Some(origin(STD_CRATE, None));
+    }
+
+    // The repository's own proc-macro crates are the likeliest to define an unfamiliar name
+    let package = package?;
+    let local = package.dependencies.values().find(|dependency| {
+        local_proc_macro(dependency).is_some_and(|local| local.exported_derives.iter().any(|name| name == path))
+    });
+    if let Some(dependency) = local {
+        return Some(local_origin(dependency));
+    }
+
+    // Each dependency that exports the derive, under the crate users know it by
+    let mut candidates: Vec<(&str, &Dependency, bool)> = Vec::new();
+    for dependency in package.dependencies.values() {
+        let name = dependency.package.replace('-', "_");
+        let macro_crate = MACRO_CRATES.iter().find(|(macro_crate, _)| *macro_crate == name);
+        let facade = macro_crate.map(|(_, facade)| *facade).unwrap_or(&name);
+        for (_, crate_name, export) in KNOWN_DERIVES.iter().filter(|(name, crate_name, _)| *name == path && crate_name == &facade) {
+            let enabled = macro_crate.is_some() || match export {
+                Export::Always => true,
+                Export::DefaultFeature(feature) => dependency.default_features || dependency.features.iter().any(|f| f == feature),
+                Export::Feature(feature) => dependency.features.iter().any(|f| f == feature),
+            };
+            candidates.push((crate_name, dependency, enabled));
+        }
+    }
+
+    // Features may also be enabled by other packages of the build, so a lone candidate stands
+    let enabled: Vec<_> = candidates.iter().filter(|(_, _, enabled)| *enabled).collect();
+    let chosen = if enabled.is_empty() { candidates.iter().collect() } else { enabled };
+    let (crate_name, dependency, _) = chosen.first()?;
+    if chosen.iter().any(|(other, _, _)| other != crate_name) {
+        return None;
+    }
+    let crate_name = if dependency.package.replace('-', "_") == *crate_name { &dependency.package } else { *crate_name };
+    Some(origin(crate_name, dependency.version.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages::PackageIndex;
+
+    #[test]
+    fn test_resolve() {
+        let manifest = concat!(
+            "[package]\nname = \"app\"\n\n",
+            "[dependencies]\nserde = { version = \"1.0\", features = [\"derive\"] }\nserde_derive = \"1.0\"\n",
+            "clap = { version = \"4.5\", features = [\"derive\"] }\nstrum = \"0.26\"\n",
+            "more = { package = \"derive_more\", version = \"1\", features = [\"display\"] }\n",
+        );
+        let index = PackageIndex::from_manifests([("Cargo.toml", manifest)]);
+        let package = index.package_of("src/main.rs");
+        let resolved = |derive: &str| resolve(derive, package, Some(&index)).map(|origin| origin.to_string());
+
+        assert_eq!(resolved("Debug").as_deref(), Some("Debug=std"));
+        assert_eq!(resolved("core::fmt::Debug").as_deref(), Some("core::fmt::Debug=std"));
+        assert_eq!(resolved("Parser").as_deref(), Some("Parser=clap@4.5"));
+        // The proc-macro crate behind serde counts as serde
+        assert_eq!(resolved("Serialize").as_deref(), Some("Serialize=serde@1.0"));
+        // strum's derive feature is off, so `Display` is derive_more's, used under a new name
+        assert_eq!(resolved("Display").as_deref(), Some("Display=derive_more@1"));
+        assert_eq!(resolved("more::From").as_deref(), Some("more::From=derive_more@1"));
+        assert_eq!(resolved("EnumIter").as_deref(), Some("EnumIter=strum@0.26"));
+        assert_eq!(resolved("Unknown"), None);
+
+        // Without a manifest, only what the statement itself says resolves
+        assert_eq!(resolve("serde::Serialize", None, None).map(|origin| origin.to_string()).as_deref(), Some("serde::Serialize=serde"));
+        assert_eq!(resolve("Serialize", None, None), None);
+
+        let origin: DeriveOrigin = "serde::Serialize=serde@1.0".parse().unwrap();
+        assert_eq!((origin.derive.as_str(), origin.crate_name.as_str(), origin.version.as_deref()), ("serde::Serialize", "serde", Some("1.0")));
+    }
+}
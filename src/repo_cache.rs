@@ -1,17 +1,45 @@
 use std::path::{Path, PathBuf};
 use std::fs;
-use std::collections::HashMap;
-use tokio::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
 use tracing::{info, warn, error};
 use serde::{Serialize, Deserialize};
 
 use crate::RepositoryInfo;
+use crate::cache_index::{CacheEntry, CacheIndex};
+use crate::cloned_repo::Revision;
+use crate::git_backend::{self, BackendKind, FetchDepth, GitBackend, GitError};
+
+/// What a clone fetches and checks out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneStrategy {
+    /// Every file at the analysed commit
+    Full,
+    /// A partial clone checking out only Rust sources and manifests, falling back to full
+    #[default]
+    Sparse,
+    /// A bare clone whose Rust files are read from the object database, with no working tree
+    Bare,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheConfig {
     pub max_repositories: usize,
     pub cache_root: PathBuf,
     pub max_size_gb: f64,
+    /// Clone full history instead of the latest commit only, as blame needs
+    #[serde(default)]
+    pub full_history: bool,
+    #[serde(default)]
+    pub git_backend: BackendKind,
+    /// Free space to leave on the cache's filesystem; cloning pauses below it. 0 disables the check
+    #[serde(default)]
+    pub min_free_gb: f64,
+    #[serde(default)]
+    pub clone_strategy: CloneStrategy,
 }
 
 impl Default for CacheConfig {
@@ -20,49 +48,340 @@ impl Default for CacheConfig {
             max_repositories: 5,
             cache_root: PathBuf::from("./repo_cache"),
             max_size_gb: 2.0,
+            full_history: false,
+            git_backend: BackendKind::default(),
+            min_free_gb: 1.0,
+            clone_strategy: CloneStrategy::default(),
         }
     }
 }
 
-#[derive(Debug)]
+/// Space reserved for a clone whose size GitHub didn't report, if nothing is cached yet
+const DEFAULT_RESERVATION_BYTES: u64 = 100 * 1024 * 1024;
+
+/// How often to recheck free space while cloning is paused for a full disk
+const LOW_DISK_RECHECK: Duration = Duration::from_secs(30);
+
+/// Bookkeeping shared by all workers, guarded by the cache's mutex.
+#[derive(Default)]
+struct CacheState {
+    index: CacheIndex,
+    /// Number of leases held on each repository; leased repositories are never evicted
+    leases: HashMap<String, usize>,
+    /// Repositories being checked, cloned, fetched or removed, which no other worker touches meanwhile
+    in_flight: HashSet<String>,
+    /// Bytes reserved for each clone or fetch that has been given space; workers
+    /// still waiting for space have no reservation
+    granted: HashMap<String, u64>,
+}
+
+impl CacheState {
+    fn is_busy(&self, full_name: &str) -> bool {
+        self.leases.contains_key(full_name) || self.in_flight.contains(full_name)
+    }
+
+    /// Whether `full_name` is held by a worker waiting for space, which frees
+    /// nothing until it is given some.
+    fn is_waiting(&self, full_name: &str) -> bool {
+        self.in_flight.contains(full_name) && !self.granted.contains_key(full_name)
+    }
+
+    /// Repositories counted against the limit besides `full_name`, including
+    /// clones that have been given space.
+    fn repository_count_besides(&self, full_name: &str) -> usize {
+        let pending = self.granted.keys().filter(|name| self.index.get(name).is_none()).count();
+        let own = self.index.get(full_name).is_some() || self.granted.contains_key(full_name);
+        self.index.len() + pending - usize::from(own)
+    }
+
+    /// Bytes cached or reserved besides `full_name`'s. A cached copy being
+    /// cloned again counts by its reservation only, as the clone replaces it.
+    fn committed_bytes_besides(&self, full_name: &str) -> u64 {
+        let replaced: u64 = self.granted.keys()
+            .map(String::as_str)
+            .filter(|name| *name != full_name)
+            .chain([full_name])
+            .filter_map(|name| self.index.get(name))
+            .map(|entry| entry.size_bytes)
+            .sum();
+        let reserved: u64 = self.granted.iter().filter(|(name, _)| *name != full_name).map(|(_, bytes)| *bytes).sum();
+        self.index.total_size() - replaced + reserved
+    }
+}
+
+/// What a repository's cached directory needs before it can be analysed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    /// The cached copy is used as is
+    Reuse,
+    /// The cached copy is kept and its full history fetched
+    Unshallow,
+    /// The repository is cloned, or its pinned revision fetched, from scratch
+    Clone,
+}
+
+/// The repository cache shared by every worker of a run.
+///
+/// Count and size limits apply to the cache as a whole. A repository requested
+/// by several workers at once is cloned only once, and space for a clone is
+/// reserved before it starts so concurrent clones can't overshoot the size
+/// limit together.
 pub struct RepositoryCache {
     config: CacheConfig,
-    active_repos: HashMap<String, PathBuf>,
+    git: Box<dyn GitBackend>,
+    state: Mutex<CacheState>,
+    /// Signalled whenever a clone finishes or a lease is released
+    changed: Condvar,
+}
+
+/// A repository checked out of the cache, protected from eviction until dropped.
+pub struct RepositoryLease<'a> {
+    cache: &'a RepositoryCache,
+    full_name: String,
+    path: PathBuf,
+    entry: Option<CacheEntry>,
+}
+
+impl RepositoryLease<'_> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cache's record of the checkout, as of when the lease was taken
+    pub fn entry(&self) -> Option<&CacheEntry> {
+        self.entry.as_ref()
+    }
+}
+
+impl Drop for RepositoryLease<'_> {
+    fn drop(&mut self) {
+        self.cache.release(&self.full_name);
+    }
 }
 
 impl RepositoryCache {
     pub fn new(config: CacheConfig) -> Self {
+        let git = git_backend::backend(config.git_backend);
+        let index = CacheIndex::load(&config.cache_root);
         Self {
             config,
-            active_repos: HashMap::new(),
+            git,
+            state: Mutex::new(CacheState { index, ..CacheState::default() }),
+            changed: Condvar::new(),
         }
     }
 
-    pub async fn ensure_repository(&mut self, repo: &RepositoryInfo) -> Result<PathBuf, CacheError> {
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn save_index(&self, state: &CacheState) {
+        if let Err(e) = state.index.save(&self.config.cache_root) {
+            warn!("Failed to save cache index: {}", e);
+        }
+    }
+
+    /// Brings the persisted index in line with the cache directory: entries
+    /// whose directories are gone are dropped, and repositories cloned by
+    /// earlier runs without an index entry are adopted so they can be reused
+    /// and evicted like any other.
+    pub fn adopt_existing(&self) -> Result<(), CacheError> {
+        let directories = match fs::read_dir(&self.config.cache_root) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str().map(String::from))
+                .collect::<Vec<_>>(),
+            Err(_) => return Ok(()),
+        };
+
+        let managed: Vec<String> = {
+            let state = self.lock_state();
+            directories.iter().filter(|directory| state.index.manages(directory)).cloned().collect()
+        };
+        let mut found = Vec::new();
+        for directory in directories {
+            let path = self.config.cache_root.join(&directory);
+            if managed.contains(&directory) || !self.git.is_valid_repository(&path) {
+                continue;
+            }
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(|_| Utc::now());
+            found.push(CacheEntry {
+                // GitHub owners can't contain underscores, so the first one separates owner and name
+                full_name: directory.replacen('_', "/", 1),
+                size_bytes: measure_size(&path, DEFAULT_RESERVATION_BYTES),
+                commit_sha: self.git.head_revision(&path).ok().map(|revision| revision.commit_sha),
+                sparse: is_sparse_checkout(&path),
+                bytes_saved: None,
+                directory,
+                cloned_at: modified,
+                last_accessed: modified,
+            });
+        }
+
+        let mut state = self.lock_state();
+        let pruned = state.index.prune_missing(&self.config.cache_root);
+        let adopted = found.len();
+        for entry in found {
+            state.index.insert(entry);
+        }
+        state.index.save(&self.config.cache_root)
+            .map_err(|e| CacheError::IoError(format!("Failed to save cache index: {}", e)))?;
+
+        if pruned > 0 || adopted > 0 {
+            info!("Cache index: adopted {} existing repositories, dropped {} missing", adopted, pruned);
+        }
+        Ok(())
+    }
+
+    /// Returns a lease on a local copy of `repo`, cloning it if needed. Blocks
+    /// while another worker clones the same repository, or while the cache is
+    /// full of repositories that are in use.
+    pub fn ensure_repository(&self, repo: &RepositoryInfo) -> Result<RepositoryLease<'_>, CacheError> {
         let repo_key = repo.full_name.clone();
-        
-        // Check if already cached
-        if let Some(path) = self.active_repos.get(&repo_key) {
-            if path.exists() {
-                info!("Repository {} already cached at {:?}", repo_key, path);
-                return Ok(path.clone());
-            } else {
-                // Path no longer exists, remove from cache
-                self.active_repos.remove(&repo_key);
+        let directory = self.sanitize_repo_name(&repo_key);
+        let mut state = self.lock_state();
+
+        // Wait for another worker's clone of the same repository to finish
+        while state.in_flight.contains(&repo_key) {
+            state = self.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+
+        // Protect the repository from eviction while it is in use
+        *state.leases.entry(repo_key.clone()).or_insert(0) += 1;
+        let mut lease = RepositoryLease {
+            cache: self,
+            full_name: repo_key.clone(),
+            path: self.config.cache_root.join(&directory),
+            entry: None,
+        };
+
+        // Hold the repository while checking, without the lock, whether its cached copy can be reused
+        let indexed = state.index.get(&repo_key).is_some() && lease.path.is_dir();
+        state.in_flight.insert(repo_key.clone());
+        drop(state);
+        let refresh = self.refresh_needed(repo, &lease.path);
+
+        // Reserve space for anything cloned or fetched, replacing a cached copy included
+        let reservation = if refresh == Refresh::Reuse {
+            0
+        } else {
+            let state = self.lock_state();
+            let reservation = self.estimate_size(repo, &state.index);
+            let mut state = self.make_space_if_needed(state, &repo_key, reservation);
+            state.granted.insert(repo_key.clone(), reservation);
+            reservation
+        };
+
+        let outcome = self.clone_repository(repo, refresh);
+        let entry = match &outcome {
+            Ok(repo_path) if refresh != Refresh::Reuse || !indexed => {
+                let size_bytes = measure_size(repo_path, reservation);
+                let sparse = is_sparse_checkout(repo_path);
+                // Partial clones don't know the size of blobs they left out, so the baseline is GitHub's
+                // size: the packed repository with its whole history, larger than a shallow full checkout
+                let bytes_saved = repo.size_kb
+                    .filter(|_| sparse)
+                    .map(|size_kb| (size_kb * 1024).saturating_sub(size_bytes));
+                if let Some(bytes_saved) = bytes_saved {
+                    info!("Sparse checkout of {} saved about {:.1}MB against a full clone", repo_key, bytes_saved as f64 / (1024.0 * 1024.0));
+                }
+                Some(CacheEntry {
+                    full_name: repo_key.clone(),
+                    directory,
+                    size_bytes,
+                    commit_sha: self.git.head_revision(repo_path).ok().map(|revision| revision.commit_sha),
+                    cloned_at: Utc::now(),
+                    last_accessed: Utc::now(),
+                    sparse,
+                    bytes_saved,
+                })
             }
+            _ => None,
+        };
+
+        let mut state = self.lock_state();
+        state.in_flight.remove(&repo_key);
+        state.granted.remove(&repo_key);
+        match entry {
+            Some(entry) => state.index.insert(entry),
+            // A failed clone removes what it wrote, so the old copy is gone too
+            None if outcome.is_err() && refresh != Refresh::Reuse => {
+                state.index.remove(&repo_key);
+            }
+            None => state.index.touch(&repo_key),
         }
+        lease.entry = state.index.get(&repo_key).cloned();
+        self.save_index(&state);
+        drop(state);
+        self.changed.notify_all();
 
-        // Ensure we have space for a new repository
-        self.make_space_if_needed().await?;
+        outcome.map(|_| lease)
+    }
 
-        // Clone the repository
-        let repo_path = self.clone_repository(repo).await?;
-        self.active_repos.insert(repo_key, repo_path.clone());
+    fn release(&self, full_name: &str) {
+        let mut state = self.lock_state();
+        if let Some(leases) = state.leases.get_mut(full_name) {
+            *leases -= 1;
+            if *leases == 0 {
+                state.leases.remove(full_name);
+            }
+        }
+        state.index.touch(full_name);
+        self.save_index(&state);
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    /// Bytes to reserve for cloning `repo`: GitHub's reported size, which
+    /// includes history and so overestimates a shallow clone, or else the
+    /// average size of cached repositories.
+    fn estimate_size(&self, repo: &RepositoryInfo, index: &CacheIndex) -> u64 {
+        match repo.size_kb {
+            Some(size_kb) => size_kb * 1024,
+            None if index.len() > 0 => index.total_size() / index.len() as u64,
+            None => DEFAULT_RESERVATION_BYTES,
+        }
+    }
 
-        Ok(repo_path)
+    /// Checks what the cached copy of `repo` at `repo_dir` needs before it
+    /// can be analysed.
+    fn refresh_needed(&self, repo: &RepositoryInfo, repo_dir: &Path) -> Refresh {
+        if !(repo_dir.exists() && self.git.is_valid_repository(repo_dir)) {
+            return Refresh::Clone;
+        }
+
+        // At the pinned revision if there is one
+        match &repo.pinned_rev {
+            Some(rev) if !self.git.is_at_revision(repo_dir, rev) => {
+                info!("Cached copy of {} is not at pinned revision {}, fetching again", repo.full_name, rev);
+                Refresh::Clone
+            }
+            _ if self.config.clone_strategy == CloneStrategy::Full && is_sparse_checkout(repo_dir) => {
+                info!("Cached copy of {} is a sparse checkout, cloning in full", repo.full_name);
+                Refresh::Clone
+            }
+            _ if self.config.full_history && self.git.is_shallow(repo_dir) => {
+                info!("Cached copy of {} is shallow, fetching full history", repo.full_name);
+                Refresh::Unshallow
+            }
+            _ => {
+                info!("Repository {} already exists locally at {:?}, using existing copy", repo.full_name, repo_dir);
+                Refresh::Reuse
+            }
+        }
     }
 
-    async fn clone_repository(&self, repo: &RepositoryInfo) -> Result<PathBuf, CacheError> {
+    /// Brings the repository's directory up to date as `refresh` requires and
+    /// returns it. A failed clone leaves no directory behind.
+    fn clone_repository(&self, repo: &RepositoryInfo, refresh: Refresh) -> Result<PathBuf, CacheError> {
         let repo_dir = self.config.cache_root.join(self.sanitize_repo_name(&repo.full_name));
         
         // Create cache directory if it doesn't exist
@@ -70,203 +389,365 @@ impl RepositoryCache {
             return Err(CacheError::IoError(format!("Failed to create cache directory: {}", e)));
         }
 
-        // Check if repository already exists locally
-        if repo_dir.exists() && self.is_valid_git_repo(&repo_dir).await {
-            info!("Repository {} already exists locally at {:?}, using existing copy", repo.full_name, repo_dir);
-            return Ok(repo_dir);
+        match refresh {
+            Refresh::Reuse => return Ok(repo_dir),
+            Refresh::Unshallow => match self.git.unshallow(&repo_dir) {
+                Ok(_) => return Ok(repo_dir),
+                Err(e) => warn!("Failed to unshallow {}, cloning again: {}", repo.full_name, e),
+            },
+            Refresh::Clone => {}
         }
 
-        // Remove existing directory if it exists but is invalid
+        // Remove existing directory if it exists but is invalid or can't be reused
         if repo_dir.exists() {
-            info!("Removing invalid repository directory for fresh clone");
+            info!("Removing existing repository directory for fresh clone");
             if let Err(e) = fs::remove_dir_all(&repo_dir) {
                 warn!("Failed to remove existing repository directory: {}", e);
             }
         }
 
-        info!("Cloning repository {} to {:?}", repo.full_name, repo_dir);
-
-        // Clone with shallow depth to save space and time
-        let output = Command::new("git")
-            .args([
-                "clone",
-                "--depth", "1",
-                "--single-branch",
-                &repo.clone_url,
-                repo_dir.to_str().unwrap()
-            ])
-            .output()
-            .await
-            .map_err(|e| CacheError::GitError(format!("Failed to execute git clone: {}", e)))?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            error!("Git clone failed for {}: status={:?}, stderr={}, stdout={}", 
-                   repo.full_name, output.status.code(), stderr, stdout);
-            return Err(CacheError::GitError(format!("Git clone failed: {}", stderr)));
+        let sparse = self.config.clone_strategy == CloneStrategy::Sparse;
+        let depth = FetchDepth {
+            shallow: !self.config.full_history,
+            sparse,
+            bare: self.config.clone_strategy == CloneStrategy::Bare,
+        };
+        let result = match self.transfer(repo, &repo_dir, depth) {
+            Err(e) if sparse && e.is_retryable() => {
+                // The server may not support partial clones, or git may be too old for sparse checkouts
+                warn!("Sparse checkout of {} failed, falling back to a full clone: {}", repo.full_name, e);
+                if repo_dir.exists() {
+                    if let Err(e) = fs::remove_dir_all(&repo_dir) {
+                        warn!("Failed to remove partial repository directory: {}", e);
+                    }
+                }
+                self.transfer(repo, &repo_dir, FetchDepth { sparse: false, ..depth })
+            }
+            result => result,
+        };
+
+        if let Err(e) = result {
+            error!("Git clone failed for {}: {}", repo.full_name, e);
+            if repo_dir.exists() {
+                if let Err(e) = fs::remove_dir_all(&repo_dir) {
+                    warn!("Failed to remove partial repository directory: {}", e);
+                }
+            }
+            return Err(CacheError::Git(e));
         }
 
-        info!("Successfully cloned {} to {:?}", repo.full_name, repo_dir);
         Ok(repo_dir)
     }
 
-    async fn make_space_if_needed(&mut self) -> Result<(), CacheError> {
-        // Check repository count limit
-        while self.active_repos.len() >= self.config.max_repositories {
-            info!("Cache at repository limit ({}/{}), removing oldest repository", 
-                  self.active_repos.len(), self.config.max_repositories);
-            self.remove_oldest_repository().await?;
-        }
+    /// Clones `repo`, or fetches its pinned revision, into `repo_dir`.
+    fn transfer(&self, repo: &RepositoryInfo, repo_dir: &Path, depth: FetchDepth) -> Result<(), GitError> {
+        let progress = git_backend::log_progress(&repo.full_name);
 
-        // Check disk space limit
-        let mut cache_size = self.get_cache_size_gb().await?;
-        while cache_size > self.config.max_size_gb && !self.active_repos.is_empty() {
-            info!("Cache size {:.2}GB exceeds limit {:.2}GB, removing oldest repository", 
-                  cache_size, self.config.max_size_gb);
-            self.remove_oldest_repository().await?;
-            cache_size = self.get_cache_size_gb().await?;
+        if let Some(rev) = &repo.pinned_rev {
+            info!("Fetching repository {} at {} to {:?} using {}", repo.full_name, rev, repo_dir, self.git.name());
+            self.git.fetch_revision(&repo.clone_url, repo_dir, rev, depth, &progress)?;
+            info!("Successfully fetched {}@{} to {:?}", repo.full_name, rev, repo_dir);
+            return Ok(());
         }
 
+        // Clone with shallow depth to save space and time, unless history is needed
+        info!("Cloning repository {} to {:?} using {}", repo.full_name, repo_dir, self.git.name());
+        self.git.clone_repository(&repo.clone_url, repo_dir, repo.default_branch.as_deref(), depth, &progress)?;
+        info!("Successfully cloned {} to {:?}", repo.full_name, repo_dir);
         Ok(())
     }
 
-    async fn remove_oldest_repository(&mut self) -> Result<(), CacheError> {
-        // For simplicity, remove the first repository in the HashMap
-        // In a more sophisticated implementation, we'd track access times
-        if let Some((repo_name, repo_path)) = self.active_repos.iter().next() {
-            let repo_name = repo_name.clone();
-            let repo_path = repo_path.clone();
-            
-            info!("Removing repository {} from cache to make space", repo_name);
-            
-            if let Err(e) = fs::remove_dir_all(&repo_path) {
-                warn!("Failed to remove repository directory {:?}: {}", repo_path, e);
+    /// Evicts least recently used repositories until there is room for one
+    /// more of `reservation` bytes within the repository and size limits, and
+    /// on the filesystem. Repositories in use are never evicted; while only
+    /// those remain, this waits for them to be released. A filesystem filled
+    /// by something other than the cache pauses cloning until space is freed.
+    fn make_space_if_needed<'a>(&'a self, mut state: MutexGuard<'a, CacheState>, repo_key: &str, reservation: u64) -> MutexGuard<'a, CacheState> {
+        let max_repositories = self.config.max_repositories;
+        let max_bytes = gb_to_bytes(self.config.max_size_gb);
+        let min_free_bytes = gb_to_bytes(self.config.min_free_gb);
+
+        loop {
+            // Whether it is new or replacing a cached copy, the repository needs one slot
+            let over_count = state.repository_count_besides(repo_key) >= max_repositories;
+            let over_size = state.committed_bytes_besides(repo_key) + reservation > max_bytes;
+            // Clones in flight haven't written everything they reserved yet
+            let in_flight_bytes: u64 = state.granted.values().sum();
+            let free_bytes = if min_free_bytes > 0 { available_bytes(&self.config.cache_root) } else { None };
+            let low_on_disk = free_bytes.is_some_and(|free| free < min_free_bytes + in_flight_bytes + reservation);
+            if !over_count && !over_size && !low_on_disk {
+                return state;
             }
-            
-            self.active_repos.remove(&repo_name);
-        }
 
-        Ok(())
-    }
+            let candidate = {
+                let state = &*state;
+                state.index.eviction_candidate(|name| state.is_busy(name)).cloned()
+            };
+            let Some(entry) = candidate else {
+                // Only clones given space and leased repositories will ever free up space, never
+                // workers waiting like this one
+                let can_free = state.granted.keys().any(|name| name != repo_key)
+                    || state.leases.keys().any(|name| name != repo_key && state.index.get(name).is_some() && !state.is_waiting(name));
+                if can_free {
+                    state = self.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+                } else if low_on_disk {
+                    warn!("Only {:.2}GB free on the cache filesystem, below the {:.2}GB minimum; pausing clone of {}",
+                          bytes_to_gb(free_bytes.unwrap_or(0)), self.config.min_free_gb, repo_key);
+                    state = self.changed.wait_timeout(state, LOW_DISK_RECHECK)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0;
+                } else {
+                    warn!("Cache is over its limits with nothing left to evict, cloning {} anyway", repo_key);
+                    return state;
+                }
+                continue;
+            };
+
+            if low_on_disk && !over_count && !over_size {
+                info!("Only {:.2}GB free on the cache filesystem, removing least recently used repository {}",
+                      bytes_to_gb(free_bytes.unwrap_or(0)), entry.full_name);
+            } else if over_count {
+                info!("Cache at repository limit ({}/{}), removing least recently used repository {}",
+                      state.repository_count_besides(repo_key) + 1, max_repositories, entry.full_name);
+            } else {
+                info!("Cache size {:.2}GB exceeds limit {:.2}GB, removing least recently used repository {}",
+                      bytes_to_gb(state.committed_bytes_besides(repo_key) + reservation), bytes_to_gb(max_bytes), entry.full_name);
+            }
 
-    async fn get_cache_size_gb(&self) -> Result<f64, CacheError> {
-        if !self.config.cache_root.exists() {
-            return Ok(0.0);
-        }
+            // Remove the directory without holding the lock; workers wanting it wait as for a clone
+            state.index.remove(&entry.full_name);
+            state.in_flight.insert(entry.full_name.clone());
+            self.save_index(&state);
+            drop(state);
 
-        let output = Command::new("du")
-            .args(["-sb", self.config.cache_root.to_str().unwrap()])
-            .output()
-            .await
-            .map_err(|e| CacheError::IoError(format!("Failed to get cache size: {}", e)))?;
+            let repo_path = CacheIndex::path_of(&self.config.cache_root, &entry);
+            if let Err(e) = fs::remove_dir_all(&repo_path) {
+                warn!("Failed to remove repository directory {:?}: {}", repo_path, e);
+            }
 
-        if !output.status.success() {
-            return Ok(0.0);
+            state = self.lock_state();
+            state.in_flight.remove(&entry.full_name);
+            self.changed.notify_all();
         }
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let size_bytes: u64 = stdout
-            .split_whitespace()
-            .next()
-            .unwrap_or("0")
-            .parse()
-            .unwrap_or(0);
-
-        Ok(size_bytes as f64 / (1024.0 * 1024.0 * 1024.0))
     }
 
     fn sanitize_repo_name(&self, repo_name: &str) -> String {
         repo_name.replace('/', "_").replace('\\', "_")
     }
 
-    async fn is_valid_git_repo(&self, repo_path: &Path) -> bool {
-        // Check if .git directory exists
-        let git_dir = repo_path.join(".git");
-        if !git_dir.exists() {
-            return false;
-        }
-
-        // Check if we can run git status in the directory
-        match Command::new("git")
-            .args(["status", "--porcelain"])
-            .current_dir(repo_path)
-            .output()
-            .await
-        {
-            Ok(output) => output.status.success(),
-            Err(_) => false,
-        }
-    }
-
-    pub fn find_rust_files(&self, repo_path: &Path) -> Result<Vec<PathBuf>, CacheError> {
-        let mut rust_files = Vec::new();
-        self.find_rust_files_recursive(repo_path, &mut rust_files)?;
-        Ok(rust_files)
+    /// Returns the commit, committer date and branch checked out at `repo_path`.
+    pub fn head_revision(&self, repo_path: &Path) -> Result<Revision, CacheError> {
+        self.git.head_revision(repo_path).map_err(CacheError::Git)
     }
 
-    fn find_rust_files_recursive(&self, dir: &Path, rust_files: &mut Vec<PathBuf>) -> Result<(), CacheError> {
-        let entries = fs::read_dir(dir)
-            .map_err(|e| CacheError::IoError(format!("Failed to read directory {:?}: {}", dir, e)))?;
-
-        for entry in entries {
-            let entry = entry.map_err(|e| CacheError::IoError(format!("Failed to read directory entry: {}", e)))?;
-            let path = entry.path();
+    pub fn cleanup(&self) -> Result<(), CacheError> {
+        info!("Cleaning up repository cache");
+        
+        self.lock_state().index = CacheIndex::default();
 
-            if path.is_dir() {
-                // Skip common directories that don't contain source code
-                if let Some(dir_name) = path.file_name().and_then(|n| n.to_str()) {
-                    if matches!(dir_name, "target" | "node_modules" | ".git" | ".github" | "vendor" | "third_party" | "deps" | "build") {
-                        continue;
-                    }
-                }
-                
-                // Recursively search subdirectories
-                self.find_rust_files_recursive(&path, rust_files)?;
-            } else if path.extension().and_then(|ext| ext.to_str()) == Some("rs") {
-                rust_files.push(path);
+        // Remove the entire cache directory, index included
+        if self.config.cache_root.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.config.cache_root) {
+                warn!("Failed to remove cache root directory: {}", e);
             }
         }
 
         Ok(())
     }
+}
 
-    pub async fn cleanup(&mut self) -> Result<(), CacheError> {
-        info!("Cleaning up repository cache");
-        
-        for (repo_name, repo_path) in &self.active_repos {
-            info!("Removing cached repository: {}", repo_name);
-            if let Err(e) = fs::remove_dir_all(repo_path) {
-                warn!("Failed to remove repository directory {:?}: {}", repo_path, e);
-            }
-        }
-        
-        self.active_repos.clear();
+/// Whether the checkout at `repo_path` only has the files matching its sparse-checkout patterns.
+pub fn is_sparse_checkout(repo_path: &Path) -> bool {
+    repo_path.join(".git").join("info").join("sparse-checkout").exists()
+}
 
-        // Remove the entire cache directory
-        if self.config.cache_root.exists() {
-            if let Err(e) = fs::remove_dir_all(&self.config.cache_root) {
-                warn!("Failed to remove cache root directory: {}", e);
+/// Total size of the files under `path`, not following symlinks.
+pub fn directory_size_bytes(path: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                total += metadata.len();
             }
         }
+    }
+    Ok(total)
+}
 
-        Ok(())
+/// Measures a checkout for the index. A checkout that can't be measured is
+/// recorded at `fallback` rather than 0, so it still counts against the limit.
+fn measure_size(path: &Path, fallback: u64) -> u64 {
+    directory_size_bytes(path).unwrap_or_else(|e| {
+        warn!("Failed to measure {:?}, assuming {:.2}GB: {}", path, bytes_to_gb(fallback), e);
+        fallback
+    })
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+#[cfg(unix)]
+fn available_bytes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stats` is only read after statvfs succeeds
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return None;
     }
+    let stats = unsafe { stats.assume_init() };
+    // Field widths differ between platforms, so the conversions aren't useless everywhere
+    #[allow(clippy::useless_conversion)]
+    let available = u64::from(stats.f_bavail) * u64::from(stats.f_frsize);
+    Some(available)
+}
+
+#[cfg(not(unix))]
+fn available_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn gb_to_bytes(gb: f64) -> u64 {
+    (gb * 1024.0 * 1024.0 * 1024.0) as u64
+}
+
+fn bytes_to_gb(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
 }
 
 #[derive(Debug)]
 pub enum CacheError {
     IoError(String),
-    GitError(String),
+    Git(GitError),
 }
 
 impl std::fmt::Display for CacheError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CacheError::IoError(msg) => write!(f, "I/O error: {}", msg),
-            CacheError::GitError(msg) => write!(f, "Git error: {}", msg),
+            CacheError::Git(e) => write!(f, "Git error: {}", e),
         }
     }
 }
 
-impl std::error::Error for CacheError {}
\ No newline at end of file
+impl std::error::Error for CacheError {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, scratch_dir};
+
+    fn fixture_repository(root: &Path) -> String {
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("lib.rs"), "#[derive(Debug)]\nstruct A;\n").unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(&origin, &["add", "-A"]);
+        git(&origin, &["commit", "-q", "-m", "first"]);
+        format!("file://{}", origin.display())
+    }
+
+    fn repository(full_name: &str, clone_url: &str) -> RepositoryInfo {
+        RepositoryInfo {
+            name: full_name.rsplit('/').next().unwrap().to_string(),
+            full_name: full_name.to_string(),
+            clone_url: clone_url.to_string(),
+            language: Some("Rust".to_string()),
+            stars: 0,
+            default_branch: None,
+            pinned_rev: None,
+            size_kb: Some(1),
+            fork: false,
+        }
+    }
+
+    #[test]
+    fn test_shared_cache_limits_and_deduplication() {
+        let scratch = scratch_dir();
+        let root = scratch.path();
+        let url = fixture_repository(root);
+        let config = CacheConfig {
+            max_repositories: 2,
+            cache_root: root.join("cache"),
+            git_backend: BackendKind::Cli,
+            ..CacheConfig::default()
+        };
+        let cache = RepositoryCache::new(config.clone());
+
+        // Concurrent requests for one repository share a single clone
+        let paths: Vec<PathBuf> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| cache.ensure_repository(&repository("a/one", &url)).unwrap().path().to_path_buf()))
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        assert!(paths.iter().all(|path| path == &paths[0] && path.join("lib.rs").exists()));
+        assert_eq!(cache.lock_state().index.len(), 1);
+
+        // The least recently used repository that isn't leased is evicted
+        let two = cache.ensure_repository(&RepositoryInfo { size_kb: Some(10 * 1024), ..repository("b/two", &url) }).unwrap();
+        let three = cache.ensure_repository(&repository("c/three", &url)).unwrap();
+        assert!(!paths[0].exists());
+        assert!(two.path().exists() && three.path().exists());
+
+        // Savings are measured against GitHub's size, and never below nothing
+        let entry = two.entry().unwrap();
+        assert!(entry.sparse);
+        assert_eq!(entry.bytes_saved, Some(10 * 1024 * 1024 - entry.size_bytes));
+        assert_eq!(three.entry().unwrap().bytes_saved, Some(0));
+        drop((two, three));
+
+        // The index survives the cache, and unindexed clones are adopted
+        fs::rename(config.cache_root.join("c_three"), config.cache_root.join("d_four")).unwrap();
+        let reopened = RepositoryCache::new(config);
+        reopened.adopt_existing().unwrap();
+        let state = reopened.lock_state();
+        let two = state.index.get("b/two").unwrap();
+        assert!(two.commit_sha.is_some());
+        assert!(two.size_bytes > 0);
+        assert_eq!(two.size_bytes, directory_size_bytes(&reopened.config().cache_root.join("b_two")).unwrap());
+        assert!(state.index.get("c/three").is_none());
+        assert_eq!(state.index.get("d/four").unwrap().directory, "d_four");
+        drop(state);
+
+        // Failed clones leave nothing behind, even when they were replacing a cached copy
+        let missing = repository("e/missing", &format!("{}-missing", url));
+        let pinned = RepositoryInfo { pinned_rev: Some("v9".to_string()), ..repository("b/two", &url) };
+        for (repo, directory) in [(missing, "e_missing"), (pinned, "b_two")] {
+            assert!(reopened.ensure_repository(&repo).is_err());
+            assert!(!reopened.config().cache_root.join(directory).exists());
+            assert!(reopened.lock_state().index.get(&repo.full_name).is_none());
+        }
+    }
+
+    #[test]
+    fn test_more_workers_than_repository_slots() {
+        let scratch = scratch_dir();
+        let root = scratch.path();
+        let url = fixture_repository(root);
+        let cache = std::sync::Arc::new(RepositoryCache::new(CacheConfig {
+            max_repositories: 2,
+            cache_root: root.join("cache"),
+            git_backend: BackendKind::Cli,
+            ..CacheConfig::default()
+        }));
+
+        // Workers waiting for a slot must not wait on each other
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for worker in 0..8 {
+            let (cache, url, sender) = (cache.clone(), url.clone(), sender.clone());
+            std::thread::spawn(move || {
+                let lease = cache.ensure_repository(&repository(&format!("owner/repo{}", worker), &url));
+                sender.send(lease.map(|lease| lease.path().join("lib.rs").exists())).unwrap();
+            });
+        }
+        for _ in 0..8 {
+            let cloned = receiver.recv_timeout(Duration::from_secs(120)).expect("workers deadlocked");
+            assert!(cloned.unwrap());
+        }
+        assert!(cache.lock_state().index.len() <= 2);
+    }
+}
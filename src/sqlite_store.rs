@@ -0,0 +1,515 @@
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::info;
+
+use crate::cloned_repo::Revision;
+use crate::manifest::SCHEMA_VERSION;
+use crate::provenance::DeriveOrigin;
+use crate::DeriveStatement;
+
+/// Normalised schema: one row per run, repository, file, annotated item, derive
+/// attribute and individual derive entry. Files and repositories are shared
+/// across runs; items and everything below them belong to the run that
+/// extracted them, so re-analysing a repository never overwrites history.
+///
+/// This is the layout of the first versioned database; [`MIGRATIONS`] brings
+/// it, or a database from before versioning, up to [`SCHEMA_VERSION`].
+///
+/// Example: repositories that put `Debug` after `Serialize`
+///
+/// ```sql
+/// SELECT DISTINCT a.repository
+/// FROM latest_derive_entries a
+/// JOIN latest_derive_entries b ON b.attribute_id = a.attribute_id
+/// WHERE a.name = 'Debug' AND b.name LIKE '%Serialize' AND a.position > b.position;
+/// ```
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    tool_version TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS repositories (
+    id INTEGER PRIMARY KEY,
+    full_name TEXT NOT NULL UNIQUE,
+    last_run_id INTEGER REFERENCES runs(id)
+);
+
+CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY,
+    repository_id INTEGER NOT NULL REFERENCES repositories(id),
+    path TEXT NOT NULL,
+    UNIQUE (repository_id, path)
+);
+
+CREATE TABLE IF NOT EXISTS items (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    file_id INTEGER NOT NULL REFERENCES files(id),
+    kind TEXT,
+    name TEXT,
+    line_number INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS derive_attributes (
+    id INTEGER PRIMARY KEY,
+    item_id INTEGER NOT NULL REFERENCES items(id),
+    line_number INTEGER NOT NULL,
+    full_line TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS derive_entries (
+    attribute_id INTEGER NOT NULL REFERENCES derive_attributes(id),
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    order_sensitive INTEGER NOT NULL,
+    PRIMARY KEY (attribute_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS items_run_file ON items(run_id, file_id);
+CREATE INDEX IF NOT EXISTS derive_attributes_item ON derive_attributes(item_id);
+CREATE INDEX IF NOT EXISTS derive_entries_name ON derive_entries(name);
+
+-- Each repository's derives as of the most recent run that analysed it
+CREATE VIEW IF NOT EXISTS latest_derive_entries AS
+SELECT r.full_name AS repository,
+       f.path AS file_path,
+       i.kind AS item_kind,
+       i.name AS item_name,
+       a.id AS attribute_id,
+       a.line_number,
+       e.position,
+       e.name,
+       e.order_sensitive
+FROM repositories r
+JOIN files f ON f.repository_id = r.id
+JOIN items i ON i.file_id = f.id AND i.run_id = r.last_run_id
+JOIN derive_attributes a ON a.item_id = i.id
+JOIN derive_entries e ON e.attribute_id = a.id;
+"#;
+
+/// Steps from each schema version to the next, applied in order to databases
+/// whose `user_version` is older than the step.
+const MIGRATIONS: &[(u32, &str)] = &[
+    // The schema version each run was written with; earlier runs can't be
+    // told apart, so they count as the oldest
+    (2, "ALTER TABLE runs ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1;"),
+    // The commit each repository was analysed at in each run
+    (3, "CREATE TABLE run_repositories (
+             run_id INTEGER NOT NULL REFERENCES runs(id),
+             repository_id INTEGER NOT NULL REFERENCES repositories(id),
+             commit_sha TEXT,
+             commit_date TEXT,
+             branch TEXT,
+             PRIMARY KEY (run_id, repository_id)
+         );
+         DROP VIEW latest_derive_entries;
+         CREATE VIEW latest_derive_entries AS
+         SELECT r.full_name AS repository,
+                rr.commit_sha,
+                f.path AS file_path,
+                i.kind AS item_kind,
+                i.name AS item_name,
+                a.id AS attribute_id,
+                a.line_number,
+                e.position,
+                e.name,
+                e.order_sensitive
+         FROM repositories r
+         LEFT JOIN run_repositories rr ON rr.run_id = r.last_run_id AND rr.repository_id = r.id
+         JOIN files f ON f.repository_id = r.id
+         JOIN items i ON i.file_id = f.id AND i.run_id = r.last_run_id
+         JOIN derive_attributes a ON a.item_id = i.id
+         JOIN derive_entries e ON e.attribute_id = a.id;"),
+    // Who last changed each attribute's line, when blame enrichment was enabled
+    (4, "ALTER TABLE derive_attributes ADD COLUMN author_hash TEXT;
+         ALTER TABLE derive_attributes ADD COLUMN line_commit_date TEXT;"),
+    // What each file was in each run, starting with its content to find copies
+    (5, "CREATE TABLE run_files (
+             run_id INTEGER NOT NULL REFERENCES runs(id),
+             file_id INTEGER NOT NULL REFERENCES files(id),
+             content_hash TEXT,
+             PRIMARY KEY (run_id, file_id)
+         );"),
+    // src, test, bench, example, build_script or generated
+    (6, "ALTER TABLE run_files ADD COLUMN kind TEXT;"),
+    (7, "ALTER TABLE run_files ADD COLUMN generated_by TEXT;"),
+    // The Cargo package owning the file
+    (8, "ALTER TABLE run_files ADD COLUMN package TEXT;
+         ALTER TABLE run_files ADD COLUMN edition TEXT;
+         ALTER TABLE run_files ADD COLUMN rust_version TEXT;
+         ALTER TABLE run_files ADD COLUMN proc_macro INTEGER;"),
+    // The crate each derive entry comes from, where it could be resolved
+    (9, "ALTER TABLE derive_entries ADD COLUMN origin_crate TEXT;
+         ALTER TABLE derive_entries ADD COLUMN origin_version TEXT;"),
+    // Origins that are proc-macro packages of the same repository
+    (10, "ALTER TABLE derive_entries ADD COLUMN origin_local INTEGER NOT NULL DEFAULT 0;"),
+];
+
+/// Creates the schema in a new database, or brings an existing one up to
+/// date, in one transaction so a failed step leaves the file as it was.
+fn migrate_schema(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    for (step, sql) in MIGRATIONS {
+        if version < *step {
+            tx.execute_batch(sql)?;
+        }
+    }
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()
+}
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate_schema(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    pub fn begin_run(&self) -> Result<i64, rusqlite::Error> {
+        self.conn.execute(
+            "INSERT INTO runs (started_at, tool_version, schema_version) VALUES (?1, ?2, ?3)",
+            params![chrono::Utc::now().to_rfc3339(), env!("CARGO_PKG_VERSION"), SCHEMA_VERSION],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Records one repository's statements under `run_id` in a single
+    /// transaction, at `revision` or else the one its statements give.
+    pub fn insert_repository(
+        &mut self,
+        run_id: i64,
+        repository: &str,
+        revision: Option<&Revision>,
+        derives: &[&DeriveStatement],
+    ) -> Result<(), rusqlite::Error> {
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO repositories (full_name, last_run_id) VALUES (?1, ?2)
+             ON CONFLICT(full_name) DO UPDATE SET last_run_id = excluded.last_run_id",
+            params![repository, run_id],
+        )?;
+        let repository_id: i64 = tx.query_row(
+            "SELECT id FROM repositories WHERE full_name = ?1",
+            params![repository],
+            |row| row.get(0),
+        )?;
+
+        // Statements from one repository in one run share a checkout
+        let first = derives.first();
+        let (commit_sha, commit_date, branch) = match revision {
+            Some(revision) => (Some(revision.commit_sha.as_str()), revision.commit_date.as_deref(), revision.branch.as_deref()),
+            None => (
+                first.and_then(|d| d.commit_sha.as_deref()),
+                first.and_then(|d| d.commit_date.as_deref()),
+                first.and_then(|d| d.branch.as_deref()),
+            ),
+        };
+        tx.execute(
+            "INSERT OR REPLACE INTO run_repositories (run_id, repository_id, commit_sha, commit_date, branch)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run_id, repository_id, commit_sha, commit_date, branch],
+        )?;
+
+        {
+            let mut insert_file = tx.prepare_cached(
+                "INSERT INTO files (repository_id, path) VALUES (?1, ?2)
+                 ON CONFLICT(repository_id, path) DO NOTHING",
+            )?;
+            let mut select_file = tx.prepare_cached("SELECT id FROM files WHERE repository_id = ?1 AND path = ?2")?;
+            let mut insert_run_file = tx.prepare_cached(
+                "INSERT OR REPLACE INTO run_files
+                     (run_id, file_id, content_hash, kind, generated_by, package, edition, rust_version, proc_macro)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            let mut insert_item = tx.prepare_cached(
+                "INSERT INTO items (run_id, file_id, kind, name, line_number) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut insert_attribute = tx.prepare_cached(
+                "INSERT INTO derive_attributes (item_id, line_number, full_line, author_hash, line_commit_date)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut insert_entry = tx.prepare_cached(
+                "INSERT INTO derive_entries
+                     (attribute_id, position, name, order_sensitive, origin_crate, origin_version, origin_local)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+
+            let mut file_ids: BTreeMap<&str, i64> = BTreeMap::new();
+            let mut item_ids: BTreeMap<(i64, usize), i64> = BTreeMap::new();
+
+            for derive in derives {
+                let file_id = match file_ids.get(derive.file_path.as_str()) {
+                    Some(id) => *id,
+                    None => {
+                        insert_file.execute(params![repository_id, derive.file_path])?;
+                        let id = select_file.query_row(params![repository_id, derive.file_path], |row| row.get(0))?;
+                        file_ids.insert(&derive.file_path, id);
+                        insert_run_file.execute(params![
+                            run_id,
+                            id,
+                            derive.content_hash,
+                            derive.file_kind.map(|kind| kind.as_str()),
+                            derive.generated_by,
+                            derive.package,
+                            derive.edition,
+                            derive.rust_version,
+                            derive.proc_macro,
+                        ])?;
+                        id
+                    }
+                };
+
+                // Attributes on the same item share a row; without a known item each gets its own
+                let existing_item = derive.item_line.and_then(|line| item_ids.get(&(file_id, line)).copied());
+                let item_id = match existing_item {
+                    Some(id) => id,
+                    None => {
+                        insert_item.execute(params![
+                            run_id,
+                            file_id,
+                            derive.item_kind,
+                            derive.item_name,
+                            derive.item_line.map(|l| l as i64),
+                        ])?;
+                        let id = tx.last_insert_rowid();
+                        if let Some(line) = derive.item_line {
+                            item_ids.insert((file_id, line), id);
+                        }
+                        id
+                    }
+                };
+
+                insert_attribute.execute(params![
+                    item_id,
+                    derive.line_number as i64,
+                    derive.full_line,
+                    derive.author_hash,
+                    derive.line_commit_date,
+                ])?;
+                let attribute_id = tx.last_insert_rowid();
+
+                for (position, name) in derive.derives.iter().enumerate() {
+                    let order_sensitive = derive.order_sensitive.contains(name);
+                    let origin = derive.origins.iter().find(|origin| &origin.derive == name);
+                    insert_entry.execute(params![
+                        attribute_id,
+                        position as i64,
+                        name,
+                        order_sensitive,
+                        origin.map(|origin| &origin.crate_name),
+                        origin.and_then(|origin| origin.version.as_deref()),
+                        origin.is_some_and(|origin| origin.local),
+                    ])?;
+                }
+            }
+        }
+
+        tx.commit()
+    }
+}
+
+/// Writes all statements as a new run, grouped by repository. Every
+/// repository in `analysed` is part of the run, so one that no longer has any
+/// statements stops reporting those of an earlier run as its latest.
+pub fn save_run<P: AsRef<Path>>(
+    derives: &[DeriveStatement],
+    analysed: &[(&str, Option<&Revision>)],
+    path: P,
+) -> Result<i64, rusqlite::Error> {
+    let mut store = SqliteStore::open(path.as_ref())?;
+    let run_id = store.begin_run()?;
+
+    let mut by_repository: BTreeMap<&str, (Option<&Revision>, Vec<&DeriveStatement>)> = analysed
+        .iter()
+        .map(|&(repository, revision)| (repository, (revision, Vec::new())))
+        .collect();
+    for derive in derives {
+        by_repository.entry(&derive.repository).or_default().1.push(derive);
+    }
+
+    for (repository, (revision, statements)) in &by_repository {
+        store.insert_repository(run_id, repository, *revision, statements)?;
+    }
+
+    info!("Saved {} derive statements from {} repositories to {} (run {})",
+          derives.len(), by_repository.len(), path.as_ref().display(), run_id);
+    Ok(run_id)
+}
+
+/// Oldest schema version among the runs [`load_latest`] reads, upgrading the
+/// database's layout first. Runs from before per-run versions report 1.
+pub fn schema_version<P: AsRef<Path>>(path: P) -> Result<u32, rusqlite::Error> {
+    let store = SqliteStore::open(path)?;
+    let version: Option<u32> = store.conn.query_row(
+        "SELECT MIN(ru.schema_version) FROM repositories r JOIN runs ru ON ru.id = r.last_run_id",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(version.unwrap_or(SCHEMA_VERSION))
+}
+
+/// Reads back each repository's statements from the most recent run that analysed it.
+pub fn load_latest<P: AsRef<Path>>(path: P) -> Result<Vec<DeriveStatement>, rusqlite::Error> {
+    let store = SqliteStore::open(path)?;
+    let mut stmt = store.conn.prepare(
+        "SELECT r.full_name, f.path, a.id, a.line_number, a.full_line, i.kind, i.name, i.line_number,
+                e.name, e.order_sensitive, rr.commit_sha, rr.commit_date, rr.branch,
+                a.author_hash, a.line_commit_date, rf.content_hash, rf.kind, rf.generated_by,
+                rf.package, rf.edition, rf.rust_version, rf.proc_macro,
+                e.origin_crate, e.origin_version, e.origin_local
+         FROM repositories r
+         LEFT JOIN run_repositories rr ON rr.run_id = r.last_run_id AND rr.repository_id = r.id
+         JOIN files f ON f.repository_id = r.id
+         LEFT JOIN run_files rf ON rf.run_id = r.last_run_id AND rf.file_id = f.id
+         JOIN items i ON i.file_id = f.id AND i.run_id = r.last_run_id
+         JOIN derive_attributes a ON a.item_id = i.id
+         JOIN derive_entries e ON e.attribute_id = a.id
+         ORDER BY a.id, e.position",
+    )?;
+    let mut derives: Vec<DeriveStatement> = Vec::new();
+    let mut current_attribute = None;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let attribute_id: i64 = row.get(2)?;
+        if current_attribute != Some(attribute_id) {
+            current_attribute = Some(attribute_id);
+            derives.push(DeriveStatement {
+                repository: row.get(0)?,
+                file_path: row.get(1)?,
+                line_number: row.get::<_, i64>(3)? as usize,
+                derives: Vec::new(),
+                full_line: row.get(4)?,
+                order_sensitive: Vec::new(),
+                item_kind: row.get(5)?,
+                item_name: row.get(6)?,
+                item_line: row.get::<_, Option<i64>>(7)?.map(|l| l as usize),
+                commit_sha: row.get(10)?,
+                commit_date: row.get(11)?,
+                branch: row.get(12)?,
+                author_hash: row.get(13)?,
+                line_commit_date: row.get(14)?,
+                content_hash: row.get(15)?,
+                file_kind: row.get::<_, Option<String>>(16)?.and_then(|kind| kind.parse().ok()),
+                generated_by: row.get(17)?,
+                package: row.get(18)?,
+                edition: row.get(19)?,
+                rust_version: row.get(20)?,
+                proc_macro: row.get(21)?,
+                origins: Vec::new(),
+            });
+        }
+
+        if let Some(derive) = derives.last_mut() {
+            let name: String = row.get(8)?;
+            if row.get(9)? {
+                derive.order_sensitive.push(name.clone());
+            }
+            if let Some(crate_name) = row.get::<_, Option<String>>(22)? {
+                derive.origins.push(DeriveOrigin {
+                    derive: name.clone(),
+                    crate_name,
+                    version: row.get(23)?,
+                    local: row.get(24)?,
+                });
+            }
+            derive.derives.push(name);
+        }
+    }
+
+    Ok(derives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn statement(repository: &str) -> DeriveStatement {
+        DeriveStatement {
+            repository: repository.to_string(),
+            file_path: "src/lib.rs".to_string(),
+            line_number: 1,
+            derives: vec!["Debug".to_string(), "Clone".to_string()],
+            full_line: "#[derive(Debug, Clone)]".to_string(),
+            ..DeriveStatement::default()
+        }
+    }
+
+    #[test]
+    fn test_upgrade_unversioned_database() {
+        let scratch = scratch_dir();
+        let path = scratch.path().join("derives.sqlite");
+
+        // A database written before versioning: the base layout, user_version 0
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(SCHEMA).unwrap();
+            conn.execute_batch(
+                "INSERT INTO runs (id, started_at, tool_version) VALUES (1, '2024-01-01T00:00:00+00:00', '0.1.0');
+                 INSERT INTO repositories (id, full_name, last_run_id) VALUES (1, 'owner/old', 1);
+                 INSERT INTO files (id, repository_id, path) VALUES (1, 1, 'src/lib.rs');
+                 INSERT INTO items (id, run_id, file_id) VALUES (1, 1, 1);
+                 INSERT INTO derive_attributes (id, item_id, line_number, full_line)
+                     VALUES (1, 1, 1, '#[derive(Debug, Clone)]');
+                 INSERT INTO derive_entries (attribute_id, position, name, order_sensitive)
+                     VALUES (1, 0, 'Debug', 0), (1, 1, 'Clone', 0);",
+            )
+            .unwrap();
+        }
+
+        // Appending a run upgrades the layout but not the old repository's run
+        save_run(&[statement("owner/new")], &[], &path).unwrap();
+        assert_eq!(schema_version(&path).unwrap(), 1);
+        let loaded = load_latest(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.iter().any(|d| d.repository == "owner/old" && d.derives == ["Debug", "Clone"]));
+
+        // Once every repository has a current run, the database is current
+        save_run(&[statement("owner/old")], &[], &path).unwrap();
+        assert_eq!(schema_version(&path).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_repository_without_statements_replaces_latest() {
+        let scratch = scratch_dir();
+        let path = scratch.path().join("derives.sqlite");
+        save_run(&[statement("owner/kept"), statement("owner/emptied")], &[], &path).unwrap();
+
+        // Analysed again, the repository's derives are all gone
+        let revision = Revision { commit_sha: "abc123".to_string(), commit_date: None, branch: Some("main".to_string()) };
+        save_run(&[statement("owner/kept")], &[("owner/kept", None), ("owner/emptied", Some(&revision))], &path).unwrap();
+
+        let loaded = load_latest(&path).unwrap();
+        assert_eq!(loaded.iter().map(|d| d.repository.as_str()).collect::<Vec<_>>(), vec!["owner/kept"]);
+        let store = SqliteStore::open(&path).unwrap();
+        let latest: i64 = store.conn
+            .query_row("SELECT COUNT(*) FROM latest_derive_entries WHERE repository = 'owner/emptied'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(latest, 0);
+        let commit: Option<String> = store.conn
+            .query_row(
+                "SELECT rr.commit_sha FROM run_repositories rr JOIN repositories r ON r.id = rr.repository_id
+                 WHERE r.full_name = 'owner/emptied' AND rr.run_id = r.last_run_id",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(commit.as_deref(), Some("abc123"));
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::provenance::{self, DeriveOrigin};
+use crate::DeriveStatement;
+
+/// Broad kind of a derive, by where its macro is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeriveFamily {
+    /// Built into the compiler, such as `Debug` or `Clone`
+    Std,
+    /// From a widely used crate, such as serde or thiserror
+    Ecosystem,
+    /// From a proc-macro package of the same repository
+    Local,
+    /// Anything that couldn't be placed
+    Other,
+}
+
+impl DeriveFamily {
+    pub const ALL: [DeriveFamily; 4] = [DeriveFamily::Std, DeriveFamily::Ecosystem, DeriveFamily::Local, DeriveFamily::Other];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeriveFamily::Std => "std",
+            DeriveFamily::Ecosystem => "ecosystem",
+            DeriveFamily::Local => "local",
+            DeriveFamily::Other => "other",
+        }
+    }
+}
+
+impl std::str::FromStr for DeriveFamily {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeriveFamily::ALL
+            .into_iter()
+            .find(|family| family.as_str() == s)
+            .ok_or_else(|| format!("Unknown derive family: {}", s))
+    }
+}
+
+/// Assigns derives to families from their resolved origins, with user
+/// mappings taking precedence.
+#[derive(Debug, Clone, Default)]
+pub struct Taxonomy {
+    /// `(pattern, family)` in the order given; the first match wins
+    mappings: Vec<(String, DeriveFamily)>,
+}
+
+impl Taxonomy {
+    /// The taxonomy without user mappings, judging from origins alone.
+    pub fn builtin() -> Self {
+        Self::default()
+    }
+
+    /// Adds mappings from `pattern = family` lines, where a pattern is a
+    /// derive path, a bare name, or `crate::*` for every derive of a crate.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn extend_from_str(&mut self, text: &str) -> Result<(), String> {
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, family) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `pattern = family`", idx + 1))?;
+            let family = family.trim().parse().map_err(|e| format!("line {}: {}", idx + 1, e))?;
+            self.mappings.push((pattern.trim().to_string(), family));
+        }
+        Ok(())
+    }
+
+    /// The family of `derive`, given its origin when it was resolved.
+    ///
+    /// A `crate::*` mapping also covers bare names resolved to that crate.
+    /// Otherwise standard derives are [`DeriveFamily::Std`], those of the
+    /// repository's own proc-macro packages [`DeriveFamily::Local`], and those
+    /// of crates provenance knows, or bare names only such crates export,
+    /// [`DeriveFamily::Ecosystem`].
+    pub fn classify(&self, derive: &str, origin: Option<&DeriveOrigin>) -> DeriveFamily {
+        let path = derive.trim_start_matches("::");
+        let mapped = self.mappings.iter().find(|(pattern, _)| match pattern.strip_suffix("::*") {
+            Some(krate) => {
+                path.strip_prefix(krate).is_some_and(|rest| rest.starts_with("::"))
+                    || origin.is_some_and(|origin| origin.crate_name.replace('-', "_") == krate.replace('-', "_"))
+            }
+            None if path.contains("::") => pattern == path,
+            None => derive_order::last_segment(pattern) == path,
+        });
+        if let Some((_, family)) = mapped {
+            return *family;
+        }
+
+        match origin {
+            Some(origin) if origin.crate_name == provenance::STD_CRATE => DeriveFamily::Std,
+            Some(origin) if origin.local => DeriveFamily::Local,
+            Some(origin) if provenance::is_known_crate(&origin.crate_name) => DeriveFamily::Ecosystem,
+            Some(_) => DeriveFamily::Other,
+            None if !path.contains("::") && provenance::is_known_derive(path) => DeriveFamily::Ecosystem,
+            None => DeriveFamily::Other,
+        }
+    }
+
+    /// The family of each of a statement's derives, in list order.
+    pub fn families(&self, statement: &DeriveStatement) -> Vec<DeriveFamily> {
+        statement
+            .derives
+            .iter()
+            .map(|derive| {
+                let origin = statement.origins.iter().find(|origin| &origin.derive == derive);
+                self.classify(derive, origin)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let mut taxonomy = Taxonomy::builtin();
+        let origin = |text: &str| text.parse::<DeriveOrigin>().unwrap();
+
+        assert_eq!(taxonomy.classify("Debug", Some(&origin("Debug=std"))), DeriveFamily::Std);
+        assert_eq!(taxonomy.classify("Serialize", Some(&origin("Serialize=serde@1"))), DeriveFamily::Ecosystem);
+        assert_eq!(taxonomy.classify("Register", Some(&origin("Register=app-macros+local"))), DeriveFamily::Local);
+        assert_eq!(taxonomy.classify("Widget", Some(&origin("Widget=gui_kit"))), DeriveFamily::Other);
+        // Unresolved names only well-known crates export are still theirs
+        assert_eq!(taxonomy.classify("Deserialize", None), DeriveFamily::Ecosystem);
+        assert_eq!(taxonomy.classify("Widget", None), DeriveFamily::Other);
+
+        taxonomy.extend_from_str("# in-house\ngui_kit::* = ecosystem\nRegister = other\n").unwrap();
+        assert_eq!(taxonomy.classify("Widget", Some(&origin("Widget=gui-kit"))), DeriveFamily::Ecosystem);
+        assert_eq!(taxonomy.classify("gui_kit::Widget", None), DeriveFamily::Ecosystem);
+        assert_eq!(taxonomy.classify("Register", Some(&origin("Register=app-macros+local"))), DeriveFamily::Other);
+
+        assert!(taxonomy.extend_from_str("Widget = popular\n").is_err());
+        assert!(taxonomy.extend_from_str("Widget\n").is_err());
+    }
+}
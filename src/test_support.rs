@@ -0,0 +1,35 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::Path;
+use std::process::Command;
+
+use tempfile::TempDir;
+
+/// Author and committer date of fixture commits, unless a test picks its own
+pub const FIXTURE_DATE: &str = "2021-06-01T12:00:00+02:00";
+
+/// A fresh directory that is removed when dropped, even if the test fails.
+pub fn scratch_dir() -> TempDir {
+    tempfile::Builder::new().prefix("rda-").tempdir().unwrap()
+}
+
+/// Runs git in `dir` as a fixed identity, committing at [`FIXTURE_DATE`].
+pub fn git(dir: &Path, args: &[&str]) {
+    git_at(dir, args, FIXTURE_DATE);
+}
+
+/// Runs git in `dir` as a fixed identity, committing at `date`.
+pub fn git_at(dir: &Path, args: &[&str], date: &str) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "Fixture")
+        .env("GIT_AUTHOR_EMAIL", "fixture@example.com")
+        .env("GIT_COMMITTER_NAME", "Fixture")
+        .env("GIT_COMMITTER_EMAIL", "fixture@example.com")
+        .env("GIT_AUTHOR_DATE", date)
+        .env("GIT_COMMITTER_DATE", date)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}